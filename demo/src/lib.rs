@@ -89,10 +89,15 @@ pub fn get_index(x: u32, y: u32, width: u32) -> usize {
 pub fn render(code: String, aspect_ratio: usize, size_index: usize) {
     set_size(aspect_ratio, size_index);
 
-    let expr = ssl::parse_source(code);
+    let on_retry = |retry, reason| console_log!("{reason}, retrying ({retry}) ...");
+    let options = ssl::ParseOptions {
+        on_retry: Some(&on_retry),
+        ..ssl::ParseOptions::default()
+    };
+    let expr = ssl::parse_source_with_options(code, &options);
 
     let (width, height) = canvas_size();
-    let image = ssl::render(&expr, width, height);
+    let image = ssl::render_with_version(&expr, width, height, options.version);
 
     for y in 0..height {
         for x in 0..width {
@@ -0,0 +1,24 @@
+//! Every program renders. Evaluation never panics, and every color channel is in [0, 1].
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssl::{fuzzing::eval, parse_source};
+
+/// Points that every program is evaluated at, including the corners and the origin.
+const POINTS: [f64; 5] = [-1.0, -0.37, 0.0, 0.5, 1.0];
+
+fuzz_target!(|data: &[u8]| {
+    let expr = parse_source(data);
+    for x in POINTS {
+        for y in POINTS {
+            let c = eval(&expr, x, y);
+            for channel in [c.r, c.g, c.b] {
+                assert!(
+                    (0.0..=1.0).contains(&channel),
+                    "{expr} at ({x}, {y}) is {c:?}"
+                );
+            }
+        }
+    }
+});
@@ -0,0 +1,16 @@
+//! Any bytes can be lexed. The tokens cover the source without gaps or overlaps.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssl::fuzzing::Lexer;
+
+fuzz_target!(|data: &[u8]| {
+    let mut end = 0;
+    for token in Lexer::new(data) {
+        assert_eq!(token.span.start, end, "Gap before {token:?}");
+        assert!(token.span.end > token.span.start, "Empty {token:?}");
+        end = token.span.end;
+    }
+    assert_eq!(end, data.len());
+});
@@ -0,0 +1,38 @@
+//! Any bytes are a valid program. Parsing never panics, and past the end of the input the
+//! forgiving parser only nests a little deeper than the input did, and never deeper than the
+//! depth limit.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssl::{
+    fuzzing::{parse_tokens, Lexer},
+    Limits, ParseOptions,
+};
+
+/// How much deeper than the number of tokens an expression may nest. The parser makes up a few
+/// levels after the input ends, and `Cornelia` is a deep expression made from few tokens.
+const EXTRA_DEPTH: usize = 64;
+
+fuzz_target!(|data: &[u8]| {
+    let lexer = Lexer::new(data);
+    let source = lexer.source();
+    let tokens: Vec<_> = lexer.collect();
+    let len = tokens.len();
+
+    // The largest seed makes the seed counter of the parser wrap around.
+    for (filter, seed) in [(false, 0), (true, 0), (false, u64::MAX)] {
+        let options = ParseOptions {
+            filter,
+            seed,
+            ..ParseOptions::default()
+        };
+        let expr = parse_tokens(tokens.clone(), source.clone(), &options);
+        let max_depth = (len + EXTRA_DEPTH).min(Limits::DEFAULT.max_depth);
+        assert!(
+            expr.depth() <= max_depth,
+            "{len} tokens nested {} deep",
+            expr.depth()
+        );
+    }
+});
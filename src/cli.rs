@@ -1,27 +1,71 @@
 //! Command line interface implementation
 
-use std::path::PathBuf;
+use std::{ffi::OsString, path::PathBuf};
 
-use clap::Parser;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use image::ImageFormat;
+
+use crate::compiler::{
+    formatter::MAX_WIDTH,
+    limits::Limits,
+    parser::{ConstantTest, ParseOptions, MAX_TRIES, SAMPLE_GRID},
+    texture::{Address, Filter},
+};
 
 /// Command line interface
 #[derive(Debug, Parser)]
 pub struct Cli {
-    /// Input file
-    #[arg(required = true)]
-    pub input: PathBuf,
+    #[command(subcommand)]
+    pub command: Command,
+}
 
-    /// Output file
-    #[arg(short, long, default_value = "output.png")]
-    pub output: String,
+impl Cli {
+    /// Parse the command line arguments. Without a command, the arguments are passed to `render`,
+    /// so `ssl <file>` keeps working.
+    pub fn parse_args() -> Self {
+        let mut args: Vec<OsString> = std::env::args_os().collect();
+        if let Some(first) = args.get(1) {
+            let is_command = Self::command()
+                .get_subcommands()
+                .any(|c| c.get_name() == first || c.get_all_aliases().any(|a| a == first));
+            let is_help = ["-h", "--help", "help"].iter().any(|h| h == first);
+            if !is_command && !is_help {
+                args.insert(1, "render".into());
+            }
+        }
+        Self::parse_from(args)
+    }
+}
+
+/// Commands of the command line interface
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Render a program to an image. This is the default command
+    Render(RenderArgs),
+    /// Print how a program is lexed and parsed
+    Inspect(InspectArgs),
+    /// Format source files
+    Fmt(FmtArgs),
+    /// Render the hash visualization of any file, such as a key or a fingerprint. Files that differ
+    /// by a single bit give unrelated images
+    Hash(HashArgs),
+    /// Render many programs at once
+    Batch(BatchArgs),
+    /// Render a program under many seeds into a contact sheet, with every thumbnail labeled with its
+    /// seed. Render a favorite with `--seed` to get it at full size
+    Gallery(GalleryArgs),
+}
 
+/// Size of the rendered images and how they are rendered
+#[derive(Debug, Args)]
+pub struct ImageArgs {
     /// Width of the output image
-    #[arg(short('W'), long, default_value = "1200")]
-    pub width: u32,
+    #[arg(short('W'), long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
 
     /// Height of the output image
-    #[arg(short('H'), long, default_value = "1200")]
-    pub height: u32,
+    #[arg(short('H'), long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
 
     /// Threads
     #[arg(short, long, default_value = "4")]
@@ -31,19 +75,382 @@ pub struct Cli {
     #[arg(short, long)]
     pub verbose: bool,
 
-    /// Do not render the image
+    /// Print statistics of every rendered program, like how many expressions it has and how often
+    /// its conditionals take each branch
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Render a heatmap instead of colors, where the brightness of a pixel shows how many
+    /// expressions are evaluated to render it. Tiles of a uniform color are not evaluated
+    #[arg(long, conflicts_with = "stats")]
+    pub profile_heatmap: bool,
+}
+
+impl ImageArgs {
+    /// Size of the image, using the given default for what was not set.
+    pub fn size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        (self.width.unwrap_or(width), self.height.unwrap_or(height))
+    }
+}
+
+/// Where and how the rendered image is written
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// Output file, or `-` to write to stdout
+    #[arg(short, long, default_value = "output.png")]
+    pub output: PathBuf,
+
+    /// Format of the output image. Inferred from the extension of the output file if not set,
+    /// and PNG when writing to stdout
+    #[arg(short, long, value_enum)]
+    pub format: Option<Format>,
+}
+
+/// Budgets that keep large inputs fast to render. Programs over a budget are truncated
+#[derive(Debug, Args)]
+pub struct LimitArgs {
+    /// Maximum number of tokens parsed. The rest of the input is ignored
+    #[arg(long, default_value_t = Limits::DEFAULT.max_tokens)]
+    pub max_tokens: usize,
+
+    /// Maximum number of expressions in a program
+    #[arg(long, default_value_t = Limits::DEFAULT.max_nodes)]
+    pub max_nodes: usize,
+
+    /// Maximum depth of nested expressions. Deeper programs can overflow the stack
+    #[arg(long, default_value_t = Limits::DEFAULT.max_depth)]
+    pub max_depth: usize,
+
+    /// Maximum estimated number of operations to render a pixel
+    #[arg(long, default_value_t = Limits::DEFAULT.max_ops)]
+    pub max_ops: usize,
+
+    /// Maximum number of nested derivatives. Deeper ones are read as their operand
+    #[arg(long, default_value_t = Limits::DEFAULT.max_derivative_depth)]
+    pub max_derivative_depth: usize,
+}
+
+impl LimitArgs {
+    /// The limits set by the arguments.
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_tokens: self.max_tokens,
+            max_nodes: self.max_nodes,
+            max_depth: self.max_depth,
+            max_ops: self.max_ops,
+            max_derivative_depth: self.max_derivative_depth,
+        }
+    }
+}
+
+/// How programs are parsed, and when they are parsed again because they are constant
+#[derive(Debug, Args)]
+pub struct ParseArgs {
+    #[command(flatten)]
+    pub limits: LimitArgs,
+
+    /// Maximum number of times a program is parsed. Constant programs are parsed again with other
+    /// choices, and with 1 they never are
+    #[arg(long, default_value_t = MAX_TRIES as u64, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_tries: u64,
+
+    /// Also parse programs again when their colors differ by less than this over the image, where
+    /// channels range from 0 to 1. The colors are sampled at a few points
+    #[arg(long, value_name = "TOLERANCE")]
+    pub near_constant: Option<f64>,
+
+    /// Fail on programs that would be repaired or truncated, instead of repairing them. Strict
+    /// programs are parsed once
     #[arg(long)]
-    pub dry_run: bool,
+    pub strict: bool,
+}
+
+impl ParseArgs {
+    /// The parse options set by the arguments.
+    pub fn options(&self) -> ParseOptions<'static> {
+        let constant_test = match self.near_constant {
+            Some(tolerance) => ConstantTest::Sampled {
+                grid: SAMPLE_GRID,
+                tolerance,
+            },
+            None => ConstantTest::Folded,
+        };
+        ParseOptions {
+            limits: self.limits.limits(),
+            max_tries: self.max_tries as usize,
+            constant_test,
+            strict: self.strict,
+            ..ParseOptions::default()
+        }
+    }
+}
+
+/// Variation of the programs
+#[derive(Debug, Args)]
+pub struct SeedArgs {
+    /// Seed for the choices made where the program is repaired. Every seed gives another variation
+    /// of the image, and seed 0 gives the usual one
+    #[arg(long, default_value = "0")]
+    pub seed: u64,
+}
+
+/// Images that programs can sample with `tex(n, u, v)`
+#[derive(Debug, Args)]
+pub struct TextureArgs {
+    /// Image that can be sampled with `tex(n, u, v)`. Can be given multiple times, the first image
+    /// is texture 0
+    #[arg(short, long("input-image"))]
+    pub input_images: Vec<PathBuf>,
+
+    /// How input images are sampled between pixels
+    #[arg(long, value_enum, default_value = "bilinear")]
+    pub sampling: Sampling,
+
+    /// How input images are sampled outside of their edges
+    #[arg(long, value_enum, default_value = "wrap")]
+    pub address: Addressing,
+}
+
+/// Options of the `render` command
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// Input file, or `-` to read from stdin
+    pub input: PathBuf,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    #[command(flatten)]
+    pub image: ImageArgs,
+
+    /// Use the program as a filter for an image, where `p` is the color of the source pixel.
+    /// The output has the size of the image unless a size is given
+    #[arg(long)]
+    pub filter: Option<PathBuf>,
+
+    /// Program that filters the rendered image, where `p` is the color of the previous pass. Can be
+    /// given multiple times to chain passes
+    #[arg(long("pass"))]
+    pub passes: Vec<PathBuf>,
+
+    /// Number of times the passes are applied
+    #[arg(long, default_value = "1")]
+    pub repeat: u32,
+
+    #[command(flatten)]
+    pub textures: TextureArgs,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub seed: SeedArgs,
+}
+
+/// Options of the `inspect` command
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    /// Input file, or `-` to read from stdin
+    pub input: PathBuf,
+
+    /// Parse the program as a filter, where `p` is the color of the source pixel
+    #[arg(long)]
+    pub filter: bool,
+
+    /// Print the generated tokens
+    #[arg(long("tokens"))]
+    pub print_tokens: bool,
 
     /// Print the generated expression
     #[arg(long("expr"))]
     pub print_expr: bool,
 
+    /// Print the generated expression as SSL source code
+    #[arg(long("source"))]
+    pub print_source: bool,
+
     /// Print the generated AST
     #[arg(long("ast"))]
     pub print_ast: bool,
 
-    /// Print the generated tokens
-    #[arg(long("tokens"))]
-    pub print_tokens: bool,
+    /// Print when the parser retries
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub seed: SeedArgs,
+}
+
+/// Options of the `fmt` command
+#[derive(Debug, Args)]
+pub struct FmtArgs {
+    /// Files to format. With `-`, stdin is formatted to stdout
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Only check that the files are formatted, failing if any are not
+    #[arg(long)]
+    pub check: bool,
+
+    /// Evaluate constant expressions and simplify the program, keeping the image it renders
+    #[arg(long)]
+    pub evaluate_constants: bool,
+
+    /// Format the files as filters, where `p` is the color of the source pixel
+    #[arg(long)]
+    pub filter: bool,
+
+    /// Maximum line length
+    #[arg(long, default_value_t = MAX_WIDTH)]
+    pub width: usize,
+}
+
+/// Options of the `hash` command
+#[derive(Debug, Args)]
+pub struct HashArgs {
+    /// File to visualize, or `-` to read from stdin
+    pub input: PathBuf,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    #[command(flatten)]
+    pub image: ImageArgs,
+}
+
+/// Options of the `batch` command
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// Input files
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Directory the images are written to, named after the input files
+    #[arg(short, long, default_value = ".")]
+    pub out_dir: PathBuf,
+
+    /// Format of the output images
+    #[arg(short, long, value_enum, default_value = "png")]
+    pub format: Format,
+
+    #[command(flatten)]
+    pub image: ImageArgs,
+
+    #[command(flatten)]
+    pub textures: TextureArgs,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub seed: SeedArgs,
+}
+
+/// Options of the `gallery` command
+#[derive(Debug, Args)]
+pub struct GalleryArgs {
+    /// Input file, or `-` to read from stdin
+    pub input: PathBuf,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Size of every thumbnail and how they are rendered
+    #[command(flatten)]
+    pub image: ImageArgs,
+
+    /// Seed of the first thumbnail. The seeds of the others count up from it
+    #[arg(long, default_value = "0")]
+    pub first_seed: u64,
+
+    /// Number of thumbnails
+    #[arg(short('n'), long, default_value = "16")]
+    pub count: u64,
+
+    /// Number of thumbnails in every row
+    #[arg(short, long, default_value = "4", value_parser = clap::value_parser!(u32).range(1..))]
+    pub columns: u32,
+
+    #[command(flatten)]
+    pub textures: TextureArgs,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+}
+
+/// Image formats that can be written
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Webp,
+    Bmp,
+    Ppm,
+    Qoi,
+}
+
+impl Format {
+    /// File extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::Webp => "webp",
+            Format::Bmp => "bmp",
+            Format::Ppm => "ppm",
+            Format::Qoi => "qoi",
+        }
+    }
+}
+
+impl From<Format> for ImageFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Png => ImageFormat::Png,
+            Format::Jpeg => ImageFormat::Jpeg,
+            Format::Webp => ImageFormat::WebP,
+            Format::Bmp => ImageFormat::Bmp,
+            Format::Ppm => ImageFormat::Pnm,
+            Format::Qoi => ImageFormat::Qoi,
+        }
+    }
+}
+
+/// Sampling filter of input images
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Sampling {
+    /// Use the closest pixel
+    Nearest,
+    /// Blend the four closest pixels
+    Bilinear,
+}
+
+impl From<Sampling> for Filter {
+    fn from(sampling: Sampling) -> Self {
+        match sampling {
+            Sampling::Nearest => Filter::Nearest,
+            Sampling::Bilinear => Filter::Bilinear,
+        }
+    }
+}
+
+/// Addressing mode of input images
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Addressing {
+    /// Repeat the image
+    Wrap,
+    /// Extend the edges of the image
+    Clamp,
+}
+
+impl From<Addressing> for Address {
+    fn from(addressing: Addressing) -> Self {
+        match addressing {
+            Addressing::Wrap => Address::Wrap,
+            Addressing::Clamp => Address::Clamp,
+        }
+    }
 }
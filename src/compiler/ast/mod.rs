@@ -8,7 +8,7 @@ use std::{
 
 use visitor::Visitor;
 
-use super::text::Span;
+use super::{text::Span, texture::Textures};
 
 mod printer;
 mod visitor;
@@ -47,6 +47,14 @@ impl Display for Expr {
             ExprKind::Abs(e) => write!(f, "Abs({})", e.inner),
             ExprKind::Sin(e) => write!(f, "Sin({})", e.inner),
             ExprKind::Cos(e) => write!(f, "Cos({})", e.inner),
+            ExprKind::Dx(e) => write!(f, "Dx({})", e.inner),
+            ExprKind::Dy(e) => write!(f, "Dy({})", e.inner),
+            ExprKind::Normal(e) => write!(f, "Normal({})", e.inner),
+            ExprKind::Shade(e) => match &e.elevation {
+                Some(elevation) => write!(f, "Shade({}, {}, {elevation})", e.height, e.light),
+                None => write!(f, "Shade({}, {})", e.height, e.light),
+            },
+            ExprKind::Tex(e) => write!(f, "Tex({}, {}, {})", e.index, e.u, e.v),
             ExprKind::Color(e) => write!(f, "{{{}, {}, {}}}", e.r, e.g, e.b),
             ExprKind::If(e) => write!(f, "If({}, {}, {})", e.cond, e.true_expr, e.false_expr),
             ExprKind::Number(n) => write!(f, "{n}"),
@@ -56,6 +64,7 @@ impl Display for Expr {
             ExprKind::Y => write!(f, "Y"),
             ExprKind::R => write!(f, "R"),
             ExprKind::A => write!(f, "A"),
+            ExprKind::Pixel(_) => write!(f, "P"),
         }
     }
 }
@@ -72,22 +81,45 @@ pub enum ExprKind {
     Abs(AbsExpr),
     Sin(SinExpr),
     Cos(CosExpr),
+    Dx(DxExpr),
+    Dy(DyExpr),
+    Normal(NormalExpr),
+    Shade(ShadeExpr),
+    Tex(TexExpr),
     TransX(TransXExpr),
     TransY(TransYExpr),
     X,
     Y,
     R,
     A,
+    Pixel(PixelExpr),
 }
 
 impl ExprKind {
-    pub fn is_zero(&self) -> bool {
+    /// Name of the kind of expression, like `If` or `TransX`.
+    pub fn name(&self) -> &'static str {
         match self {
-            Self::Number(n) => *n == 0.0,
-            Self::Color(ColorExpr { r, g, b }) => {
-                r.kind.is_zero() && g.kind.is_zero() && b.kind.is_zero()
-            }
-            _ => false,
+            ExprKind::Bin(_) => "Bin",
+            ExprKind::If(_) => "If",
+            ExprKind::Number(_) => "Number",
+            ExprKind::Color(_) => "Color",
+            ExprKind::Paren(_) => "Paren",
+            ExprKind::Neg(_) => "Neg",
+            ExprKind::Abs(_) => "Abs",
+            ExprKind::Sin(_) => "Sin",
+            ExprKind::Cos(_) => "Cos",
+            ExprKind::Dx(_) => "Dx",
+            ExprKind::Dy(_) => "Dy",
+            ExprKind::Normal(_) => "Normal",
+            ExprKind::Shade(_) => "Shade",
+            ExprKind::Tex(_) => "Tex",
+            ExprKind::TransX(_) => "TransX",
+            ExprKind::TransY(_) => "TransY",
+            ExprKind::X => "X",
+            ExprKind::Y => "Y",
+            ExprKind::R => "R",
+            ExprKind::A => "A",
+            ExprKind::Pixel(_) => "Pixel",
         }
     }
 
@@ -104,6 +136,12 @@ impl ExprKind {
             ExprKind::Color(ColorExpr { r, g, b }) => {
                 r.is_constant() && g.is_constant() && b.is_constant()
             }
+            // Comparisons between numbers are how boolean constants are represented.
+            ExprKind::Bin(BinExpr {
+                op: BinOp::LessThan | BinOp::GreaterThan,
+                lhs,
+                rhs,
+            }) => matches!((&lhs.kind, &rhs.kind), (Self::Number(_), Self::Number(_))),
             ExprKind::Bin(_)
             | ExprKind::If(_)
             | ExprKind::Paren(_)
@@ -111,12 +149,18 @@ impl ExprKind {
             | ExprKind::Abs(_)
             | ExprKind::Sin(_)
             | ExprKind::Cos(_)
+            | ExprKind::Dx(_)
+            | ExprKind::Dy(_)
+            | ExprKind::Normal(_)
+            | ExprKind::Shade(_)
+            | ExprKind::Tex(_)
             | ExprKind::TransX(_)
             | ExprKind::TransY(_)
             | ExprKind::X
             | ExprKind::Y
             | ExprKind::R
-            | ExprKind::A => false,
+            | ExprKind::A
+            | ExprKind::Pixel(_) => false,
         }
     }
 }
@@ -125,6 +169,247 @@ impl Expr {
     pub fn is_constant(&self) -> bool {
         self.kind.is_constant()
     }
+
+    /// Check if the expression reads the color of the source pixel anywhere.
+    pub fn uses_pixel(&self) -> bool {
+        struct Finder(bool);
+        impl Visitor for Finder {
+            fn visit_pixel_expr(&mut self, _expr: &PixelExpr) {
+                self.0 = true;
+            }
+            fn visit_number_expr(&mut self, _expr: &NumberExpr) {}
+            fn visit_color_expr(&mut self, expr: &ColorExpr) {
+                self.visit_expr(&expr.r);
+                self.visit_expr(&expr.g);
+                self.visit_expr(&expr.b);
+            }
+            fn visit_x_expr(&mut self) {}
+            fn visit_y_expr(&mut self) {}
+            fn visit_r_expr(&mut self) {}
+            fn visit_a_expr(&mut self) {}
+        }
+
+        let mut finder = Finder(false);
+        finder.visit_expr(self);
+        finder.0
+    }
+
+    /// The type of value the expression evaluates to, if it can be known without evaluating it.
+    pub fn value_type(&self) -> Option<ValueType> {
+        self.kind.value_type()
+    }
+
+    /// Number of expressions on the longest path from this expression down to a leaf.
+    #[allow(dead_code)] // TODO: Find a better solution
+    pub fn depth(&self) -> usize {
+        struct Depth {
+            current: usize,
+            max: usize,
+        }
+        impl Visitor for Depth {
+            fn visit_expr(&mut self, expr: &Expr) {
+                self.current += 1;
+                self.max = self.max.max(self.current);
+                self.do_visit_expr(expr);
+                self.current -= 1;
+            }
+            fn visit_pixel_expr(&mut self, _expr: &PixelExpr) {}
+            fn visit_number_expr(&mut self, _expr: &NumberExpr) {}
+            fn visit_color_expr(&mut self, expr: &ColorExpr) {
+                self.visit_expr(&expr.r);
+                self.visit_expr(&expr.g);
+                self.visit_expr(&expr.b);
+            }
+            fn visit_x_expr(&mut self) {}
+            fn visit_y_expr(&mut self) {}
+            fn visit_r_expr(&mut self) {}
+            fn visit_a_expr(&mut self) {}
+        }
+
+        let mut depth = Depth { current: 0, max: 0 };
+        depth.visit_expr(self);
+        depth.max
+    }
+
+    /// The direct children of the expression, in the order [Expr::map_children] visits them.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Bin(e) => vec![&e.lhs, &e.rhs],
+            ExprKind::If(e) => vec![&e.cond, &e.true_expr, &e.false_expr],
+            ExprKind::Color(e) => vec![&e.r, &e.g, &e.b],
+            ExprKind::Paren(ParenExpr { inner })
+            | ExprKind::Neg(NegExpr { inner })
+            | ExprKind::Abs(AbsExpr { inner })
+            | ExprKind::Sin(SinExpr { inner })
+            | ExprKind::Cos(CosExpr { inner })
+            | ExprKind::Dx(DxExpr { inner })
+            | ExprKind::Dy(DyExpr { inner })
+            | ExprKind::Normal(NormalExpr { inner }) => vec![inner],
+            ExprKind::Shade(e) => {
+                let mut children = vec![&*e.height, &*e.light];
+                children.extend(e.elevation.as_deref());
+                children
+            }
+            ExprKind::Tex(e) => vec![&e.index, &e.u, &e.v],
+            ExprKind::TransX(e) => vec![&e.trans, &e.inner],
+            ExprKind::TransY(e) => vec![&e.trans, &e.inner],
+            ExprKind::Number(_)
+            | ExprKind::X
+            | ExprKind::Y
+            | ExprKind::R
+            | ExprKind::A
+            | ExprKind::Pixel(_) => vec![],
+        }
+    }
+
+    /// Take the direct children out of the expression, in the order [Expr::map_children] visits
+    /// them. The expression is returned with placeholders in place of its children.
+    pub fn split_children(self) -> (Expr, Vec<Expr>) {
+        let mut children = Vec::new();
+        let expr = self.map_children(|child| {
+            children.push(child);
+            Expr {
+                kind: ExprKind::Number(0.0),
+                span: Span::new(0, 0),
+            }
+        });
+        (expr, children)
+    }
+
+    /// Rebuild the expression by applying `f` to each of its direct children.
+    pub fn map_children(self, mut f: impl FnMut(Expr) -> Expr) -> Expr {
+        let kind = match self.kind {
+            ExprKind::Bin(e) => ExprKind::Bin(BinExpr::new(e.op, f(*e.lhs), f(*e.rhs))),
+            ExprKind::If(e) => {
+                ExprKind::If(IfExpr::new(f(*e.cond), f(*e.true_expr), f(*e.false_expr)))
+            }
+            ExprKind::Color(e) => ExprKind::Color(ColorExpr::new(f(*e.r), f(*e.g), f(*e.b))),
+            ExprKind::Paren(e) => ExprKind::Paren(ParenExpr::new(f(*e.inner))),
+            ExprKind::Neg(e) => ExprKind::Neg(NegExpr::new(f(*e.inner))),
+            ExprKind::Abs(e) => ExprKind::Abs(AbsExpr::new(f(*e.inner))),
+            ExprKind::Sin(e) => ExprKind::Sin(SinExpr::new(f(*e.inner))),
+            ExprKind::Cos(e) => ExprKind::Cos(CosExpr::new(f(*e.inner))),
+            ExprKind::Dx(e) => ExprKind::Dx(DxExpr::new(f(*e.inner))),
+            ExprKind::Dy(e) => ExprKind::Dy(DyExpr::new(f(*e.inner))),
+            ExprKind::Normal(e) => ExprKind::Normal(NormalExpr::new(f(*e.inner))),
+            ExprKind::Shade(e) => ExprKind::Shade(ShadeExpr::new(
+                f(*e.height),
+                f(*e.light),
+                e.elevation.map(|elevation| f(*elevation)),
+            )),
+            ExprKind::Tex(e) => ExprKind::Tex(TexExpr {
+                index: Box::new(f(*e.index)),
+                u: Box::new(f(*e.u)),
+                v: Box::new(f(*e.v)),
+                textures: e.textures,
+            }),
+            ExprKind::TransX(e) => ExprKind::TransX(TransXExpr::new(f(*e.trans), f(*e.inner))),
+            ExprKind::TransY(e) => ExprKind::TransY(TransYExpr::new(f(*e.trans), f(*e.inner))),
+            kind @ (ExprKind::Number(_)
+            | ExprKind::X
+            | ExprKind::Y
+            | ExprKind::R
+            | ExprKind::A
+            | ExprKind::Pixel(_)) => kind,
+        };
+        Expr {
+            kind,
+            span: self.span,
+        }
+    }
+}
+
+impl Expr {
+    /// Rebuild the expression bottom-up by applying `f` to every expression after its children.
+    ///
+    /// The tree is walked with an explicit stack, so its depth is not limited by the size of the
+    /// call stack.
+    pub fn transform_up(self, mut f: impl FnMut(Expr) -> Expr) -> Expr {
+        enum Task {
+            Visit(Expr),
+            Build(Expr, usize),
+        }
+
+        let mut tasks = vec![Task::Visit(self)];
+        let mut done: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(expr) => {
+                    let (expr, children) = expr.split_children();
+                    tasks.push(Task::Build(expr, children.len()));
+                    tasks.extend(children.into_iter().rev().map(Task::Visit));
+                }
+                Task::Build(expr, count) => {
+                    let mut children = done.split_off(done.len() - count).into_iter();
+                    let expr = expr.map_children(|_| children.next().expect("missing child"));
+                    done.push(f(expr));
+                }
+            }
+        }
+        done.pop().expect("missing result")
+    }
+}
+
+/// The type of value an expression evaluates to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    Number,
+    Color,
+    Bool,
+}
+
+impl ExprKind {
+    /// The type of value the expression evaluates to. Returns [None] if the type depends on the
+    /// point the expression is evaluated at.
+    pub fn value_type(&self) -> Option<ValueType> {
+        use ValueType::*;
+
+        // Functions like `sin` and `abs` turn booleans into numbers.
+        let numeric = |t: Option<ValueType>| match t? {
+            Color => Some(Color),
+            Number | Bool => Some(Number),
+        };
+
+        match self {
+            ExprKind::Number(_) | ExprKind::X | ExprKind::Y | ExprKind::R | ExprKind::A => {
+                Some(Number)
+            }
+            ExprKind::Color(_) => Some(Color),
+            ExprKind::Paren(e) => e.inner.value_type(),
+            ExprKind::TransX(e) => e.inner.value_type(),
+            ExprKind::TransY(e) => e.inner.value_type(),
+            ExprKind::Neg(e) => numeric(e.inner.value_type()),
+            ExprKind::Abs(e) => numeric(e.inner.value_type()),
+            ExprKind::Sin(e) => numeric(e.inner.value_type()),
+            ExprKind::Cos(e) => numeric(e.inner.value_type()),
+            ExprKind::Dx(e) => numeric(e.inner.value_type()),
+            ExprKind::Dy(e) => numeric(e.inner.value_type()),
+            ExprKind::Normal(_) | ExprKind::Tex(_) | ExprKind::Pixel(_) => Some(Color),
+            ExprKind::Shade(e) => numeric(e.height.value_type()),
+            ExprKind::If(e) => match (e.true_expr.value_type(), e.false_expr.value_type()) {
+                (Some(t), Some(f)) if t == f => Some(t),
+                _ => None,
+            },
+            ExprKind::Bin(e) => {
+                let (l, r) = (e.lhs.value_type(), e.rhs.value_type());
+                if l == Some(Color) || r == Some(Color) {
+                    return match e.op {
+                        BinOp::Or | BinOp::And if l != r => None,
+                        _ => Some(Color),
+                    };
+                }
+                let (l, r) = (l?, r?);
+                match e.op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => Some(Number),
+                    BinOp::Mod | BinOp::Pow if l == Bool && r == Bool => Some(Bool),
+                    BinOp::Mod | BinOp::Pow => Some(Number),
+                    BinOp::LessThan | BinOp::GreaterThan => Some(Bool),
+                    BinOp::Or | BinOp::And if l == r => Some(l),
+                    BinOp::Or | BinOp::And => None,
+                }
+            }
+        }
+    }
 }
 
 /// Color expression. Syntax: `{r, g, b}`.
@@ -143,6 +428,13 @@ impl ColorExpr {
             b: Box::new(b),
         }
     }
+
+    /// Reorder values between the fields of a color literal and the red, green and blue channels
+    /// of the color it evaluates to. The evaluator reads a literal as `{r, b, g}`, so the last two
+    /// trade places. Swapping them twice gives the same order, so this converts both ways.
+    pub fn channel_order<T>([r, g, b]: [T; 3]) -> [T; 3] {
+        [r, b, g]
+    }
 }
 
 /// Operator for binary expressions. The precedence is used to determine the order of operations.
@@ -246,6 +538,54 @@ impl TransYExpr {
     }
 }
 
+/// Shade a height field. Syntax: `shade(<height>, <light>)` or
+/// `shade(<height>, <light>, <elevation>)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadeExpr {
+    pub height: Box<Expr>,
+    pub light: Box<Expr>,
+    /// The elevation of the light. The light is 45 degrees above the surface when it is `None`.
+    pub elevation: Option<Box<Expr>>,
+}
+
+impl ShadeExpr {
+    pub fn new(height: Expr, light: Expr, elevation: Option<Expr>) -> Self {
+        Self {
+            height: Box::new(height),
+            light: Box::new(light),
+            elevation: elevation.map(Box::new),
+        }
+    }
+}
+
+/// Sample a bound texture. Syntax: `tex(<index>, <u>, <v>)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexExpr {
+    pub index: Box<Expr>,
+    pub u: Box<Expr>,
+    pub v: Box<Expr>,
+    /// The textures bound to the expression. Empty until textures are bound.
+    pub textures: Textures,
+}
+
+impl TexExpr {
+    pub fn new(index: Expr, u: Expr, v: Expr) -> Self {
+        Self {
+            index: Box::new(index),
+            u: Box::new(u),
+            v: Box::new(v),
+            textures: Textures::default(),
+        }
+    }
+}
+
+/// Color of the source pixel when an image is filtered. Syntax: `p`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PixelExpr {
+    /// The image being filtered. Empty until a source is bound.
+    pub source: Textures,
+}
+
 /// Define an expression kind that simply wraps an expression.
 macro_rules! wrapper_expr {
     ($name:ident: $doc:literal) => {
@@ -270,3 +610,6 @@ wrapper_expr!(NegExpr: "Negated expression. Syntax: `-<expr>`.");
 wrapper_expr!(AbsExpr: "Absolute value expression. Syntax: `abs(<expr>)`.");
 wrapper_expr!(SinExpr: "Sine expression. Syntax: `sin(<expr>)`.");
 wrapper_expr!(CosExpr: "Cosine expression. Syntax: `cos(<expr>)`.");
+wrapper_expr!(DxExpr: "Partial derivative with respect to x. Syntax: `dx(<expr>)`.");
+wrapper_expr!(DyExpr: "Partial derivative with respect to y. Syntax: `dy(<expr>)`.");
+wrapper_expr!(NormalExpr: "Surface normal of a height field. Syntax: `normal(<height>)`.");
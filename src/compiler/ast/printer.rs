@@ -98,6 +98,65 @@ impl Visitor for Printer {
         self.unindent();
     }
 
+    fn visit_dx_expr(&mut self, expr: &super::DxExpr) {
+        vprintln!(self, "DxExpr:");
+        self.indent();
+        self.do_visit_dx_expr(expr);
+        self.unindent();
+    }
+
+    fn visit_dy_expr(&mut self, expr: &super::DyExpr) {
+        vprintln!(self, "DyExpr:");
+        self.indent();
+        self.do_visit_dy_expr(expr);
+        self.unindent();
+    }
+
+    fn visit_normal_expr(&mut self, expr: &super::NormalExpr) {
+        vprintln!(self, "NormalExpr:");
+        self.indent();
+        self.do_visit_normal_expr(expr);
+        self.unindent();
+    }
+
+    fn visit_shade_expr(&mut self, expr: &super::ShadeExpr) {
+        vprintln!(self, "ShadeExpr:");
+        self.indent();
+        vprintln!(self, "Height:");
+        self.indent();
+        self.visit_expr(&expr.height);
+        self.unindent();
+        vprintln!(self, "Light:");
+        self.indent();
+        self.visit_expr(&expr.light);
+        self.unindent();
+        if let Some(elevation) = &expr.elevation {
+            vprintln!(self, "Elevation:");
+            self.indent();
+            self.visit_expr(elevation);
+            self.unindent();
+        }
+        self.unindent();
+    }
+
+    fn visit_tex_expr(&mut self, expr: &super::TexExpr) {
+        vprintln!(self, "TexExpr:");
+        self.indent();
+        vprintln!(self, "Index:");
+        self.indent();
+        self.visit_expr(&expr.index);
+        self.unindent();
+        vprintln!(self, "U:");
+        self.indent();
+        self.visit_expr(&expr.u);
+        self.unindent();
+        vprintln!(self, "V:");
+        self.indent();
+        self.visit_expr(&expr.v);
+        self.unindent();
+        self.unindent();
+    }
+
     fn visit_number_expr(&mut self, expr: &super::NumberExpr) {
         vprintln!(self, "NumberExpr: {:?}", expr)
     }
@@ -175,4 +234,8 @@ impl Visitor for Printer {
     fn visit_a_expr(&mut self) {
         vprintln!(self, "A")
     }
+
+    fn visit_pixel_expr(&mut self, _expr: &super::PixelExpr) {
+        vprintln!(self, "P")
+    }
 }
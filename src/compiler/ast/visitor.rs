@@ -12,6 +12,11 @@ pub trait Visitor {
             super::ExprKind::Abs(e) => self.visit_abs_expr(e),
             super::ExprKind::Sin(e) => self.visit_sin_expr(e),
             super::ExprKind::Cos(e) => self.visit_cos_expr(e),
+            super::ExprKind::Dx(e) => self.visit_dx_expr(e),
+            super::ExprKind::Dy(e) => self.visit_dy_expr(e),
+            super::ExprKind::Normal(e) => self.visit_normal_expr(e),
+            super::ExprKind::Shade(e) => self.visit_shade_expr(e),
+            super::ExprKind::Tex(e) => self.visit_tex_expr(e),
             super::ExprKind::Number(e) => self.visit_number_expr(e),
             super::ExprKind::Color(e) => self.visit_color_expr(e),
             super::ExprKind::If(e) => self.visit_if_expr(e),
@@ -21,6 +26,7 @@ pub trait Visitor {
             super::ExprKind::Y => self.visit_y_expr(),
             super::ExprKind::R => self.visit_r_expr(),
             super::ExprKind::A => self.visit_a_expr(),
+            super::ExprKind::Pixel(e) => self.visit_pixel_expr(e),
         }
     }
 
@@ -67,6 +73,47 @@ pub trait Visitor {
         self.visit_expr(&expr.inner)
     }
 
+    fn visit_dx_expr(&mut self, expr: &super::DxExpr) {
+        self.do_visit_dx_expr(expr);
+    }
+    fn do_visit_dx_expr(&mut self, expr: &super::DxExpr) {
+        self.visit_expr(&expr.inner)
+    }
+
+    fn visit_dy_expr(&mut self, expr: &super::DyExpr) {
+        self.do_visit_dy_expr(expr);
+    }
+    fn do_visit_dy_expr(&mut self, expr: &super::DyExpr) {
+        self.visit_expr(&expr.inner)
+    }
+
+    fn visit_normal_expr(&mut self, expr: &super::NormalExpr) {
+        self.do_visit_normal_expr(expr);
+    }
+    fn do_visit_normal_expr(&mut self, expr: &super::NormalExpr) {
+        self.visit_expr(&expr.inner)
+    }
+
+    fn visit_shade_expr(&mut self, expr: &super::ShadeExpr) {
+        self.do_visit_shade_expr(expr);
+    }
+    fn do_visit_shade_expr(&mut self, expr: &super::ShadeExpr) {
+        self.visit_expr(&expr.height);
+        self.visit_expr(&expr.light);
+        if let Some(elevation) = &expr.elevation {
+            self.visit_expr(elevation);
+        }
+    }
+
+    fn visit_tex_expr(&mut self, expr: &super::TexExpr) {
+        self.do_visit_tex_expr(expr);
+    }
+    fn do_visit_tex_expr(&mut self, expr: &super::TexExpr) {
+        self.visit_expr(&expr.index);
+        self.visit_expr(&expr.u);
+        self.visit_expr(&expr.v);
+    }
+
     fn visit_if_expr(&mut self, expr: &super::IfExpr) {
         self.do_visit_if_expr(expr);
     }
@@ -98,4 +145,5 @@ pub trait Visitor {
     fn visit_y_expr(&mut self);
     fn visit_r_expr(&mut self);
     fn visit_a_expr(&mut self);
+    fn visit_pixel_expr(&mut self, expr: &super::PixelExpr);
 }
@@ -0,0 +1,336 @@
+//! Forward-mode automatic differentiation. Expressions are evaluated with dual numbers, which carry
+//! the partial derivatives with respect to x and y alongside the value. This is what the `dx(e)`
+//! and `dy(e)` built-ins are evaluated with.
+//!
+//! The value is computed with the same operations as the [evaluator], so it is always identical to
+//! the result of [evaluator::eval_expr]. Where the evaluator turns NaN into zero, and for
+//! booleans, the derivative is zero.
+
+#[cfg(test)]
+mod tests;
+
+use std::f64::consts::PI;
+
+use super::{
+    ast::{BinOp, ColorExpr, Expr, ExprKind},
+    evaluator::{self, bool_to_f64, wrap, Color, Observer, Result},
+};
+
+/// Step size of the finite differences used where derivatives are not propagated exactly.
+const STEP: f64 = 1e-4;
+
+/// A coordinate together with its partial derivatives with respect to the x and y coordinates of
+/// the pixel. Translations turn the coordinates into functions of the pixel coordinates.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Coord {
+    v: f64,
+    dx: f64,
+    dy: f64,
+}
+
+impl Coord {
+    /// The x coordinate of a pixel.
+    fn x(x: f64) -> Self {
+        Self {
+            v: x,
+            dx: 1.0,
+            dy: 0.0,
+        }
+    }
+
+    /// The y coordinate of a pixel.
+    fn y(y: f64) -> Self {
+        Self {
+            v: y,
+            dx: 0.0,
+            dy: 1.0,
+        }
+    }
+
+    fn to_dual(self) -> Dual {
+        Dual {
+            value: Result::Number(self.v),
+            dx: Result::Number(self.dx),
+            dy: Result::Number(self.dy),
+        }
+    }
+}
+
+/// Result of evaluating an expression, together with its partial derivatives. The derivatives are
+/// colors if the value is a color, and numbers otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Dual {
+    pub value: Result,
+    pub dx: Result,
+    pub dy: Result,
+}
+
+impl Dual {
+    /// A value that does not change with the coordinates.
+    fn constant(value: Result) -> Self {
+        Self {
+            value,
+            dx: Result::Number(0.0),
+            dy: Result::Number(0.0),
+        }
+    }
+
+    /// Convert NaN values to zero, like the evaluator does. Derivatives are also converted from
+    /// `-0.0` to `0.0`, so the derivative of a constant does not depend on how it is written.
+    fn normalize(&mut self) {
+        self.value.nan_to_zero();
+        for d in [&mut self.dx, &mut self.dy] {
+            d.nan_to_zero();
+            *d = d.call(|d| d + 0.0);
+        }
+    }
+}
+
+/// Channel `i` of a result. Numbers and booleans have the same value in every channel.
+fn channel(r: &Result, i: usize) -> f64 {
+    match r {
+        Result::Color(c) => [c.r, c.g, c.b][i],
+        Result::Number(n) => *n,
+        Result::Bool(b) => bool_to_f64(*b),
+    }
+}
+
+/// Build the derivative of a value from the derivative of each of its channels. Booleans are
+/// constant, and NaN values are turned into zero by the evaluator, so both have a derivative of
+/// zero.
+fn derivative(value: &Result, f: impl Fn(usize) -> f64) -> Result {
+    let d = |i| match channel(value, i).is_nan() {
+        true => 0.0,
+        false => f(i),
+    };
+    match value {
+        Result::Color(_) => Result::Color(Color {
+            r: d(0),
+            g: d(1),
+            b: d(2),
+        }),
+        Result::Number(_) => Result::Number(d(0)),
+        Result::Bool(_) => Result::Number(0.0),
+    }
+}
+
+/// A term of a derivative. Zero if the derivative `d` is zero, even if `f` is infinite, so
+/// operands that do not change do not contribute.
+fn term(d: f64, f: f64) -> f64 {
+    match d == 0.0 {
+        true => 0.0,
+        false => d * f,
+    }
+}
+
+/// Differentiate a function of one argument. `f` is called with a channel of the argument and the
+/// derivative of that channel.
+fn unary(inner: &Dual, value: Result, f: impl Fn(f64, f64) -> f64) -> Dual {
+    let d = |d: &Result| derivative(&value, |i| f(channel(&inner.value, i), channel(d, i)));
+    Dual {
+        dx: d(&inner.dx),
+        dy: d(&inner.dy),
+        value,
+    }
+}
+
+/// Differentiate a function of two arguments. `f` is called with a channel of each argument
+/// followed by its derivative.
+fn binary(l: &Dual, r: &Dual, value: Result, f: impl Fn(f64, f64, f64, f64) -> f64) -> Dual {
+    let d = |dl: &Result, dr: &Result| {
+        derivative(&value, |i| {
+            f(
+                channel(&l.value, i),
+                channel(dl, i),
+                channel(&r.value, i),
+                channel(dr, i),
+            )
+        })
+    };
+    Dual {
+        dx: d(&l.dx, &r.dx),
+        dy: d(&l.dy, &r.dy),
+        value,
+    }
+}
+
+/// Differentiate a function of the coordinates with central finite differences. This is used for
+/// nested derivatives, like `dx(dx(e))`, for the lighting built-ins, which are themselves computed
+/// from derivatives, and for images.
+fn finite_difference(x: Coord, y: Coord, mut at: impl FnMut(f64, f64) -> Result) -> Dual {
+    let step = Result::Number(2.0 * STEP);
+    let dx = (at(x.v + STEP, y.v) - at(x.v - STEP, y.v)) / step.clone();
+    let dy = (at(x.v, y.v + STEP) - at(x.v, y.v - STEP)) / step;
+
+    // Chain rule, as the coordinates may themselves depend on the pixel coordinates.
+    let chain = |a: f64, b: f64| dx.clone() * Result::Number(a) + dy.clone() * Result::Number(b);
+    Dual {
+        value: at(x.v, y.v),
+        dx: chain(x.dx, y.dx),
+        dy: chain(x.dy, y.dy),
+    }
+}
+
+/// Evaluate an expression and its partial derivatives at a point, reporting every evaluation to
+/// an observer like the [evaluator] does.
+pub(crate) fn eval_dual<O: Observer>(expr: &Expr, x: Coord, y: Coord, obs: &mut O) -> Dual {
+    obs.visit(expr);
+    let mut res = match &expr.kind {
+        ExprKind::Bin(e) => {
+            let l = eval_dual(&e.lhs, x, y, obs);
+            let r = eval_dual(&e.rhs, x, y, obs);
+            let (lv, rv) = (l.value.clone(), r.value.clone());
+            match e.op {
+                BinOp::Add => binary(&l, &r, lv + rv, |_, da, _, db| da + db),
+                BinOp::Sub => binary(&l, &r, lv - rv, |_, da, _, db| da - db),
+                BinOp::Mul => binary(&l, &r, lv * rv, |a, da, b, db| term(da, b) + term(db, a)),
+                BinOp::Div => binary(&l, &r, lv / rv, |a, da, b, db| {
+                    term(da, 1.0 / b) - term(db, a / (b * b))
+                }),
+                BinOp::Mod => binary(&l, &r, lv.fmod(rv), |a, da, b, db| {
+                    da - term(db, (a / b).trunc())
+                }),
+                BinOp::Pow => binary(&l, &r, lv.pow(rv), |a, da, b, db| {
+                    term(da, b * a.powf(b - 1.0)) + term(db, a.powf(b) * a.ln())
+                }),
+                BinOp::LessThan => Dual::constant(lv.less(rv)),
+                BinOp::GreaterThan => Dual::constant(lv.greater(rv)),
+                // The operand that is picked by the evaluator is passed through with its
+                // derivatives.
+                BinOp::Or | BinOp::And => {
+                    let picked = match e.op {
+                        BinOp::Or => lv.clone().or(rv),
+                        _ => lv.clone().and(rv),
+                    };
+                    match picked {
+                        Result::Bool(_) => Dual::constant(picked),
+                        v if v == lv => l,
+                        _ => r,
+                    }
+                }
+            }
+        }
+        ExprKind::Color(c) => {
+            let [r, g, b] =
+                ColorExpr::channel_order([&c.r, &c.g, &c.b].map(|e| eval_dual(e, x, y, obs)));
+            let color = |f: fn(&Dual) -> &Result| {
+                Result::Color(Color {
+                    r: f(&r).as_number(),
+                    g: f(&g).as_number(),
+                    b: f(&b).as_number(),
+                })
+            };
+            Dual {
+                value: color(|d| &d.value),
+                dx: color(|d| &d.dx),
+                dy: color(|d| &d.dy),
+            }
+        }
+        ExprKind::Paren(e) => eval_dual(&e.inner, x, y, obs),
+        ExprKind::Neg(e) => {
+            let inner = eval_dual(&e.inner, x, y, obs);
+            let value = inner.value.clone() * Result::Number(-1.0);
+            unary(&inner, value, |_, d| -d)
+        }
+        ExprKind::Abs(e) => {
+            let inner = eval_dual(&e.inner, x, y, obs);
+            let value = inner.value.abs();
+            unary(&inner, value, |a, d| match a == 0.0 {
+                true => 0.0,
+                false => a.signum() * d,
+            })
+        }
+        ExprKind::Sin(e) => {
+            let mut inner = eval_dual(&e.inner, x, y, obs);
+            let value = inner.value.call(f64::sin);
+            unary(&inner, value, |a, d| term(d, a.cos()))
+        }
+        ExprKind::Cos(e) => {
+            let mut inner = eval_dual(&e.inner, x, y, obs);
+            let value = inner.value.call(f64::cos);
+            unary(&inner, value, |a, d| term(d, -a.sin()))
+        }
+        ExprKind::Dx(e) => finite_difference(x, y, |x, y| eval_derivatives(&e.inner, x, y, obs).dx),
+        ExprKind::Dy(e) => finite_difference(x, y, |x, y| eval_derivatives(&e.inner, x, y, obs).dy),
+        ExprKind::Normal(_) | ExprKind::Shade(_) | ExprKind::Tex(_) | ExprKind::Pixel(_) => {
+            finite_difference(x, y, |x, y| evaluator::eval_expr_observed(expr, x, y, obs))
+        }
+        ExprKind::Number(n) => Dual::constant(Result::Number(*n)),
+        ExprKind::TransX(e) => {
+            let offset = eval_dual(&e.trans, x, y, obs);
+            let x = Coord {
+                v: wrap(x.v - offset.value.as_number()),
+                dx: x.dx - offset.dx.as_number(),
+                dy: x.dy - offset.dy.as_number(),
+            };
+            eval_dual(&e.inner, x, y, obs)
+        }
+        ExprKind::TransY(e) => {
+            let offset = eval_dual(&e.trans, x, y, obs);
+            let y = Coord {
+                v: wrap(y.v - offset.value.as_number()),
+                dx: y.dx - offset.dx.as_number(),
+                dy: y.dy - offset.dy.as_number(),
+            };
+            eval_dual(&e.inner, x, y, obs)
+        }
+        ExprKind::X => x.to_dual(),
+        ExprKind::Y => y.to_dual(),
+        ExprKind::R => {
+            let r = f64::sqrt(x.v * x.v + y.v * y.v);
+            let d = |dx: f64, dy: f64| Result::Number((x.v * dx + y.v * dy) / r);
+            Dual {
+                value: Result::Number(r),
+                dx: d(x.dx, y.dx),
+                dy: d(x.dy, y.dy),
+            }
+        }
+        ExprKind::A => {
+            let r2 = x.v * x.v + y.v * y.v;
+            let d = |dx: f64, dy: f64| Result::Number((x.v * dy - y.v * dx) / r2 / PI);
+            Dual {
+                value: Result::Number(f64::atan(y.v / x.v) / PI),
+                dx: d(x.dx, y.dx),
+                dy: d(x.dy, y.dy),
+            }
+        }
+        ExprKind::If(e) => {
+            let cond = evaluator::eval_expr_observed(&e.cond, x.v, y.v, obs).as_bool();
+            obs.branch(e, cond);
+            match cond {
+                true => eval_dual(&e.true_expr, x, y, obs),
+                false => eval_dual(&e.false_expr, x, y, obs),
+            }
+        }
+    };
+    res.normalize();
+    res
+}
+
+/// Evaluate an expression and its partial derivatives at the coordinates of a pixel.
+pub(crate) fn eval_derivatives<O: Observer>(expr: &Expr, x: f64, y: f64, obs: &mut O) -> Dual {
+    eval_dual(expr, Coord::x(x), Coord::y(y), obs)
+}
+
+/// The value of an expression at a point, together with its partial derivatives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    /// The value of the expression. Unlike [crate::render], the value is not clamped.
+    pub value: Color,
+    /// Partial derivative with respect to x
+    pub dx: Color,
+    /// Partial derivative with respect to y
+    pub dy: Color,
+}
+
+/// Evaluate an expression and its partial derivatives with respect to x and y at a point.
+/// Numbers and booleans are returned as gray colors, like when they are rendered.
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn gradient(expr: &Expr, x: f64, y: f64) -> Gradient {
+    let d = eval_derivatives(expr, x, y, &mut ());
+    Gradient {
+        value: d.value.as_color(),
+        dx: d.dx.as_color(),
+        dy: d.dy.as_color(),
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+
+use super::eval_derivatives;
+use crate::compiler::{
+    differential::{random_source, Rng},
+    evaluator::{self, Result},
+    parser,
+};
+
+/// Points where the derivatives are checked. They avoid the origin and the axes, where some
+/// expressions are not differentiable.
+const POINTS: &[(f64, f64)] = &[(0.3, 0.7), (-0.45, 0.2), (0.8, -0.6), (-0.15, -0.35)];
+
+/// Step size of the finite differences the derivatives are compared against.
+const H: f64 = 1e-6;
+
+fn number(r: &Result) -> f64 {
+    match r {
+        Result::Number(n) => *n,
+        r => panic!("Expected a number, got {r:?}"),
+    }
+}
+
+/// Check the derivatives of an expression against central finite differences.
+fn check_derivatives(source: &str) {
+    let expr = parser::parse_source(source.to_string());
+    for &(x, y) in POINTS {
+        let f = |x, y| evaluator::eval_expr(&expr, x, y).as_color();
+        let d = eval_derivatives(&expr, x, y, &mut ());
+        let (dx, dy) = (d.dx.as_color(), d.dy.as_color());
+
+        let (l, r) = (f(x - H, y), f(x + H, y));
+        let (b, t) = (f(x, y - H), f(x, y + H));
+        let pairs = [
+            (dx.r, (r.r - l.r) / (2.0 * H)),
+            (dx.g, (r.g - l.g) / (2.0 * H)),
+            (dx.b, (r.b - l.b) / (2.0 * H)),
+            (dy.r, (t.r - b.r) / (2.0 * H)),
+            (dy.g, (t.g - b.g) / (2.0 * H)),
+            (dy.b, (t.b - b.b) / (2.0 * H)),
+        ];
+        for (exact, approx) in pairs {
+            assert!(
+                (exact - approx).abs() <= 1e-4 * (1.0 + approx.abs()),
+                "{source}: derivative at ({x}, {y}) is {exact}, expected about {approx}"
+            );
+        }
+    }
+}
+
+macro_rules! test_derivatives {
+    ($name:ident: $source:literal) => {
+        #[test]
+        fn $name() {
+            check_derivatives($source);
+        }
+    };
+}
+
+test_derivatives!( poly:        "x * x * y + 3.0 * y"              );
+test_derivatives!( div:         "x / (y + 2.0)"                     );
+test_derivatives!( pow:         "(x + 2.0) ^ (y + 1.5)"             );
+test_derivatives!( modulo:      "(x * 3.0) % 0.7"                   );
+test_derivatives!( trig:        "sin(x * 3.0) * cos(y * 2.0)"       );
+test_derivatives!( abs:         "|x - y|"                           );
+test_derivatives!( radius:      "r"                                 );
+test_derivatives!( angle:       "a"                                 );
+test_derivatives!( color:       "{x, y * y, sin(x * y)}"            );
+test_derivatives!( color_num:   "{x, y, 1.0} * (x + y)"             );
+test_derivatives!( trans_x:     "tx(y * 0.2, x * x)"                );
+test_derivatives!( trans_y:     "ty(x * 0.3 + 0.1, sin(y * 2.0))"   );
+test_derivatives!( max:         "x | y"                             );
+test_derivatives!( cond:        "if x < y then x * y else x + y end" );
+test_derivatives!( nested:      "dx(x * x * y)"                     );
+test_derivatives!( normal:      "normal(x * x + y * 0.5)"           );
+test_derivatives!( shade:       "shade(sin(x * 2.0) * y, 0.3)"      );
+test_derivatives!( nested_tx:   "tx(y * 0.5, dx(sin(x * 2.0)))"     );
+
+#[test]
+fn builtins() {
+    let eval = |source: &str, x, y| {
+        number(&evaluator::eval_expr(
+            &parser::parse_source(source.to_string()),
+            x,
+            y,
+        ))
+    };
+    assert_eq!(eval("dx(x * x)", 0.5, 0.0), 1.0);
+    assert_eq!(eval("dy(x * x)", 0.5, 0.0), 0.0);
+    assert_eq!(eval("dy(x * y)", 0.5, 0.25), 0.5);
+    assert_eq!(eval("dx(x < y)", 0.5, 0.25), 0.0);
+    assert_eq!(eval("dx(tx(0.5, x))", 0.1, 0.0), 1.0);
+    assert_eq!(eval("dx(tx(x, x))", 0.1, 0.0), 0.0);
+}
+
+#[test]
+fn values_match_evaluator() {
+    let mut rng = Rng(0xd1ff);
+    let grid = [-0.75, -0.25, 0.0, 0.5, 0.875];
+    for _ in 0..2000 {
+        let source = random_source(&mut rng);
+        let expr = parser::parse_source(source.clone());
+        for x in grid {
+            for y in grid {
+                let d = eval_derivatives(&expr, x, y, &mut ());
+                let value = evaluator::eval_expr(&expr, x, y);
+                assert!(
+                    d.value == value,
+                    "{source:?} ({expr}) at ({x}, {y}): {:?} != {value:?}",
+                    d.value
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn deeply_nested_derivatives_render_quickly() {
+    let expr = parser::parse_source("dx(dx(dx(dx(dx(dx(dx(sin(x*y*9.0)+r)))))))");
+    let capped = parser::parse_source("dx(dx(dx(((((sin(x*y*9.0)+r)))))))");
+    assert_eq!(expr.to_string(), capped.to_string());
+
+    let start = Instant::now();
+    crate::renderer::render(&expr, 64, 64);
+    assert!(start.elapsed() < Duration::from_secs(5), "{expr}");
+}
@@ -2,8 +2,8 @@ mod tests;
 
 use super::{
     ast::{
-        AbsExpr, BinExpr, BinOp, ColorExpr, CosExpr, Expr, ExprKind, IfExpr, NegExpr, ParenExpr,
-        SinExpr, TransXExpr, TransYExpr,
+        AbsExpr, BinExpr, BinOp, ColorExpr, CosExpr, DxExpr, DyExpr, Expr, ExprKind, IfExpr,
+        NegExpr, NormalExpr, ParenExpr, ShadeExpr, SinExpr, TransXExpr, TransYExpr, ValueType,
     },
     evaluator,
 };
@@ -15,21 +15,38 @@ fn evaluate_constant_expr(expr: &Expr) -> Expr {
         span: expr.span.clone(),
     };
     match result {
-        evaluator::Result::Color(c) => expr(ExprKind::Color(ColorExpr::new(
-            expr(ExprKind::Number(c.r)),
-            expr(ExprKind::Number(c.g)),
-            expr(ExprKind::Number(c.b)),
-        ))),
+        evaluator::Result::Color(c) => {
+            let [r, g, b] =
+                ColorExpr::channel_order([c.r, c.g, c.b]).map(|n| expr(ExprKind::Number(n)));
+            expr(ExprKind::Color(ColorExpr::new(r, g, b)))
+        }
         evaluator::Result::Number(n) => expr(ExprKind::Number(n)),
-        evaluator::Result::Bool(_) => expr(ExprKind::Number(result.as_number())),
+        // Booleans can not be written as literals, so they are folded into a constant comparison.
+        evaluator::Result::Bool(b) => {
+            let (l, r) = match b {
+                true => (0.0, 1.0),
+                false => (1.0, 0.0),
+            };
+            expr(ExprKind::Bin(BinExpr::new(
+                BinOp::LessThan,
+                expr(ExprKind::Number(l)),
+                expr(ExprKind::Number(r)),
+            )))
+        }
     }
 }
 
+/// Evaluate every constant subexpression of an expression.
 pub fn evaluate_constants(expr: Expr) -> Expr {
+    expr.transform_up(evaluate_constant_node)
+}
+
+/// Evaluate the constant parts of an expression whose children have already been evaluated.
+fn evaluate_constant_node(expr: Expr) -> Expr {
     match expr.kind {
         ExprKind::Bin(e) => {
-            let mut lhs = evaluate_constants(*e.lhs);
-            let mut rhs = evaluate_constants(*e.rhs);
+            let mut lhs = *e.lhs;
+            let mut rhs = *e.rhs;
 
             let mut l_const = false;
             let mut r_const = false;
@@ -52,29 +69,39 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
                 return evaluate_constant_expr(&expr(ExprKind::Bin(BinExpr::new(e.op, lhs, rhs))));
             }
 
-            // Handle other constant cases
-            match (&lhs.kind, &rhs.kind, &e.op) {
-                (l, r, BinOp::Mul) if l.is_zero() || r.is_zero() => expr(ExprKind::Number(0.0)),
-                (l, _, BinOp::Mul) if l.is_one() => rhs,
-                (_, r, BinOp::Mul) if r.is_one() => lhs,
+            use ValueType::{Bool, Color, Number};
+            let is =
+                |e: &Expr, types: &[ValueType]| e.value_type().is_some_and(|t| types.contains(&t));
+            let positive_zero = |n: &f64| *n == 0.0 && n.is_sign_positive();
 
-                (l, r, BinOp::Div) if l.is_zero() || r.is_zero() => expr(ExprKind::Number(0.0)),
-                (_, r, BinOp::Div) if r.is_one() => lhs,
-                (l, r, BinOp::Div) if r == l => expr(ExprKind::Number(1.0)),
+            // Handle other constant cases. The result has to be exactly the same for every value of
+            // the other operand, including `-0.0`, booleans and colors.
+            match (&lhs.kind, &rhs.kind, &e.op) {
+                (l, _, BinOp::Mul) if l.is_one() && is(&rhs, &[Number, Color]) => rhs,
+                (_, r, BinOp::Mul) if r.is_one() && is(&lhs, &[Number, Color]) => lhs,
 
-                (l, r, BinOp::Mod) if l.is_zero() || r.is_zero() => expr(ExprKind::Number(0.0)),
-                (l, r, BinOp::Mod) if r == l => expr(ExprKind::Number(0.0)),
+                (_, r, BinOp::Div) if r.is_one() && is(&lhs, &[Number, Color]) => lhs,
 
-                (l, _, BinOp::Pow) if l.is_zero() => expr(ExprKind::Number(0.0)),
-                (l, _, BinOp::Pow) if l.is_one() => expr(ExprKind::Number(1.0)),
-                (_, r, BinOp::Pow) if r.is_one() => lhs,
-                (_, r, BinOp::Pow) if r.is_zero() => expr(ExprKind::Number(1.0)),
+                (_, ExprKind::Number(r), BinOp::Mod) if *r == 0.0 && is(&lhs, &[Number]) => {
+                    expr(ExprKind::Number(0.0))
+                }
+                (ExprKind::Number(l), _, BinOp::Mod) if positive_zero(l) && is(&rhs, &[Number]) => {
+                    expr(ExprKind::Number(0.0))
+                }
 
-                (l, _, BinOp::Add) if l.is_zero() => rhs,
-                (_, r, BinOp::Add) if r.is_zero() => lhs,
+                (l, _, BinOp::Pow) if l.is_one() && is(&rhs, &[Number, Bool]) => {
+                    expr(ExprKind::Number(1.0))
+                }
+                (_, r, BinOp::Pow) if r.is_one() && is(&lhs, &[Number, Color]) => lhs,
+                (_, ExprKind::Number(r), BinOp::Pow) if *r == 0.0 && is(&lhs, &[Number, Bool]) => {
+                    expr(ExprKind::Number(1.0))
+                }
 
-                (l, _, BinOp::Sub) if l.is_zero() => expr(ExprKind::Neg(NegExpr::new(rhs))),
-                (_, r, BinOp::Sub) if r.is_zero() => lhs,
+                (_, ExprKind::Number(r), BinOp::Sub)
+                    if positive_zero(r) && is(&lhs, &[Number, Color]) =>
+                {
+                    lhs
+                }
 
                 (_, _, BinOp::Mul)
                 | (_, _, BinOp::Div)
@@ -89,9 +116,9 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::If(e) => {
-            let cond = evaluate_constants(*e.cond);
-            let true_expr = evaluate_constants(*e.true_expr);
-            let false_expr = evaluate_constants(*e.false_expr);
+            let cond = *e.cond;
+            let true_expr = *e.true_expr;
+            let false_expr = *e.false_expr;
 
             if cond.is_constant() {
                 match evaluator::eval_expr(&cond, 0.0, 0.0).as_bool() {
@@ -106,15 +133,15 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::TransX(e) => {
-            let trans = evaluate_constants(*e.trans);
-            let inner = evaluate_constants(*e.inner);
+            let trans = *e.trans;
+            let inner = *e.inner;
 
             match (&trans.kind, &inner.kind) {
-                (t, _) if t.is_zero() => return inner,
                 (_, i) if i.is_constant() => return inner,
+                // The inner expression is evaluated at `x - x = 0`.
                 (ExprKind::X, ExprKind::X) => {
                     return Expr {
-                        kind: ExprKind::Number(0.5),
+                        kind: ExprKind::Number(0.0),
                         span: expr.span,
                     }
                 }
@@ -132,15 +159,15 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::TransY(e) => {
-            let trans = evaluate_constants(*e.trans);
-            let inner = evaluate_constants(*e.inner);
+            let trans = *e.trans;
+            let inner = *e.inner;
 
             match (&trans.kind, &inner.kind) {
-                (t, _) if t.is_zero() => return inner,
                 (_, i) if i.is_constant() => return inner,
-                (ExprKind::Y, ExprKind::A) | (ExprKind::Y, ExprKind::Y) => {
+                // The inner expression is evaluated at `y - y = 0`.
+                (ExprKind::Y, ExprKind::Y) => {
                     return Expr {
-                        kind: ExprKind::Number(0.5),
+                        kind: ExprKind::Number(0.0),
                         span: expr.span,
                     }
                 }
@@ -158,7 +185,7 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::Neg(NegExpr { inner }) => {
-            let inner = evaluate_constants(*inner);
+            let inner = *inner;
             let is_const = inner.is_constant();
             let expr = Expr {
                 kind: ExprKind::Neg(NegExpr::new(inner)),
@@ -170,7 +197,7 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::Abs(AbsExpr { inner }) => {
-            let inner = evaluate_constants(*inner);
+            let inner = *inner;
             let is_const = inner.is_constant();
             let expr = Expr {
                 kind: ExprKind::Abs(AbsExpr::new(inner)),
@@ -182,7 +209,7 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::Sin(SinExpr { inner }) => {
-            let inner = evaluate_constants(*inner);
+            let inner = *inner;
             let is_const = inner.is_constant();
             let expr = Expr {
                 kind: ExprKind::Sin(SinExpr::new(inner)),
@@ -194,7 +221,7 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::Cos(CosExpr { inner }) => {
-            let inner = evaluate_constants(*inner);
+            let inner = *inner;
             let is_const = inner.is_constant();
             let expr = Expr {
                 kind: ExprKind::Cos(CosExpr::new(inner)),
@@ -205,8 +232,62 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
                 false => expr,
             }
         }
+        ExprKind::Dx(DxExpr { inner }) => {
+            let inner = *inner;
+            let is_const = inner.is_constant();
+            let expr = Expr {
+                kind: ExprKind::Dx(DxExpr::new(inner)),
+                span: expr.span,
+            };
+            match is_const {
+                true => evaluate_constant_expr(&expr),
+                false => expr,
+            }
+        }
+        ExprKind::Dy(DyExpr { inner }) => {
+            let inner = *inner;
+            let is_const = inner.is_constant();
+            let expr = Expr {
+                kind: ExprKind::Dy(DyExpr::new(inner)),
+                span: expr.span,
+            };
+            match is_const {
+                true => evaluate_constant_expr(&expr),
+                false => expr,
+            }
+        }
+        ExprKind::Normal(NormalExpr { inner }) => {
+            let inner = *inner;
+            let is_const = inner.is_constant();
+            let expr = Expr {
+                kind: ExprKind::Normal(NormalExpr::new(inner)),
+                span: expr.span,
+            };
+            match is_const {
+                true => evaluate_constant_expr(&expr),
+                false => expr,
+            }
+        }
+        ExprKind::Shade(e) => {
+            let height = *e.height;
+            let light = *e.light;
+            let elevation = e.elevation.map(|elevation| *elevation);
+            let is_const = height.is_constant()
+                && light.is_constant()
+                && elevation.as_ref().is_none_or(Expr::is_constant);
+            let expr = Expr {
+                kind: ExprKind::Shade(ShadeExpr::new(height, light, elevation)),
+                span: expr.span,
+            };
+            match is_const {
+                true => evaluate_constant_expr(&expr),
+                false => expr,
+            }
+        }
+        // Textures are bound after parsing, so sampling them is never constant.
+        ExprKind::Tex(_) => expr,
         ExprKind::Paren(ParenExpr { inner }) => {
-            let inner = evaluate_constants(*inner);
+            let inner = *inner;
             let is_const = inner.is_constant();
             let expr = Expr {
                 kind: ExprKind::Paren(ParenExpr::new(inner)),
@@ -218,9 +299,9 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
             }
         }
         ExprKind::Color(c) => {
-            let mut r = evaluate_constants(*c.r);
-            let mut g = evaluate_constants(*c.g);
-            let mut b = evaluate_constants(*c.b);
+            let mut r = *c.r;
+            let mut g = *c.g;
+            let mut b = *c.b;
             if r.is_constant() {
                 r = evaluate_constant_expr(&r);
             }
@@ -240,5 +321,6 @@ pub fn evaluate_constants(expr: Expr) -> Expr {
         ExprKind::Y => expr,
         ExprKind::R => expr,
         ExprKind::A => expr,
+        ExprKind::Pixel(_) => expr,
     }
 }
@@ -13,29 +13,29 @@ macro_rules! test_syntax {
 }
 
 // Multiplication
-test_syntax!( mul_zero_left:  "x * 0.0"   => "0.0" );
-test_syntax!( mul_zero_right: "0.0 * x"   => "0.0" );
+test_syntax!( mul_zero_left:  "x * 0.0"   => "x * 0.0" );
+test_syntax!( mul_zero_right: "0.0 * x"   => "0.0 * x" );
 test_syntax!( mul_one_left:   "x * 1.0"   => "x"   );
 test_syntax!( mul_one_right:  "1.0 * x"   => "x"   );
 test_syntax!( mul_num:        "2.0 * 2.0" => "4.0" );
 
 // Division
-test_syntax!( div_zero_left:  "x / 0.0"     => "0.0" );
-test_syntax!( div_zero_right: "0.0 / x"     => "0.0" );
+test_syntax!( div_zero_left:  "x / 0.0"     => "x / 0.0" );
+test_syntax!( div_zero_right: "0.0 / x"     => "0.0 / x" );
 test_syntax!( div_one_left:   "x / 1.0"     => "x"   );
-test_syntax!( div_equal_x:    "x / x"       => "1.0" );
-test_syntax!( div_equal_y:    "y / y"       => "1.0" );
+test_syntax!( div_equal_x:    "x / x"       => "x / x" );
+test_syntax!( div_equal_y:    "y / y"       => "y / y" );
 test_syntax!( div_equal_num:  "10.0 / 10.0" => "1.0" );
 test_syntax!( div_num:        "10.0 / 2.0"  => "5.0" );
 
 // Addition
-test_syntax!( add_zero_left:  "x + 0.0"   => "x"   );
-test_syntax!( add_zero_right: "0.0 + x"   => "x"   );
+test_syntax!( add_zero_left:  "x + 0.0"   => "x + 0.0" );
+test_syntax!( add_zero_right: "0.0 + x"   => "0.0 + x" );
 test_syntax!( add_num:        "2.0 + 2.0" => "4.0" );
 
 // Subtraction
 test_syntax!( sub_zero_left:  "x - 0.0"   => "x"   );
-test_syntax!( sub_zero_right: "0.0 - x"   => "-x"  );
+test_syntax!( sub_zero_right: "0.0 - x"   => "0.0 - x" );
 
 // Modulo
 test_syntax!( mod_zero_left:  "x % 0.0"     => "0.0" );
@@ -43,7 +43,7 @@ test_syntax!( mod_zero_right: "0.0 % x"     => "0.0" );
 test_syntax!( mod_num:        "10.0 % 3.0"  => "1.0" );
 
 // Power
-test_syntax!( pow_zero_left:  "0.0 ^ x"   => "0.0" );
+test_syntax!( pow_zero_left:  "0.0 ^ x"   => "0.0 ^ x" );
 test_syntax!( pow_zero_right: "x ^ 0.0"   => "1.0" );
 test_syntax!( pow_one_left:   "1.0 ^ x"   => "1.0" );
 test_syntax!( pow_one_right:  "x ^ 1.0"   => "x"   );
@@ -53,6 +53,15 @@ test_syntax!( pow_num:        "2.0 ^ 2.0" => "4.0" );
 test_syntax!( sin: "sin(0.0)" => "0.0" );
 test_syntax!( cos: "cos(0.0)" => "1.0" );
 
+// Derivatives
+test_syntax!( dx_const: "dx(2.0 * 3.0)"         => "0.0"             );
+test_syntax!( dy_const: "dy({1.0, 2.0, 3.0})"   => "{0.0, 0.0, 0.0}" );
+test_syntax!( dx_x:     "dx(x)"                 => "dx(x)"           );
+
+// Lighting
+test_syntax!( normal_const: "normal(2.0)"       => "{0.0, 1.0, 0.0}" );
+test_syntax!( shade_x:      "shade(x, 0.0)"     => "shade(x, 0.0)"   );
+
 // Parentheses
 test_syntax!( paren:     "(1.0)"     => "1.0" );
 test_syntax!( paren_bin: "(1.0/2.0)" => "0.5" );
@@ -62,16 +71,21 @@ test_syntax!( abs_pos: "|1.0|"  => "1.0" );
 test_syntax!( abs_neg: "|-1.0|" => "1.0" );
 
 // Boolean Operations (less, greater, or/max, and/min)
-test_syntax!( less_true:     "1.0 < 2.0" => "1.0"  );
-test_syntax!( less_false:    "2.0 < 1.0" => "-1.0" );
-test_syntax!( greater_true:  "2.0 > 1.0" => "1.0"  );
-test_syntax!( greater_false: "1.0 > 2.0" => "-1.0" );
+test_syntax!( less_true:     "1.0 < 2.0" => "0.0 < 1.0");
+test_syntax!( less_false:    "2.0 < 1.0" => "1.0 < 0.0");
+test_syntax!( greater_true:  "2.0 > 1.0" => "0.0 < 1.0");
+test_syntax!( greater_false: "1.0 > 2.0" => "1.0 < 0.0");
 test_syntax!( or:            "1.0 | 0.0" => "1.0"  );
 test_syntax!( and:           "1.0 & 0.0" => "0.0"  );
 
+// Colors
+test_syntax!( color_num: "{1.0, 2.0, 3.0} * 2.0" => "{2.0, 4.0, 6.0}" );
+
 // Translate Operatoins
-test_syntax!( trans_x_zero:       "tx(0.0, x)" => "x"   );
-test_syntax!( trans_y_zero:       "ty(0.0, y)" => "y"   );
+test_syntax!( trans_x_zero:       "tx(0.0, x)" => "tx(0.0, x)" );
+test_syntax!( trans_y_zero:       "ty(0.0, y)" => "ty(0.0, y)" );
 test_syntax!( trans_x_zero_inner: "tx(x, 0.0)" => "0.0" );
 test_syntax!( trans_y_zero_inner: "ty(y, 0.0)" => "0.0" );
-test_syntax!( trans_x_x:          "tx(x, x)"   => "0.5" );
+test_syntax!( trans_x_x:          "tx(x, x)"   => "0.0" );
+test_syntax!( trans_y_y:          "ty(y, y)"   => "0.0" );
+test_syntax!( trans_y_a:          "ty(y, a)"   => "ty(y, a)" );
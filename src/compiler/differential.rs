@@ -0,0 +1,157 @@
+//! Differential tests checking that optimizations do not change the rendered image. Random
+//! source strings are parsed, optimized and evaluated on a grid of points, and the optimized
+//! expression must agree with the original everywhere.
+
+use super::{ast::Expr, constant_evaluator, evaluator, parser, simplifier};
+
+/// Number of random programs to check per optimization.
+const CASES: usize = 4000;
+
+/// Number of grid points along each axis. Points are dyadic fractions strictly inside (-1, 1), so
+/// translations by dyadic offsets are exact.
+const GRID: usize = 15;
+
+/// Building blocks for random source strings.
+const VOCABULARY: &[&str] = &[
+    "x", "y", "r", "a", "0.0", "1.0", "2.0", "0.5", "0.25", "3.7", "+", "-", "*", "/", "%", "^",
+    "<", ">", "|", "&", "(", ")", "{", "}", ",", " ", "sin(", "cos(", "tx(", "ty(", "if ",
+    " then ", " else ", " end", "dx(", "dy(", "q",
+];
+
+/// Small deterministic random number generator (xorshift64).
+pub(super) struct Rng(pub(super) u64);
+
+impl Rng {
+    pub(super) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub(super) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+pub(super) fn random_source(rng: &mut Rng) -> String {
+    let len = 1 + rng.below(30);
+    (0..len)
+        .map(|_| VOCABULARY[rng.below(VOCABULARY.len())])
+        .collect()
+}
+
+/// A point where the original and optimized expressions disagree.
+struct Mismatch {
+    point: (f64, f64),
+    original: evaluator::Color,
+    optimized: evaluator::Color,
+}
+
+fn same_channel(a: f64, b: f64) -> bool {
+    a == b || (a.is_nan() && b.is_nan()) || (a - b).abs() <= 1e-9
+}
+
+fn same_color(a: &evaluator::Color, b: &evaluator::Color) -> bool {
+    same_channel(a.r, b.r) && same_channel(a.g, b.g) && same_channel(a.b, b.b)
+}
+
+/// Find the first grid point where the expressions render different colors.
+fn compare(original: &Expr, optimized: &Expr) -> Option<Mismatch> {
+    let coord = |i: usize| (i + 1) as f64 / (GRID + 1) as f64 * 2.0 - 1.0;
+    for i in 0..GRID {
+        for j in 0..GRID {
+            let (x, y) = (coord(i), coord(j));
+            let a = evaluator::eval(original, x, y);
+            let b = evaluator::eval(optimized, x, y);
+            if !same_color(&a, &b) {
+                return Some(Mismatch {
+                    point: (x, y),
+                    original: a,
+                    optimized: b,
+                });
+            }
+        }
+    }
+    None
+}
+
+fn check(source: &str, optimize: fn(Expr) -> Expr) -> Option<Mismatch> {
+    let original = parser::parse_source(source.to_string());
+    let optimized = optimize(original.clone());
+    compare(&original, &optimized)
+}
+
+/// Shrink a failing source by removing characters for as long as it keeps failing.
+fn shrink(mut source: String, optimize: fn(Expr) -> Expr) -> String {
+    let mut shrunk = true;
+    while shrunk {
+        shrunk = false;
+        for len in [8, 4, 2, 1] {
+            let mut start = 0;
+            while start + len <= source.len() {
+                let mut candidate = source.clone();
+                candidate.replace_range(start..start + len, "");
+                if check(&candidate, optimize).is_some() {
+                    source = candidate;
+                    shrunk = true;
+                } else {
+                    start += 1;
+                }
+            }
+        }
+    }
+    source
+}
+
+/// Check random programs and panic with a minimal counterexample if any disagree.
+fn differential_test(seed: u64, source: fn(&mut Rng) -> String, optimize: fn(Expr) -> Expr) {
+    let mut rng = Rng(seed);
+    for _ in 0..CASES {
+        let source = source(&mut rng);
+        if check(&source, optimize).is_none() {
+            continue;
+        }
+
+        let source = shrink(source, optimize);
+        let original = parser::parse_source(source.clone());
+        let optimized = optimize(original.clone());
+        let m = compare(&original, &optimized).unwrap();
+        panic!(
+            "Optimization changed the output of {source:?}\n\
+             original:  {original}\n\
+             optimized: {optimized}\n\
+             at {:?}: {:?} != {:?}",
+            m.point, m.original, m.optimized
+        );
+    }
+}
+
+#[test]
+fn constant_evaluator_preserves_output() {
+    differential_test(
+        0x5eed,
+        random_source,
+        constant_evaluator::evaluate_constants,
+    );
+}
+
+#[test]
+fn simplifier_preserves_output() {
+    differential_test(0xc0ffee, random_source, simplifier::simplify);
+}
+
+/// A random program inside two translations along the same axis, with offsets that sum to the
+/// edges of the image and past them.
+fn nested_translation(rng: &mut Rng) -> String {
+    const OFFSETS: &[&str] = &["0.0", "0.25", "0.5", "0.75", "1.0", "3.7", "-0.25", "-0.75"];
+    let trans = ["tx(", "ty("][rng.below(2)];
+    let a = OFFSETS[rng.below(OFFSETS.len())];
+    let b = OFFSETS[rng.below(OFFSETS.len())];
+    format!("{trans}{a}, {trans}{b}, {}))", random_source(rng))
+}
+
+#[test]
+fn simplifier_preserves_nested_translations() {
+    differential_test(0x7a7a, nested_translation, simplifier::simplify);
+}
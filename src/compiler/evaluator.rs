@@ -4,7 +4,11 @@ use std::{
     ops::{Add, Div, Mul, Sub},
 };
 
-use crate::compiler::ast::{BinOp, Expr, ExprKind};
+use crate::compiler::{
+    ast::{AbsExpr, BinOp, ColorExpr, CosExpr, Expr, ExprKind, IfExpr, NegExpr, SinExpr},
+    autodiff, lighting,
+    version::LanguageVersion,
+};
 
 /// Result of evaluating an expression.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -15,7 +19,7 @@ pub(crate) enum Result {
 }
 
 /// Cast an SSL boolean to a float.
-fn bool_to_f64(b: bool) -> f64 {
+pub(crate) fn bool_to_f64(b: bool) -> f64 {
     match b {
         true => 1.0,
         false => -1.0,
@@ -84,7 +88,7 @@ impl Result {
     }
 
     /// Convert NaN values to zero.
-    fn nan_to_zero(&mut self) {
+    pub(crate) fn nan_to_zero(&mut self) {
         fn zero_if_nan(n: &mut f64) {
             if n.is_nan() {
                 *n = 0.0;
@@ -103,7 +107,7 @@ impl Result {
     }
 
     /// Call a function on the result.
-    fn call(&mut self, f: impl Fn(f64) -> f64) -> Result {
+    pub(crate) fn call(&mut self, f: impl Fn(f64) -> f64) -> Result {
         match self {
             Result::Color(c) => color!(f(c.r), f(c.g), f(c.b)),
             Result::Number(n) => number!(f(*n)),
@@ -115,8 +119,11 @@ impl Result {
 /// An RGB color.
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Color {
+    /// Red channel
     pub r: f64,
+    /// Green channel
     pub g: f64,
+    /// Blue channel
     pub b: f64,
 }
 
@@ -126,13 +133,18 @@ fn sigmoid(x: f64) -> f64 {
 }
 
 /// Normalize a value to the range [-1, 1].
-fn norm(x: f64) -> f64 {
+pub(crate) fn norm(x: f64) -> f64 {
     sigmoid(x % 50.0)
 }
 
 impl Color {
-    /// Clamp the color values to the range [0, 1].
-    fn clamp(&mut self) {
+    /// Clamp the color values to the range [0, 1] the way a version of the language does.
+    fn clamp(&mut self, version: LanguageVersion) {
+        // Infinite channels have no remainder. They become black, like the renderer draws NaN.
+        let norm = |x: f64| match version.normalize(x) {
+            n if n.is_nan() => 0.0,
+            n => n,
+        };
         self.r = norm(self.r);
         self.g = norm(self.g);
         self.b = norm(self.b);
@@ -183,7 +195,7 @@ impl_trait_op!(Result, Div, div);
 macro_rules! impl_float_op {
     ($struct:ident, $name:ident, $sym:tt) => {
         impl $struct {
-            fn $name(self, other: Self) -> Self {
+            pub(crate) fn $name(self, other: Self) -> Self {
                 match (self, other) {
                     (Result::Color(c1), Result::Color(c2)) => {
                         color!(c1.r $sym c2.r, c1.g $sym c2.g, c1.b $sym c2.b)
@@ -220,7 +232,7 @@ impl_float_op!(Result, fmod, %);
 macro_rules! impl_bin_op {
     ($struct:ident, $name:ident, $sym:tt) => {
         impl $struct {
-            fn $name(self, other: Self) -> Self {
+            pub(crate) fn $name(self, other: Self) -> Self {
                 match (self, other) {
                     (Result::Color(c1), Result::Color(c2)) => {
                         let r = bool_to_f64(c1.r $sym c2.r);
@@ -272,7 +284,7 @@ impl_bin_op!(Result, greater, >);
 
 impl Result {
     /// Raise the result to a power.
-    fn pow(&self, other: Self) -> Self {
+    pub(crate) fn pow(&self, other: Self) -> Self {
         match (self, other) {
             (Result::Color(c1), Result::Color(c2)) => {
                 color!(c1.r.powf(c2.r), c1.g.powf(c2.g), c1.b.powf(c2.b))
@@ -299,7 +311,7 @@ impl Result {
     }
 
     /// The absolute value.
-    fn abs(&self) -> Self {
+    pub(crate) fn abs(&self) -> Self {
         match self {
             Result::Color(c) => {
                 color!(c.r.abs(), c.g.abs(), c.b.abs())
@@ -310,7 +322,7 @@ impl Result {
     }
 
     /// The OR operation.
-    fn or(self, other: Self) -> Self {
+    pub(crate) fn or(self, other: Self) -> Self {
         if let (Self::Bool(b1), Self::Bool(b2)) = (&self, &other) {
             return bool!(*b1 || *b2);
         }
@@ -322,7 +334,7 @@ impl Result {
     }
 
     // The AND operation.
-    fn and(self, other: Self) -> Self {
+    pub(crate) fn and(self, other: Self) -> Self {
         if let (Self::Bool(b1), Self::Bool(b2)) = (&self, &other) {
             return bool!(*b1 && *b2);
         }
@@ -334,72 +346,290 @@ impl Result {
     }
 }
 
-fn wrap(x: f64) -> f64 {
+pub(crate) fn wrap(x: f64) -> f64 {
     match x.is_sign_positive() {
         true => (x + 1.0) % 2.0 - 1.0,
         false => (x - 1.0) % 2.0 + 1.0,
     }
 }
 
+/// Depth up to which expressions are evaluated recursively. Deeper subexpressions are evaluated
+/// with explicit stacks, which is slower but not limited by the size of the call stack.
+const MAX_RECURSION: usize = 128;
+
+/// Receives events while an expression is evaluated, to collect statistics about the evaluation.
+/// The evaluator is generic over the observer, so the empty methods of `()` cost nothing.
+pub trait Observer {
+    /// Called for every expression that is evaluated. Derivatives and lighting report every
+    /// evaluation of their operands, with dual numbers and at each point of finite differences.
+    fn visit(&mut self, _expr: &Expr) {}
+
+    /// Called when a conditional picks a branch.
+    fn branch(&mut self, _expr: &IfExpr, _taken: bool) {}
+}
+
+impl Observer for () {}
+
 /// Evaluate an expression at a point.
 pub(crate) fn eval_expr(expr: &Expr, x: f64, y: f64) -> Result {
+    eval_expr_observed(expr, x, y, &mut ())
+}
+
+/// Evaluate an expression at a point like [eval_expr], reporting the evaluation to an observer.
+pub(crate) fn eval_expr_observed<O: Observer>(expr: &Expr, x: f64, y: f64, obs: &mut O) -> Result {
+    eval_recursive(expr, x, y, 0, obs)
+}
+
+/// Evaluate an expression at a point recursively, falling back to [eval_iterative] once the
+/// recursion gets too deep.
+fn eval_recursive<O: Observer>(expr: &Expr, x: f64, y: f64, depth: usize, obs: &mut O) -> Result {
+    if depth >= MAX_RECURSION {
+        return eval_iterative(expr, x, y, obs);
+    }
+    obs.visit(expr);
+    macro_rules! eval {
+        ($expr:expr, $x:expr, $y:expr) => {
+            eval_recursive($expr, $x, $y, depth + 1, obs)
+        };
+    }
+
     let mut res = match &expr.kind {
         ExprKind::Bin(e) => {
-            let l = eval_expr(&e.lhs, x, y);
-            let r = eval_expr(&e.rhs, x, y);
-            match e.op {
-                BinOp::Add => l + r,
-                BinOp::Sub => l - r,
-                BinOp::Mul => l * r,
-                BinOp::Div => l / r,
-                BinOp::Mod => l.fmod(r),
-                BinOp::Pow => l.pow(r),
-                BinOp::Or => l.or(r),
-                BinOp::And => l.and(r),
-                BinOp::LessThan => l.less(r),
-                BinOp::GreaterThan => l.greater(r),
-            }
+            let l = eval!(&e.lhs, x, y);
+            let r = eval!(&e.rhs, x, y);
+            apply_bin_op(&e.op, l, r)
         }
         ExprKind::Color(c) => {
-            let r = eval_expr(&c.r, x, y);
-            let b = eval_expr(&c.g, x, y);
-            let g = eval_expr(&c.b, x, y);
+            let r = eval!(&c.r, x, y);
+            let b = eval!(&c.g, x, y);
+            let g = eval!(&c.b, x, y);
             color!(r.as_number(), g.as_number(), b.as_number())
         }
-        ExprKind::Paren(e) => eval_expr(&e.inner, x, y),
-        ExprKind::Neg(e) => eval_expr(&e.inner, x, y) * number!(-1.0),
-        ExprKind::Abs(e) => eval_expr(&e.inner, x, y).abs(),
-        ExprKind::Number(n) => number!(*n),
+        ExprKind::Paren(e) => eval!(&e.inner, x, y),
+        ExprKind::Neg(e) => eval!(&e.inner, x, y) * number!(-1.0),
+        ExprKind::Abs(e) => eval!(&e.inner, x, y).abs(),
         ExprKind::TransX(e) => {
-            let offset = eval_expr(&e.trans, x, y).as_number();
-            eval_expr(&e.inner, wrap(x - offset), y)
+            let offset = eval!(&e.trans, x, y).as_number();
+            eval!(&e.inner, wrap(x - offset), y)
         }
         ExprKind::TransY(e) => {
-            let offset = eval_expr(&e.trans, x, y).as_number();
-            eval_expr(&e.inner, x, wrap(y - offset))
+            let offset = eval!(&e.trans, x, y).as_number();
+            eval!(&e.inner, x, wrap(y - offset))
         }
-        ExprKind::X => number!(x),
-        ExprKind::Y => number!(y),
-        ExprKind::R => number!(f64::sqrt(x * x + y * y)),
-        ExprKind::A => number!(f64::atan(y / x) / PI),
         ExprKind::If(e) => {
-            let cond = eval_expr(&e.cond, x, y);
-            if cond.as_bool() {
-                eval_expr(&e.true_expr, x, y)
+            let cond = eval!(&e.cond, x, y).as_bool();
+            obs.branch(e, cond);
+            if cond {
+                eval!(&e.true_expr, x, y)
             } else {
-                eval_expr(&e.false_expr, x, y)
+                eval!(&e.false_expr, x, y)
             }
         }
-        ExprKind::Sin(e) => eval_expr(&e.inner, x, y).call(f64::sin),
-        ExprKind::Cos(e) => eval_expr(&e.inner, x, y).call(f64::cos),
+        ExprKind::Sin(e) => eval!(&e.inner, x, y).call(f64::sin),
+        ExprKind::Cos(e) => eval!(&e.inner, x, y).call(f64::cos),
+        ExprKind::Tex(e) => {
+            let n = eval!(&e.index, x, y).as_number();
+            let u = eval!(&e.u, x, y).as_number();
+            let v = eval!(&e.v, x, y).as_number();
+            Result::Color(e.textures.sample(n, u, v))
+        }
+        _ => eval_leaf(expr, x, y, obs),
     };
     res.nan_to_zero();
     res
 }
 
+/// Evaluate an expression that has no operands evaluated by the evaluator itself.
+fn eval_leaf<O: Observer>(expr: &Expr, x: f64, y: f64, obs: &mut O) -> Result {
+    match &expr.kind {
+        ExprKind::Number(n) => number!(*n),
+        ExprKind::X => number!(x),
+        ExprKind::Y => number!(y),
+        ExprKind::R => number!(f64::sqrt(x * x + y * y)),
+        ExprKind::A => number!(f64::atan(y / x) / PI),
+        ExprKind::Dx(e) => autodiff::eval_derivatives(&e.inner, x, y, obs).dx,
+        ExprKind::Dy(e) => autodiff::eval_derivatives(&e.inner, x, y, obs).dy,
+        ExprKind::Normal(e) => lighting::normal(&e.inner, x, y, obs),
+        ExprKind::Shade(e) => {
+            lighting::shade(&e.height, &e.light, e.elevation.as_deref(), x, y, obs)
+        }
+        ExprKind::Pixel(e) => Result::Color(e.source.sample(0.0, x, y)),
+        _ => unreachable!("expression has operands"),
+    }
+}
+
+/// Apply a binary operator to the results of its operands.
+fn apply_bin_op(op: &BinOp, l: Result, r: Result) -> Result {
+    match op {
+        BinOp::Add => l + r,
+        BinOp::Sub => l - r,
+        BinOp::Mul => l * r,
+        BinOp::Div => l / r,
+        BinOp::Mod => l.fmod(r),
+        BinOp::Pow => l.pow(r),
+        BinOp::Or => l.or(r),
+        BinOp::And => l.and(r),
+        BinOp::LessThan => l.less(r),
+        BinOp::GreaterThan => l.greater(r),
+    }
+}
+
+/// A step of the evaluation of an expression.
+enum Task<'a> {
+    /// Evaluate an expression and push its result.
+    Eval(&'a Expr, f64, f64),
+    /// Pop the results of the children of an expression and push the result of the expression.
+    Apply(&'a Expr),
+    /// Pop the condition and evaluate one of the branches.
+    Select(&'a IfExpr, f64, f64),
+    /// Pop the offset and evaluate the inner expression at the translated x coordinate.
+    TransX(&'a Expr, f64, f64),
+    /// Pop the offset and evaluate the inner expression at the translated y coordinate.
+    TransY(&'a Expr, f64, f64),
+}
+
+/// Evaluate an expression at a point with explicit stacks instead of recursion, so the depth of
+/// the expression is not limited by the size of the call stack.
+fn eval_iterative<O: Observer>(expr: &Expr, x: f64, y: f64, obs: &mut O) -> Result {
+    let mut tasks = vec![Task::Eval(expr, x, y)];
+    let mut values: Vec<Result> = Vec::new();
+
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Eval(expr, x, y) => {
+                obs.visit(expr);
+                let res = match &expr.kind {
+                    ExprKind::Bin(e) => {
+                        tasks.push(Task::Apply(expr));
+                        tasks.push(Task::Eval(&e.rhs, x, y));
+                        tasks.push(Task::Eval(&e.lhs, x, y));
+                        continue;
+                    }
+                    ExprKind::Color(c) => {
+                        tasks.push(Task::Apply(expr));
+                        tasks.push(Task::Eval(&c.b, x, y));
+                        tasks.push(Task::Eval(&c.g, x, y));
+                        tasks.push(Task::Eval(&c.r, x, y));
+                        continue;
+                    }
+                    ExprKind::Tex(e) => {
+                        tasks.push(Task::Apply(expr));
+                        tasks.push(Task::Eval(&e.v, x, y));
+                        tasks.push(Task::Eval(&e.u, x, y));
+                        tasks.push(Task::Eval(&e.index, x, y));
+                        continue;
+                    }
+                    ExprKind::Neg(NegExpr { inner })
+                    | ExprKind::Abs(AbsExpr { inner })
+                    | ExprKind::Sin(SinExpr { inner })
+                    | ExprKind::Cos(CosExpr { inner }) => {
+                        tasks.push(Task::Apply(expr));
+                        tasks.push(Task::Eval(inner, x, y));
+                        continue;
+                    }
+                    // The result of the inner expression is already free of NaN.
+                    ExprKind::Paren(e) => {
+                        tasks.push(Task::Eval(&e.inner, x, y));
+                        continue;
+                    }
+                    ExprKind::If(e) => {
+                        tasks.push(Task::Select(e, x, y));
+                        tasks.push(Task::Eval(&e.cond, x, y));
+                        continue;
+                    }
+                    ExprKind::TransX(e) => {
+                        tasks.push(Task::TransX(&e.inner, x, y));
+                        tasks.push(Task::Eval(&e.trans, x, y));
+                        continue;
+                    }
+                    ExprKind::TransY(e) => {
+                        tasks.push(Task::TransY(&e.inner, x, y));
+                        tasks.push(Task::Eval(&e.trans, x, y));
+                        continue;
+                    }
+                    _ => eval_leaf(expr, x, y, obs),
+                };
+                push_result(&mut values, res);
+            }
+            Task::Apply(expr) => {
+                let mut pop = || values.pop().expect("missing operand");
+                let res = match &expr.kind {
+                    ExprKind::Bin(e) => {
+                        let r = pop();
+                        let l = pop();
+                        apply_bin_op(&e.op, l, r)
+                    }
+                    ExprKind::Color(_) => {
+                        let third = pop();
+                        let second = pop();
+                        let first = pop();
+                        let [r, g, b] = ColorExpr::channel_order([first, second, third]);
+                        color!(r.as_number(), g.as_number(), b.as_number())
+                    }
+                    ExprKind::Tex(e) => {
+                        let v = pop().as_number();
+                        let u = pop().as_number();
+                        let n = pop().as_number();
+                        Result::Color(e.textures.sample(n, u, v))
+                    }
+                    ExprKind::Neg(_) => pop() * number!(-1.0),
+                    ExprKind::Abs(_) => pop().abs(),
+                    ExprKind::Sin(_) => pop().call(f64::sin),
+                    ExprKind::Cos(_) => pop().call(f64::cos),
+                    _ => unreachable!("expression has no operands to apply"),
+                };
+                push_result(&mut values, res);
+            }
+            Task::Select(e, x, y) => {
+                let cond = values.pop().expect("missing condition").as_bool();
+                obs.branch(e, cond);
+                match cond {
+                    true => tasks.push(Task::Eval(&e.true_expr, x, y)),
+                    false => tasks.push(Task::Eval(&e.false_expr, x, y)),
+                }
+            }
+            Task::TransX(inner, x, y) => {
+                let offset = values.pop().expect("missing offset").as_number();
+                tasks.push(Task::Eval(inner, wrap(x - offset), y));
+            }
+            Task::TransY(inner, x, y) => {
+                let offset = values.pop().expect("missing offset").as_number();
+                tasks.push(Task::Eval(inner, x, wrap(y - offset)));
+            }
+        }
+    }
+
+    values.pop().expect("missing result")
+}
+
+/// Push the result of an expression, with NaN values turned into zero.
+fn push_result(values: &mut Vec<Result>, mut res: Result) {
+    res.nan_to_zero();
+    values.push(res);
+}
+
 /// Evaluate an expression at a given point and return the clamped color.
+#[allow(dead_code)] // TODO: Find a better solution
 pub fn eval(expr: &Expr, x: f64, y: f64) -> Color {
-    let mut res = eval_expr(expr, x, y).as_color();
-    res.clamp();
+    eval_with_version(expr, x, y, LanguageVersion::LATEST)
+}
+
+/// Evaluate an expression at a given point and return the color clamped by a version of the
+/// language.
+pub fn eval_with_version(expr: &Expr, x: f64, y: f64, version: LanguageVersion) -> Color {
+    eval_observed(expr, x, y, version, &mut ())
+}
+
+/// Evaluate an expression at a given point like [eval_with_version], reporting the evaluation to
+/// an observer.
+pub fn eval_observed<O: Observer>(
+    expr: &Expr,
+    x: f64,
+    y: f64,
+    version: LanguageVersion,
+    obs: &mut O,
+) -> Color {
+    let mut res = eval_recursive(expr, x, y, 0, obs).as_color();
+    res.clamp(version);
     res
 }
@@ -0,0 +1,359 @@
+//! Formatter that turns an expression back into SSL source code. Parsing the source again gives the
+//! same expression, so repaired random input can be saved as a program and edited.
+//!
+//! Parentheses are only written where the parser needs them. Besides precedence, two parts of the
+//! grammar need care:
+//! - A negation takes everything after it, so `-x + y` is `-(x + y)`. Negations are wrapped in
+//!   parentheses unless nothing follows them.
+//! - Inside `|...|`, a `|` ends the absolute value, so `or` has to be wrapped in parentheses.
+//!
+//! [format_source] also breaks lines that are too long. Chains of binary operators are broken
+//! before each operator, conditions are written as blocks, and function arguments are written on
+//! their own lines.
+
+#[cfg(test)]
+mod tests;
+
+use super::ast::{BinOp, Expr, ExprKind};
+
+/// Where an expression is written.
+#[derive(Debug, Clone, Copy)]
+struct Context {
+    /// Nothing follows the expression before the end of the enclosing delimiters.
+    tail: bool,
+    /// The expression is directly inside an absolute value.
+    in_abs: bool,
+}
+
+impl Context {
+    /// Context of an expression that is delimited on both sides, like a function argument.
+    const DELIMITED: Self = Self {
+        tail: true,
+        in_abs: false,
+    };
+}
+
+/// Default maximum line length of [format_source].
+#[allow(dead_code)] // TODO: Find a better solution
+pub const MAX_WIDTH: usize = 100;
+
+/// Number of spaces lines are indented by.
+const INDENT: usize = 4;
+
+/// Format an expression as SSL source code on a single line. The color of the source pixel is
+/// written as `p`, which only filters read back, so filters have to be parsed again with
+/// [parse_filter](super::parser::parse_filter).
+pub fn to_source(expr: &Expr) -> String {
+    let mut out = String::new();
+    write_expr(&mut out, expr, Context::DELIMITED);
+    out
+}
+
+/// Format an expression as the contents of a source file, breaking lines that are longer than
+/// `width` where possible.
+pub fn format_source(expr: &Expr, width: usize) -> String {
+    let mut layout = Layout {
+        out: String::new(),
+        width,
+    };
+    layout.expr(expr, Context::DELIMITED, 0);
+    layout.out.push('\n');
+    layout.out
+}
+
+/// Skip parentheses in the expression. They are written again where they are needed.
+fn unparen(expr: &Expr) -> &Expr {
+    match &expr.kind {
+        ExprKind::Paren(e) => unparen(&e.inner),
+        _ => expr,
+    }
+}
+
+/// Check if an expression starts with a negation, which takes everything after it.
+fn is_negation(expr: &Expr) -> bool {
+    match &unparen(expr).kind {
+        ExprKind::Neg(_) => true,
+        ExprKind::Number(n) => n.is_finite() && n.is_sign_negative(),
+        _ => false,
+    }
+}
+
+fn needs_parens(expr: &Expr, ctx: Context) -> bool {
+    match &unparen(expr).kind {
+        ExprKind::Bin(e) => ctx.in_abs && e.op == BinOp::Or,
+        _ => !ctx.tail && is_negation(expr),
+    }
+}
+
+fn write_expr(out: &mut String, expr: &Expr, ctx: Context) {
+    match needs_parens(expr, ctx) {
+        true => write_parenthesized(out, expr),
+        false => write_bare(out, unparen(expr), ctx),
+    }
+}
+
+fn write_parenthesized(out: &mut String, expr: &Expr) {
+    out.push('(');
+    write_bare(out, unparen(expr), Context::DELIMITED);
+    out.push(')');
+}
+
+/// Check if the operand of a binary expression binds weaker than the operator. All operators are
+/// left associative, so this includes right operands with operators of the same precedence.
+fn binds_weaker(expr: &Expr, op: &BinOp, right: bool) -> bool {
+    match &unparen(expr).kind {
+        ExprKind::Bin(e) => match right {
+            true => e.op.precedence() <= op.precedence(),
+            false => e.op.precedence() < op.precedence(),
+        },
+        _ => false,
+    }
+}
+
+/// Write the operand of a binary expression, in parentheses if it binds weaker than the operator.
+fn write_operand(out: &mut String, expr: &Expr, op: &BinOp, right: bool, ctx: Context) {
+    match binds_weaker(expr, op, right) {
+        true => write_parenthesized(out, expr),
+        false => write_expr(out, expr, ctx),
+    }
+}
+
+fn write_number(out: &mut String, n: f64) {
+    if n.is_nan() {
+        out.push_str("(0.0 / 0.0)");
+    } else if n.is_infinite() {
+        out.push_str(match n > 0.0 {
+            true => "(1.0 / 0.0)",
+            false => "(-1.0 / 0.0)",
+        });
+    } else {
+        if n.is_sign_negative() {
+            out.push('-');
+        }
+        // Number literals always have a decimal point.
+        let literal = n.abs().to_string();
+        out.push_str(&literal);
+        if !literal.contains('.') {
+            out.push_str(".0");
+        }
+    }
+}
+
+fn write_function(out: &mut String, name: &str, args: &[&Expr]) {
+    out.push_str(name);
+    out.push('(');
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_expr(out, arg, Context::DELIMITED);
+    }
+    out.push(')');
+}
+
+fn operator(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+        BinOp::Pow => "^",
+        BinOp::LessThan => "<",
+        BinOp::GreaterThan => ">",
+        BinOp::Or => "|",
+        BinOp::And => "&",
+    }
+}
+
+/// Write an expression without parentheses around it.
+fn write_bare(out: &mut String, expr: &Expr, ctx: Context) {
+    match &expr.kind {
+        ExprKind::Bin(e) => {
+            let lhs = Context { tail: false, ..ctx };
+            write_operand(out, &e.lhs, &e.op, false, lhs);
+            out.push(' ');
+            out.push_str(operator(&e.op));
+            out.push(' ');
+            write_operand(out, &e.rhs, &e.op, true, ctx);
+        }
+        ExprKind::Neg(e) => {
+            out.push('-');
+            write_expr(out, &e.inner, ctx);
+        }
+        ExprKind::Abs(e) => {
+            out.push('|');
+            let inner = Context {
+                tail: true,
+                in_abs: true,
+            };
+            write_expr(out, &e.inner, inner);
+            out.push('|');
+        }
+        ExprKind::Paren(e) => write_expr(out, &e.inner, ctx),
+        ExprKind::Number(n) => write_number(out, *n),
+        ExprKind::Color(e) => {
+            out.push('{');
+            write_expr(out, &e.r, Context::DELIMITED);
+            out.push_str(", ");
+            write_expr(out, &e.g, Context::DELIMITED);
+            out.push_str(", ");
+            write_expr(out, &e.b, Context::DELIMITED);
+            out.push('}');
+        }
+        ExprKind::If(e) => {
+            out.push_str("if ");
+            write_expr(out, &e.cond, Context::DELIMITED);
+            out.push_str(" then ");
+            write_expr(out, &e.true_expr, Context::DELIMITED);
+            out.push_str(" else ");
+            write_expr(out, &e.false_expr, Context::DELIMITED);
+            out.push_str(" end");
+        }
+        ExprKind::Sin(e) => write_function(out, "sin", &[&e.inner]),
+        ExprKind::Cos(e) => write_function(out, "cos", &[&e.inner]),
+        ExprKind::Dx(e) => write_function(out, "dx", &[&e.inner]),
+        ExprKind::Dy(e) => write_function(out, "dy", &[&e.inner]),
+        ExprKind::Normal(e) => write_function(out, "normal", &[&e.inner]),
+        ExprKind::Shade(_) => write_function(out, "shade", &expr.children()),
+        ExprKind::Tex(e) => write_function(out, "tex", &[&e.index, &e.u, &e.v]),
+        ExprKind::TransX(e) => write_function(out, "tx", &[&e.trans, &e.inner]),
+        ExprKind::TransY(e) => write_function(out, "ty", &[&e.trans, &e.inner]),
+        ExprKind::X => out.push('x'),
+        ExprKind::Y => out.push('y'),
+        ExprKind::R => out.push('r'),
+        ExprKind::A => out.push('a'),
+        ExprKind::Pixel(_) => out.push('p'),
+    }
+}
+
+/// Writes expressions over multiple lines when they do not fit on one.
+struct Layout {
+    out: String,
+    width: usize,
+}
+
+impl Layout {
+    /// Column the next character is written at.
+    fn column(&self) -> usize {
+        self.out.len() - self.out.rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.extend(std::iter::repeat_n(' ', indent));
+    }
+
+    /// Write the expression on the current line if it fits.
+    fn fits(&mut self, expr: &Expr, ctx: Context) -> bool {
+        let mut flat = String::new();
+        write_expr(&mut flat, expr, ctx);
+        let fits = self.column() + flat.len() <= self.width;
+        if fits {
+            self.out.push_str(&flat);
+        }
+        fits
+    }
+
+    fn expr(&mut self, expr: &Expr, ctx: Context, indent: usize) {
+        if self.fits(expr, ctx) {
+            return;
+        }
+        match needs_parens(expr, ctx) {
+            true => self.parenthesized(expr, indent),
+            false => self.bare(unparen(expr), ctx, indent),
+        }
+    }
+
+    fn parenthesized(&mut self, expr: &Expr, indent: usize) {
+        self.out.push('(');
+        self.newline(indent + INDENT);
+        self.bare(unparen(expr), Context::DELIMITED, indent + INDENT);
+        self.newline(indent);
+        self.out.push(')');
+    }
+
+    fn operand(&mut self, expr: &Expr, op: &BinOp, right: bool, ctx: Context, indent: usize) {
+        let mut flat = String::new();
+        write_operand(&mut flat, expr, op, right, ctx);
+        if self.column() + flat.len() <= self.width {
+            self.out.push_str(&flat);
+        } else if binds_weaker(expr, op, right) {
+            self.parenthesized(expr, indent);
+        } else {
+            self.expr(expr, ctx, indent);
+        }
+    }
+
+    /// Write a function call or color with each argument on its own line.
+    fn arguments(&mut self, open: &str, args: &[&Expr], close: char, indent: usize) {
+        self.out.push_str(open);
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.newline(indent + INDENT);
+            self.expr(arg, Context::DELIMITED, indent + INDENT);
+        }
+        self.newline(indent);
+        self.out.push(close);
+    }
+
+    /// Write an expression without parentheses around it, breaking it over multiple lines.
+    fn bare(&mut self, expr: &Expr, ctx: Context, indent: usize) {
+        match &expr.kind {
+            // The operators of a chain line up, as the left operand is broken the same way.
+            ExprKind::Bin(e) => {
+                let lhs = Context { tail: false, ..ctx };
+                self.operand(&e.lhs, &e.op, false, lhs, indent);
+                self.newline(indent + INDENT);
+                self.out.push_str(operator(&e.op));
+                self.out.push(' ');
+                self.operand(&e.rhs, &e.op, true, ctx, indent + INDENT);
+            }
+            ExprKind::If(e) => {
+                self.out.push_str("if ");
+                self.expr(&e.cond, Context::DELIMITED, indent + INDENT);
+                self.out.push_str(" then");
+                self.newline(indent + INDENT);
+                self.expr(&e.true_expr, Context::DELIMITED, indent + INDENT);
+                self.newline(indent);
+                self.out.push_str("else");
+                self.newline(indent + INDENT);
+                self.expr(&e.false_expr, Context::DELIMITED, indent + INDENT);
+                self.newline(indent);
+                self.out.push_str("end");
+            }
+            ExprKind::Neg(e) => {
+                self.out.push('-');
+                self.expr(&e.inner, ctx, indent);
+            }
+            ExprKind::Abs(e) => {
+                self.out.push('|');
+                let inner = Context {
+                    tail: true,
+                    in_abs: true,
+                };
+                self.expr(&e.inner, inner, indent);
+                self.out.push('|');
+            }
+            ExprKind::Paren(e) => self.expr(&e.inner, ctx, indent),
+            ExprKind::Color(e) => self.arguments("{", &[&e.r, &e.g, &e.b], '}', indent),
+            ExprKind::Sin(e) => self.arguments("sin(", &[&e.inner], ')', indent),
+            ExprKind::Cos(e) => self.arguments("cos(", &[&e.inner], ')', indent),
+            ExprKind::Dx(e) => self.arguments("dx(", &[&e.inner], ')', indent),
+            ExprKind::Dy(e) => self.arguments("dy(", &[&e.inner], ')', indent),
+            ExprKind::Normal(e) => self.arguments("normal(", &[&e.inner], ')', indent),
+            ExprKind::Shade(_) => self.arguments("shade(", &expr.children(), ')', indent),
+            ExprKind::Tex(e) => self.arguments("tex(", &[&e.index, &e.u, &e.v], ')', indent),
+            ExprKind::TransX(e) => self.arguments("tx(", &[&e.trans, &e.inner], ')', indent),
+            ExprKind::TransY(e) => self.arguments("ty(", &[&e.trans, &e.inner], ')', indent),
+            ExprKind::Number(_)
+            | ExprKind::X
+            | ExprKind::Y
+            | ExprKind::R
+            | ExprKind::A
+            | ExprKind::Pixel(_) => write_bare(&mut self.out, expr, ctx),
+        }
+    }
+}
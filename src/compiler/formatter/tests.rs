@@ -0,0 +1,192 @@
+use super::{format_source, to_source, MAX_WIDTH};
+use crate::compiler::{
+    ast::{Expr, ExprKind, NegExpr},
+    constant_evaluator,
+    differential::{random_source, Rng},
+    evaluator, parser,
+};
+
+/// Number of random programs to check.
+const CASES: usize = 4000;
+
+/// Remove what the formatter does not keep: parentheses, and the difference between a negated
+/// number and a negative number.
+fn normalize(expr: Expr) -> Expr {
+    let expr = expr.map_children(normalize);
+    match expr.kind {
+        ExprKind::Paren(e) => *e.inner,
+        ExprKind::Neg(NegExpr { inner }) => match inner.kind {
+            ExprKind::Number(n) => Expr {
+                kind: ExprKind::Number(-n),
+                span: expr.span,
+            },
+            kind => Expr {
+                kind: ExprKind::Neg(NegExpr::new(Expr {
+                    kind,
+                    span: inner.span,
+                })),
+                span: expr.span,
+            },
+        },
+        _ => expr,
+    }
+}
+
+macro_rules! test_format {
+    ($($name:ident: $input:expr => $output:expr),*$(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                let expr = parser::parse_source($input.to_string());
+                assert_eq!(to_source(&expr), $output);
+            }
+        )*
+    };
+}
+
+test_format! {
+    precedence: "x+y*r" => "x + y * r",
+    precedence_parens: "(x + y) * r" => "(x + y) * r",
+    redundant_parens: "((x)) + (y * r)" => "x + y * r",
+    left_associative: "(x - y) - r" => "x - y - r",
+    right_operand: "x - (y - r)" => "x - (y - r)",
+    pow: "x^(y^2.0)" => "x ^ (y ^ 2.0)",
+    neg_tail: "x * -y" => "x * -y",
+    neg_takes_rest: "-x + y" => "-x + y",
+    neg_operand: "(-x) + y" => "(-x) + y",
+    neg_middle: "x * (-y) + r" => "x * (-y) + r",
+    abs: "|x| | |y|" => "|x| | |y|",
+    abs_or: "|(x | y)|" => "|(x | y)|",
+    color: "{x, 1.0, y}" => "{x, 1.0, y}",
+    if_expr: "if x < y then -x else y end" => "if x < y then -x else y end",
+    functions: "tx(0.5, sin(x)) + shade(dx(y), 0.25)" => "tx(0.5, sin(x)) + shade(dx(y), 0.25)",
+    elevation: "shade(x, a, 0.1)" => "shade(x, a, 0.1)",
+    images: "normal(x) * tex(1.0, x, y)" => "normal(x) * tex(1.0, x, y)",
+    number: "3.0" => "3.0",
+}
+
+#[test]
+fn negative_numbers() {
+    let expr = constant_evaluator::evaluate_constants(parser::parse_source("(-2.0) * x"));
+    assert_eq!(to_source(&expr), "(-2.0) * x");
+    let expr = constant_evaluator::evaluate_constants(parser::parse_source("x * -2.0"));
+    assert_eq!(to_source(&expr), "x * -2.0");
+}
+
+#[test]
+fn round_trip() {
+    let mut rng = Rng(0xf0f0);
+    for _ in 0..CASES {
+        let source = random_source(&mut rng);
+        let expr = parser::parse_source(source.clone());
+        let formatted = to_source(&expr);
+        let parsed = parser::parse_source(formatted.clone());
+        assert_eq!(
+            normalize(parsed.clone()),
+            normalize(expr.clone()),
+            "{source:?} was formatted as {formatted:?}"
+        );
+        assert_eq!(to_source(&parsed), formatted, "{source:?}");
+    }
+}
+
+/// Filters write the source pixel as `p`, which is only read back by [parser::parse_filter].
+#[test]
+fn round_trip_filters() {
+    let mut rng = Rng(0xf117);
+    for _ in 0..CASES {
+        let source = random_source(&mut rng).replace('q', "p");
+        let expr = parser::parse_filter(source.clone());
+        let formatted = to_source(&expr);
+        let parsed = parser::parse_filter(formatted.clone());
+        assert_eq!(
+            normalize(parsed),
+            normalize(expr.clone()),
+            "{source:?} was formatted as {formatted:?}"
+        );
+        assert_eq!(
+            normalize(parser::parse_filter(format_source(&expr, 24))),
+            normalize(expr),
+            "{source:?}"
+        );
+    }
+}
+
+/// Folded constants can be negative or infinite, which is written as an expression.
+#[test]
+fn round_trip_folded() {
+    let mut rng = Rng(0x0f0f);
+    let coord = |i: usize| i as f64 / 4.0 - 0.95;
+    for _ in 0..CASES {
+        let source = random_source(&mut rng);
+        let expr = constant_evaluator::evaluate_constants(parser::parse_source(source.clone()));
+        let formatted = to_source(&expr);
+        let parsed = parser::parse_source(formatted.clone());
+        for (x, y) in (0..8).flat_map(|i| (0..8).map(move |j| (coord(i), coord(j)))) {
+            let a = evaluator::eval(&expr, x, y);
+            let b = evaluator::eval(&parsed, x, y);
+            assert!(
+                [(a.r, b.r), (a.g, b.g), (a.b, b.b)]
+                    .iter()
+                    .all(|(a, b)| a == b || (a.is_nan() && b.is_nan())),
+                "{source:?} was formatted as {formatted:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn round_trip_with_line_breaks() {
+    let mut rng = Rng(0xb4ea);
+    for _ in 0..CASES {
+        let source = random_source(&mut rng);
+        let expr = parser::parse_source(source.clone());
+        let formatted = format_source(&expr, 24);
+        let parsed = parser::parse_source(formatted.clone());
+        assert_eq!(
+            normalize(parsed),
+            normalize(expr.clone()),
+            "{source:?} was formatted as\n{formatted}"
+        );
+        let flat = to_source(&expr);
+        if flat.len() <= MAX_WIDTH {
+            assert_eq!(format_source(&expr, MAX_WIDTH), flat + "\n");
+        }
+    }
+}
+
+#[test]
+fn line_breaks() {
+    let expr = parser::parse_source(
+        "sin(x * 10.0) * sin(y * 10.0) + if r < 0.5 then {1.0, 0.0, 0.5} else (x + y) * 2.0 end"
+            .to_string(),
+    );
+    assert_eq!(
+        format_source(&expr, 40),
+        "\
+sin(x * 10.0) * sin(y * 10.0)
+    + if r < 0.5 then
+        {1.0, 0.0, 0.5}
+    else
+        (x + y) * 2.0
+    end
+"
+    );
+    assert_eq!(
+        format_source(&expr, 20),
+        "\
+sin(x * 10.0)
+    * sin(y * 10.0)
+    + if r < 0.5 then
+        {
+            1.0,
+            0.0,
+            0.5
+        }
+    else
+        (x + y)
+            * 2.0
+    end
+"
+    );
+}
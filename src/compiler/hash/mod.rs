@@ -0,0 +1,46 @@
+//! Hash visualization. Any data is digested with SHA-256, and the digest is turned into the
+//! source that the forgiving parser reads. Inputs that differ in a single bit have unrelated
+//! digests, so they are rendered as unrelated images.
+
+use sha2::{Digest, Sha256};
+
+use super::{
+    ast::Expr,
+    parser::{self, ParseOptions, MAX_TRIES},
+    version::LanguageVersion,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Number of digests chained into the source of a hash visualization. Every digest adds 32
+/// bytes of source, and more source makes larger programs.
+const DIGESTS: usize = 3;
+
+/// Digest data into the source of its hash visualization. The first digest is the SHA-256 of the
+/// data, and every following digest is the SHA-256 of the one before it.
+pub fn hash_source(data: &[u8]) -> Vec<u8> {
+    let mut digest = Sha256::digest(data);
+    let mut source = Vec::with_capacity(DIGESTS * digest.len());
+    for _ in 0..DIGESTS {
+        source.extend_from_slice(&digest);
+        digest = Sha256::digest(digest);
+    }
+    source
+}
+
+/// Parse the hash visualization of any data. Like the command line, the source is parsed again
+/// until the expression is not constant.
+pub fn parse_hash(data: &[u8]) -> Expr {
+    parse_hash_with_version(data, LanguageVersion::LATEST)
+}
+
+/// Parse the hash visualization of any data the way a version of the language does.
+pub fn parse_hash_with_version(data: &[u8], version: LanguageVersion) -> Expr {
+    // Hash visualizations are parsed again while they are constant in every version.
+    let options = ParseOptions {
+        max_tries: MAX_TRIES,
+        ..ParseOptions::with_version(version)
+    };
+    parser::parse_source_with_options(hash_source(data), &options)
+}
@@ -0,0 +1,37 @@
+use super::{hash_source, parse_hash};
+
+#[test]
+fn visualizations_are_deterministic() {
+    let data = b"ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGx2";
+    assert_eq!(hash_source(data), hash_source(data));
+    assert_eq!(parse_hash(data).to_string(), parse_hash(data).to_string());
+}
+
+#[test]
+fn sources_are_chained_digests() {
+    // SHA-256 of the empty input.
+    let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    let source = hash_source(b"");
+    let hex: String = source[..32].iter().map(|b| format!("{b:02x}")).collect();
+    assert_eq!(hex, empty);
+    assert_eq!(source.len() % 32, 0);
+}
+
+#[test]
+fn flipped_bits_give_unrelated_programs() {
+    let data = b"The quick brown fox jumps over the lazy dog".to_vec();
+    let original = parse_hash(&data).to_string();
+    for i in 0..data.len() * 8 {
+        let mut flipped = data.clone();
+        flipped[i / 8] ^= 1 << (i % 8);
+        assert_ne!(parse_hash(&flipped).to_string(), original, "bit {i}");
+    }
+}
+
+#[test]
+fn visualizations_are_not_constant() {
+    for n in 0..200u32 {
+        let expr = parse_hash(&n.to_le_bytes());
+        assert!(!expr.is_constant(), "{n}: {expr}");
+    }
+}
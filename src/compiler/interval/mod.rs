@@ -0,0 +1,540 @@
+//! Interval arithmetic evaluator. Computes bounds of an expression over a rectangle of points, so
+//! the renderer can fill regions that are known to render as a single color without evaluating
+//! every pixel.
+//!
+//! The bounds are conservative: every value the [evaluator](super::evaluator) can produce for a
+//! point inside the rectangle lies within the bounds. Whenever this can not be guaranteed cheaply,
+//! the bounds are widened or the evaluator gives up with [Bounds::Unknown].
+
+#[cfg(test)]
+mod tests;
+
+use std::f64::consts::PI;
+
+use super::{
+    ast::{BinOp, ColorExpr, Expr, ExprKind},
+    texture,
+    version::LanguageVersion,
+};
+
+/// Relative amount to widen the results of functions that are not correctly rounded.
+const WIDEN: f64 = 1e-12;
+
+/// A closed interval. The bounds may be infinite, but are never NaN.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Interval {
+    /// The interval containing every float.
+    pub const FULL: Self = Self {
+        lo: f64::NEG_INFINITY,
+        hi: f64::INFINITY,
+    };
+
+    /// Create an interval from two bounds.
+    pub fn new(lo: f64, hi: f64) -> Self {
+        debug_assert!(lo <= hi, "Invalid interval [{lo}, {hi}]");
+        Self { lo, hi }
+    }
+
+    /// Create an interval containing a single value.
+    pub fn point(v: f64) -> Self {
+        Self::new(v, v)
+    }
+
+    /// The smallest interval containing both intervals.
+    pub fn hull(self, other: Self) -> Self {
+        Self::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+
+    pub fn contains(&self, v: f64) -> bool {
+        self.lo <= v && v <= self.hi
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.lo.is_finite() && self.hi.is_finite()
+    }
+
+    /// Widen the interval slightly to account for rounding errors. Infinite bounds are kept.
+    fn widen(self) -> Self {
+        let widen = |v: f64, dir: f64| match v.is_finite() {
+            true => v + dir * (v.abs() * WIDEN + f64::MIN_POSITIVE),
+            false => v,
+        };
+        Self::new(widen(self.lo, -1.0), widen(self.hi, 1.0))
+    }
+
+    /// The smallest interval containing all the values. If any value is NaN, the evaluator turns
+    /// it into zero, but the other points could be anything, so the full interval is returned.
+    fn from_values(values: &[f64]) -> Self {
+        if values.iter().any(|v| v.is_nan()) {
+            return Self::FULL;
+        }
+        let lo = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        Self::new(lo, hi)
+    }
+
+    fn neg(self) -> Self {
+        Self::new(-self.hi, -self.lo)
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self::from_values(&[self.lo + other.lo, self.hi + other.hi])
+    }
+
+    fn sub(self, other: Self) -> Self {
+        self.add(other.neg())
+    }
+
+    fn mul(self, other: Self) -> Self {
+        // Zero times infinity is NaN.
+        if (self.contains(0.0) && !other.is_finite()) || (other.contains(0.0) && !self.is_finite())
+        {
+            return Self::FULL;
+        }
+        Self::from_values(&[
+            self.lo * other.lo,
+            self.lo * other.hi,
+            self.hi * other.lo,
+            self.hi * other.hi,
+        ])
+    }
+
+    fn div(self, other: Self) -> Self {
+        if other.contains(0.0) || (!self.is_finite() && !other.is_finite()) {
+            return Self::FULL;
+        }
+        Self::from_values(&[
+            self.lo / other.lo,
+            self.lo / other.hi,
+            self.hi / other.lo,
+            self.hi / other.hi,
+        ])
+    }
+
+    /// Floating point remainder. The result has the sign of `self` and is smaller than `other`.
+    fn fmod(self, other: Self) -> Self {
+        if other.contains(0.0) || !self.is_finite() {
+            return Self::FULL;
+        }
+        let min = other.lo.abs().min(other.hi.abs());
+        let max = other.lo.abs().max(other.hi.abs());
+        if -min < self.lo && self.hi < min {
+            return self;
+        }
+        Self::new((-max).max(self.lo.min(0.0)), max.min(self.hi.max(0.0)))
+    }
+
+    fn pow(self, other: Self) -> Self {
+        // With a positive base, `x^y` is monotonic in both `x` and `y`, so the extremes are found
+        // in the corners. Negative bases produce NaN for most exponents.
+        if self.lo <= 0.0 {
+            return Self::FULL;
+        }
+        Self::from_values(&[
+            self.lo.powf(other.lo),
+            self.lo.powf(other.hi),
+            self.hi.powf(other.lo),
+            self.hi.powf(other.hi),
+        ])
+        .widen()
+    }
+
+    fn abs(self) -> Self {
+        match (self.lo >= 0.0, self.hi <= 0.0) {
+            (true, _) => self,
+            (_, true) => self.neg(),
+            _ => Self::new(0.0, self.hi.max(-self.lo)),
+        }
+    }
+
+    fn sqrt(self) -> Self {
+        Self::new(self.lo.sqrt(), self.hi.sqrt())
+    }
+
+    fn atan(self) -> Self {
+        Self::new(self.lo.atan(), self.hi.atan()).widen()
+    }
+
+    fn sin(self) -> Self {
+        // Maxima of sin are at `(2k + 0.5) * pi` and minima at `(2k + 1.5) * pi`.
+        self.trig(f64::sin, 0.5)
+    }
+
+    fn cos(self) -> Self {
+        // Maxima of cos are at `2k * pi` and minima at `(2k + 1) * pi`.
+        self.trig(f64::cos, 0.0)
+    }
+
+    /// Bounds of a periodic function with extrema at `(k + phase) * pi`, with maxima at even `k`.
+    fn trig(self, f: fn(f64) -> f64, phase: f64) -> Self {
+        let unit = Self::new(-1.0, 1.0);
+        // For large arguments, the multiples of pi can not be located accurately.
+        if self.lo.abs().max(self.hi.abs()) > 1e6 || self.hi - self.lo >= 2.0 * PI {
+            return unit;
+        }
+
+        let mut values = vec![f(self.lo), f(self.hi)];
+
+        // Look slightly outside the interval, so rounding can not hide an extremum.
+        let first = (self.lo / PI - phase - 1e-9).ceil() as i64;
+        let last = (self.hi / PI - phase + 1e-9).floor() as i64;
+        for k in first..=last {
+            values.push(if k % 2 == 0 { 1.0 } else { -1.0 });
+        }
+
+        let i = Self::from_values(&values).widen();
+        Self::new(i.lo.max(-1.0), i.hi.min(1.0))
+    }
+}
+
+/// Bounds of a boolean: whether it can be true and whether it can be false.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoolBounds {
+    pub can_be_true: bool,
+    pub can_be_false: bool,
+}
+
+impl BoolBounds {
+    fn from_bool(b: bool) -> Self {
+        Self {
+            can_be_true: b,
+            can_be_false: !b,
+        }
+    }
+
+    fn or(self, other: Self) -> Self {
+        Self {
+            can_be_true: self.can_be_true || other.can_be_true,
+            can_be_false: self.can_be_false && other.can_be_false,
+        }
+    }
+
+    fn and(self, other: Self) -> Self {
+        Self {
+            can_be_true: self.can_be_true && other.can_be_true,
+            can_be_false: self.can_be_false || other.can_be_false,
+        }
+    }
+
+    fn xor(self, other: Self) -> Self {
+        Self {
+            can_be_true: (self.can_be_true && other.can_be_false)
+                || (self.can_be_false && other.can_be_true),
+            can_be_false: (self.can_be_true && other.can_be_true)
+                || (self.can_be_false && other.can_be_false),
+        }
+    }
+
+    fn hull(self, other: Self) -> Self {
+        Self {
+            can_be_true: self.can_be_true || other.can_be_true,
+            can_be_false: self.can_be_false || other.can_be_false,
+        }
+    }
+
+    /// The boolean as a number. True is `1.0` and false is `-1.0`.
+    fn as_interval(self) -> Interval {
+        match (self.can_be_true, self.can_be_false) {
+            (true, false) => Interval::point(1.0),
+            (false, true) => Interval::point(-1.0),
+            _ => Interval::new(-1.0, 1.0),
+        }
+    }
+}
+
+/// Bounds of each channel of a color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorBounds {
+    pub r: Interval,
+    pub g: Interval,
+    pub b: Interval,
+}
+
+impl ColorBounds {
+    fn splat(i: Interval) -> Self {
+        Self { r: i, g: i, b: i }
+    }
+
+    fn map(self, f: impl Fn(Interval) -> Interval) -> Self {
+        Self {
+            r: f(self.r),
+            g: f(self.g),
+            b: f(self.b),
+        }
+    }
+
+    fn zip(self, other: Self, f: impl Fn(Interval, Interval) -> Interval) -> Self {
+        Self {
+            r: f(self.r, other.r),
+            g: f(self.g, other.g),
+            b: f(self.b, other.b),
+        }
+    }
+
+    /// Bounds of the clamped color, as computed by
+    /// [eval_with_version](super::evaluator::eval_with_version). Returns [None] if a channel may be
+    /// infinite, or may wrap around in the clamping function.
+    pub fn clamp(self, version: LanguageVersion) -> Option<Self> {
+        let (lo, hi) = version.monotonic_range();
+        let clamp = |i: Interval| {
+            let i = i.widen();
+            (lo < i.lo && i.hi < hi)
+                .then(|| Interval::new(version.normalize(i.lo), version.normalize(i.hi)))
+        };
+        Some(Self {
+            r: clamp(self.r)?,
+            g: clamp(self.g)?,
+            b: clamp(self.b)?,
+        })
+    }
+}
+
+/// Bounds of the result of evaluating an expression. Mirrors [Result](super::evaluator::Result).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bounds {
+    Color(ColorBounds),
+    Number(Interval),
+    Bool(BoolBounds),
+    /// The type of the result is not known, so nothing is known about it.
+    Unknown,
+}
+
+impl Bounds {
+    /// Order of the variants in [Result](super::evaluator::Result), which decides the result of `|`
+    /// and `&` between different types.
+    fn rank(&self) -> u8 {
+        match self {
+            Bounds::Color(_) => 0,
+            Bounds::Number(_) => 1,
+            Bounds::Bool(_) => 2,
+            Bounds::Unknown => 3,
+        }
+    }
+
+    /// Cast the bounds to a number.
+    fn as_number(&self) -> Option<Interval> {
+        match self {
+            Bounds::Color(c) => Some(c.r.add(c.g).add(c.b).div(Interval::point(3.0))),
+            Bounds::Number(n) => Some(*n),
+            Bounds::Bool(b) => Some(b.as_interval()),
+            Bounds::Unknown => None,
+        }
+    }
+
+    /// Cast the bounds to a color.
+    pub fn as_color(&self) -> Option<ColorBounds> {
+        match self {
+            Bounds::Color(c) => Some(*c),
+            Bounds::Number(n) => Some(ColorBounds::splat(*n)),
+            Bounds::Bool(b) => Some(ColorBounds::splat(b.as_interval())),
+            Bounds::Unknown => None,
+        }
+    }
+
+    /// Cast the bounds to a boolean.
+    fn as_bool(&self) -> Option<BoolBounds> {
+        match self {
+            Bounds::Bool(b) => Some(*b),
+            _ => {
+                let n = self.as_number()?;
+                Some(BoolBounds {
+                    can_be_true: n.hi >= 0.0,
+                    can_be_false: n.lo < 0.0,
+                })
+            }
+        }
+    }
+
+    /// Bounds containing both bounds.
+    fn hull(self, other: Self) -> Self {
+        match (self, other) {
+            (Bounds::Color(a), Bounds::Color(b)) => Bounds::Color(a.zip(b, Interval::hull)),
+            (Bounds::Number(a), Bounds::Number(b)) => Bounds::Number(a.hull(b)),
+            (Bounds::Bool(a), Bounds::Bool(b)) => Bounds::Bool(a.hull(b)),
+            _ => Bounds::Unknown,
+        }
+    }
+
+    /// Apply an arithmetic operation. Colors are combined channel by channel and booleans are cast
+    /// to numbers.
+    fn arithmetic(self, other: Self, f: impl Fn(Interval, Interval) -> Interval) -> Self {
+        match (self, other) {
+            (Bounds::Unknown, _) | (_, Bounds::Unknown) => Bounds::Unknown,
+            (Bounds::Color(a), Bounds::Color(b)) => Bounds::Color(a.zip(b, f)),
+            (Bounds::Color(a), b) => {
+                let b = b.as_number().unwrap();
+                Bounds::Color(a.map(|a| f(a, b)))
+            }
+            (a, Bounds::Color(b)) => {
+                let a = a.as_number().unwrap();
+                Bounds::Color(b.map(|b| f(a, b)))
+            }
+            (a, b) => Bounds::Number(f(a.as_number().unwrap(), b.as_number().unwrap())),
+        }
+    }
+
+    /// Apply a function to a number or every channel of a color.
+    fn call(self, f: impl Fn(Interval) -> Interval) -> Self {
+        match self {
+            Bounds::Color(c) => Bounds::Color(c.map(f)),
+            Bounds::Unknown => Bounds::Unknown,
+            n => Bounds::Number(f(n.as_number().unwrap())),
+        }
+    }
+
+    /// Compare two bounds with `<`.
+    fn less(self, other: Self) -> Self {
+        let less = |a: Interval, b: Interval| BoolBounds {
+            can_be_true: a.lo < b.hi,
+            can_be_false: a.hi >= b.lo,
+        };
+        match (self, other) {
+            (Bounds::Unknown, _) | (_, Bounds::Unknown) => Bounds::Unknown,
+            (Bounds::Color(_), _) | (_, Bounds::Color(_)) => {
+                self.arithmetic(other, |a, b| less(a, b).as_interval())
+            }
+            (a, b) => Bounds::Bool(less(a.as_number().unwrap(), b.as_number().unwrap())),
+        }
+    }
+
+    /// The `|` and `&` operations. Booleans are combined logically, numbers with max and min, and
+    /// otherwise one of the operands is picked.
+    fn select(self, other: Self, or: bool) -> Self {
+        match (self, other) {
+            (Bounds::Unknown, _) | (_, Bounds::Unknown) => Bounds::Unknown,
+            (Bounds::Bool(a), Bounds::Bool(b)) => Bounds::Bool(if or { a.or(b) } else { a.and(b) }),
+            (Bounds::Number(a), Bounds::Number(b)) => Bounds::Number(match or {
+                true => Interval::new(a.lo.max(b.lo), a.hi.max(b.hi)),
+                false => Interval::new(a.lo.min(b.lo), a.hi.min(b.hi)),
+            }),
+            // Colors are compared lexicographically, so either of them may be picked.
+            (a @ Bounds::Color(_), b @ Bounds::Color(_)) => a.hull(b),
+            (a, b) => match (or, a.rank() < b.rank()) {
+                (true, true) | (false, false) => b,
+                (true, false) | (false, true) => a,
+            },
+        }
+    }
+}
+
+/// Wrap the bounds of a translated coordinate into the range [-1, 1]. Returns [None] if the
+/// coordinate may be infinite, as wrapping it produces NaN.
+fn wrap(i: Interval) -> Option<Interval> {
+    if !i.is_finite() {
+        return None;
+    }
+    // Wrapping adds and subtracts one, which rounds. Values close to the edges may even round
+    // across the seam, so they are treated like values that wrap.
+    match -1.0 + 1e-9 < i.lo && i.hi < 1.0 - 1e-9 {
+        true => Some(Interval::new(i.lo - 1e-15, i.hi + 1e-15)),
+        false => Some(Interval::new(-1.0, 1.0)),
+    }
+}
+
+/// Evaluate bounds of an expression over all points in the rectangle spanned by `x` and `y`.
+pub fn eval_bounds(expr: &Expr, x: Interval, y: Interval) -> Bounds {
+    match &expr.kind {
+        ExprKind::Bin(e) => {
+            let l = eval_bounds(&e.lhs, x, y);
+            let r = eval_bounds(&e.rhs, x, y);
+            match e.op {
+                BinOp::Add => l.arithmetic(r, Interval::add),
+                BinOp::Sub => l.arithmetic(r, Interval::sub),
+                BinOp::Mul => l.arithmetic(r, Interval::mul),
+                BinOp::Div => l.arithmetic(r, Interval::div),
+                BinOp::Mod => match (l, r) {
+                    // The remainder of `1.0` and `-1.0` is always zero, which is true.
+                    (Bounds::Bool(_), Bounds::Bool(_)) => Bounds::Bool(BoolBounds::from_bool(true)),
+                    _ => l.arithmetic(r, Interval::fmod),
+                },
+                BinOp::Pow => match (l, r) {
+                    (Bounds::Bool(a), Bounds::Bool(b)) => Bounds::Bool(a.xor(b)),
+                    _ => l.arithmetic(r, Interval::pow),
+                },
+                BinOp::Or => l.select(r, true),
+                BinOp::And => l.select(r, false),
+                BinOp::LessThan => l.less(r),
+                BinOp::GreaterThan => r.less(l),
+            }
+        }
+        ExprKind::Color(c) => {
+            let [r, g, b] = ColorExpr::channel_order([&c.r, &c.g, &c.b])
+                .map(|e| eval_bounds(e, x, y).as_number());
+            match (r, g, b) {
+                (Some(r), Some(g), Some(b)) => Bounds::Color(ColorBounds { r, g, b }),
+                _ => Bounds::Unknown,
+            }
+        }
+        ExprKind::Paren(e) => eval_bounds(&e.inner, x, y),
+        ExprKind::Neg(e) => eval_bounds(&e.inner, x, y).call(Interval::neg),
+        ExprKind::Abs(e) => eval_bounds(&e.inner, x, y).call(Interval::abs),
+        ExprKind::Sin(e) => eval_bounds(&e.inner, x, y).call(Interval::sin),
+        ExprKind::Cos(e) => eval_bounds(&e.inner, x, y).call(Interval::cos),
+        ExprKind::Number(n) => Bounds::Number(Interval::from_values(&[*n])),
+        ExprKind::TransX(e) => {
+            let offset = eval_bounds(&e.trans, x, y).as_number();
+            match offset.and_then(|o| wrap(x.sub(o))) {
+                Some(x) => eval_bounds(&e.inner, x, y),
+                None => Bounds::Unknown,
+            }
+        }
+        ExprKind::TransY(e) => {
+            let offset = eval_bounds(&e.trans, x, y).as_number();
+            match offset.and_then(|o| wrap(y.sub(o))) {
+                Some(y) => eval_bounds(&e.inner, x, y),
+                None => Bounds::Unknown,
+            }
+        }
+        ExprKind::X => Bounds::Number(x),
+        ExprKind::Y => Bounds::Number(y),
+        ExprKind::R => {
+            let (x, y) = (x.abs(), y.abs());
+            Bounds::Number(x.mul(x).add(y.mul(y)).sqrt())
+        }
+        ExprKind::A => match x.contains(0.0) {
+            // Dividing by zero gives an infinite or NaN angle.
+            true => Bounds::Number(Interval::new(-0.5, 0.5)),
+            false => Bounds::Number(y.div(x).atan().div(Interval::point(PI))),
+        },
+        // Derivatives are not bounded.
+        ExprKind::Dx(_) | ExprKind::Dy(_) | ExprKind::Normal(_) | ExprKind::Shade(_) => {
+            Bounds::Unknown
+        }
+        // Samples are between the values of black and white.
+        ExprKind::Tex(_) | ExprKind::Pixel(_) => {
+            let range = Interval::new(texture::value(0.0), texture::value(1.0));
+            Bounds::Color(ColorBounds {
+                r: range,
+                g: range,
+                b: range,
+            })
+        }
+        ExprKind::If(e) => match eval_bounds(&e.cond, x, y).as_bool() {
+            Some(BoolBounds {
+                can_be_true: true,
+                can_be_false: false,
+            }) => eval_bounds(&e.true_expr, x, y),
+            Some(BoolBounds {
+                can_be_true: false,
+                can_be_false: true,
+            }) => eval_bounds(&e.false_expr, x, y),
+            _ => eval_bounds(&e.true_expr, x, y).hull(eval_bounds(&e.false_expr, x, y)),
+        },
+    }
+}
+
+/// Evaluate bounds of the color of an expression over a rectangle of points, clamped by a version
+/// of the language.
+pub fn eval_color_bounds(
+    expr: &Expr,
+    x: Interval,
+    y: Interval,
+    version: LanguageVersion,
+) -> Option<ColorBounds> {
+    eval_bounds(expr, x, y).as_color()?.clamp(version)
+}
@@ -0,0 +1,149 @@
+use std::f64::consts::PI;
+
+use super::{eval_bounds, eval_color_bounds, Bounds, Interval};
+use crate::compiler::{
+    differential::{random_source, Rng},
+    evaluator, parser,
+    version::LanguageVersion,
+};
+
+/// Number of random programs to check.
+const CASES: usize = 2000;
+
+/// Size of the image the random regions are taken from.
+const SIZE: u32 = 97;
+
+/// Normalized coordinate of a pixel, computed the same way as the renderer.
+fn coord(i: u32) -> f64 {
+    (i as f64) / (SIZE as f64) * 2.0 - 1.0
+}
+
+fn contains(bounds: &Bounds, result: &evaluator::Result) -> bool {
+    match (bounds, result) {
+        (Bounds::Unknown, _) => true,
+        (Bounds::Number(i), evaluator::Result::Number(n)) => i.contains(*n),
+        (Bounds::Color(c), evaluator::Result::Color(v)) => {
+            c.r.contains(v.r) && c.g.contains(v.g) && c.b.contains(v.b)
+        }
+        (Bounds::Bool(b), evaluator::Result::Bool(v)) => match v {
+            true => b.can_be_true,
+            false => b.can_be_false,
+        },
+        _ => false,
+    }
+}
+
+#[test]
+fn bounds_contain_every_point() {
+    let mut rng = Rng(0xb0b);
+    for _ in 0..CASES {
+        let source = random_source(&mut rng);
+        let expr = parser::parse_source(source.clone());
+
+        let x0 = rng.below(SIZE as usize) as u32;
+        let y0 = rng.below(SIZE as usize) as u32;
+        let x1 = (x0 + rng.below(12) as u32).min(SIZE - 1);
+        let y1 = (y0 + rng.below(12) as u32).min(SIZE - 1);
+        let x = Interval::new(coord(x0), coord(x1));
+        let y = Interval::new(coord(y0), coord(y1));
+
+        let bounds = eval_bounds(&expr, x, y);
+        let color_bounds = eval_color_bounds(&expr, x, y, LanguageVersion::LATEST);
+        for px in x0..=x1 {
+            for py in y0..=y1 {
+                let (nx, ny) = (coord(px), coord(py));
+                let result = evaluator::eval_expr(&expr, nx, ny);
+                assert!(
+                    contains(&bounds, &result),
+                    "{source:?} ({expr}) at ({nx}, {ny}): {result:?} not in {bounds:?}"
+                );
+
+                let Some(c) = &color_bounds else { continue };
+                let color = evaluator::eval(&expr, nx, ny);
+                assert!(
+                    c.r.contains(color.r) && c.g.contains(color.g) && c.b.contains(color.b),
+                    "{source:?} ({expr}) at ({nx}, {ny}): {color:?} not in {c:?}"
+                );
+            }
+        }
+    }
+}
+
+/// Render an expression without culling any regions.
+fn render_per_pixel(expr: &crate::compiler::ast::Expr, width: u32, height: u32) -> image::RgbImage {
+    image::RgbImage::from_fn(width, height, |x, y| {
+        let nx = (x as f64) / (width as f64) * 2.0 - 1.0;
+        let ny = (y as f64) / (height as f64) * 2.0 - 1.0;
+        let c = evaluator::eval(expr, nx, ny);
+        let channel = |c: f64| (c * (u8::MAX as f64)) as u8;
+        image::Rgb([channel(c.r), channel(c.g), channel(c.b)])
+    })
+}
+
+#[test]
+fn culled_render_matches_per_pixel_render() {
+    let mut rng = Rng(0xfeed);
+    for _ in 0..CASES / 10 {
+        let source = random_source(&mut rng);
+        let expr = parser::parse_source(source.clone());
+        let culled = crate::renderer::render(&expr, 53, 40);
+        assert!(
+            culled == render_per_pixel(&expr, 53, 40),
+            "Culling changed the image of {source:?} ({expr})"
+        );
+    }
+}
+
+#[test]
+fn culled_render_matches_examples() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let expr = parser::parse_source(source);
+        let culled = crate::renderer::render(&expr, 64, 64);
+        assert!(
+            culled == render_per_pixel(&expr, 64, 64),
+            "Culling changed the image of {}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn uniform_regions_are_detected() {
+    let expr = parser::parse_source("if x < 0.0 then {1.0, 0.0, 0.0} else 2.0 end".to_string());
+    let (x, y) = (Interval::new(-1.0, -0.5), Interval::new(-1.0, 1.0));
+    let left = eval_color_bounds(&expr, x, y, LanguageVersion::LATEST);
+    let c = left.unwrap();
+    assert!(c.r.hi - c.r.lo < 1e-9);
+    assert!(c.g.hi - c.g.lo < 1e-9);
+
+    let middle = eval_bounds(&expr, Interval::new(-0.5, 0.5), Interval::new(-1.0, 1.0));
+    assert_eq!(middle, Bounds::Unknown);
+}
+
+#[test]
+fn trig_extrema() {
+    let i = Interval::new(0.0, PI).cos();
+    assert!(i.lo <= -1.0 && i.hi >= 1.0);
+
+    let i = Interval::new(0.1, 0.2).sin();
+    assert!(i.lo <= 0.1f64.sin() && i.hi >= 0.2f64.sin() && i.hi < 0.21);
+
+    let i = Interval::new(1.0, 2.0).sin();
+    assert_eq!(i.hi, 1.0);
+    assert!(i.lo <= 1.0f64.sin());
+}
+
+#[test]
+fn nan_gives_full_interval() {
+    let zero = Interval::new(-1.0, 1.0);
+    assert_eq!(Interval::point(1.0).div(zero), Interval::FULL);
+    assert_eq!(Interval::FULL.mul(zero), Interval::FULL);
+    assert_eq!(Interval::point(2.0).fmod(zero), Interval::FULL);
+    assert_eq!(
+        Interval::point(-2.0).pow(Interval::point(0.5)),
+        Interval::FULL
+    );
+}
@@ -2,7 +2,7 @@
 
 use std::rc::Rc;
 
-use super::text::Span;
+use super::{text::Span, version::LanguageVersion};
 
 /// A language token.
 #[derive(Debug, PartialEq, Clone)]
@@ -40,11 +40,16 @@ pub enum TokenKind {
     A,
     TX,
     TY,
+    DX,
+    DY,
 
     Comma,
 
     Sin,
     Cos,
+    Normal,
+    Shade,
+    Tex,
 
     If,
     Then,
@@ -57,7 +62,8 @@ pub enum TokenKind {
 }
 
 impl TokenKind {
-    /// Converts the token kind into a [usize].
+    /// Converts the token kind into a [usize]. The parser makes its choices from these numbers,
+    /// so they must not change within a [LanguageVersion].
     #[rustfmt::skip]
     pub fn as_usize(&self) -> usize {
         match self {
@@ -91,6 +97,11 @@ impl TokenKind {
             TokenKind::Then        => 25,
             TokenKind::Else        => 26,
             TokenKind::End         => 27,
+            TokenKind::DX          => 28,
+            TokenKind::DY          => 29,
+            TokenKind::Normal      => 30,
+            TokenKind::Shade       => 31,
+            TokenKind::Tex         => 32,
             TokenKind::Number(n)   => ((n.abs() % 1.0) * (usize::MAX as f64)) as usize,
             TokenKind::Other(c)    => (*c) as usize,
         }
@@ -109,14 +120,22 @@ impl TokenKind {
 pub struct Lexer {
     source: Rc<Vec<u8>>,
     cursor: usize,
+    version: LanguageVersion,
 }
 
 impl Lexer {
-    /// Create a new lexer from a source string.
-    pub fn new(source: String) -> Self {
+    /// Create a new lexer from source code, which can be any bytes.
+    #[allow(dead_code)] // TODO: Find a better solution
+    pub fn new(source: impl Into<Vec<u8>>) -> Self {
+        Self::with_version(source, LanguageVersion::LATEST)
+    }
+
+    /// Create a new lexer that reads source code the way a version of the language does.
+    pub fn with_version(source: impl Into<Vec<u8>>, version: LanguageVersion) -> Self {
         Self {
-            source: Rc::new(source.into_bytes()),
+            source: Rc::new(source.into()),
             cursor: 0,
+            version,
         }
     }
 
@@ -151,6 +170,27 @@ impl Lexer {
         }
     }
 
+    /// Checks if the next characters spell out a word.
+    fn is_at_word(&self, word: &str) -> bool {
+        self.source[self.cursor..].starts_with(word.as_bytes())
+    }
+
+    /// Checks if the next characters spell out a whole word, which is not part of a longer word.
+    fn is_at_whole_word(&self, word: &str) -> bool {
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+        self.is_at_word(word) && !is_word(self.peak(-1)) && !is_word(self.peak(word.len() as isize))
+    }
+
+    /// The keyword of the language version that the next characters spell out, if any. Keywords
+    /// inside longer words are not read, so text that mentions them lexes as it did before they
+    /// existed.
+    fn keyword(&self) -> Option<&'static (&'static str, TokenKind)> {
+        self.version
+            .keywords()
+            .iter()
+            .find(|(word, _)| self.is_at_whole_word(word))
+    }
+
     /// Checks if the next characters make up a decimal number literal (like 2.1).
     fn is_at_number(&self) -> bool {
         let mut cursor = self.cursor;
@@ -215,16 +255,20 @@ impl Iterator for Lexer {
             }
             '0'..='9' if self.is_at_number() => {
                 let mut number = String::new();
+                let mut has_dot = false;
                 while let Some(c) = self.current() {
-                    if c.is_ascii_digit() || c == '.' {
+                    // A second dot ends the number, so `1.2.3` is lexed as `1.2` followed by `.3`.
+                    if c.is_ascii_digit() || (c == '.' && !has_dot) {
+                        has_dot |= c == '.';
                         number.push(c);
                         self.next();
                     } else {
                         break;
                     }
                 }
+                // `is_at_number` only lets digits around a single dot through, which always parse.
                 Some(Token {
-                    kind: TokenKind::Number(number.parse().expect("Invalid number parsed.")),
+                    kind: TokenKind::Number(number.parse().unwrap_or_default()),
                     span: self.span(token_start),
                 })
             }
@@ -235,20 +279,26 @@ impl Iterator for Lexer {
                     span: self.span(token_start),
                 })
             }
+            _ if self.keyword().is_some() => {
+                let (word, kind) = self.keyword()?;
+                self.cursor += word.len() - 1;
+                token(self, kind.clone())
+            }
             c => {
                 #[rustfmt::skip]
-                let (size, token) = match [self.peak(0), self.peak(1), self.peak(2), self.peak(3)] {
-                    [Some('i'), Some('f'),         _,         _] => (2, token(self, TokenKind::If)),
-                    [Some('t'), Some('h'), Some('e'), Some('n')] => (4, token(self, TokenKind::Then)),
-                    [Some('e'), Some('l'), Some('s'), Some('e')] => (4, token(self, TokenKind::Else)),
-                    [Some('e'), Some('n'), Some('d'),         _] => (3, token(self, TokenKind::End)),
-                    [Some('s'), Some('i'), Some('n'),         _] => (3, token(self, TokenKind::Sin)),
-                    [Some('c'), Some('o'), Some('s'),         _] => (3, token(self, TokenKind::Cos)),
+                let (size, kind) = match [self.peak(0), self.peak(1), self.peak(2), self.peak(3)] {
+                    [Some('i'), Some('f'),         _,         _] => (2, TokenKind::If),
+                    [Some('t'), Some('h'), Some('e'), Some('n')] => (4, TokenKind::Then),
+                    [Some('e'), Some('l'), Some('s'), Some('e')] => (4, TokenKind::Else),
+                    [Some('e'), Some('n'), Some('d'),         _] => (3, TokenKind::End),
+                    [Some('s'), Some('i'), Some('n'),         _] => (3, TokenKind::Sin),
+                    [Some('c'), Some('o'), Some('s'),         _] => (3, TokenKind::Cos),
                     _ => return token(self, TokenKind::Other(c)),
                 };
-                // Subtract one because `token` already increments the cursor.
+                // Subtract one because `token` increments the cursor, and the span has to cover
+                // the whole word.
                 self.cursor += size - 1;
-                token
+                token(self, kind)
             }
         }
     }
@@ -0,0 +1,98 @@
+//! Lighting of height fields. The `normal` and `shade` built-ins treat an expression as the height
+//! of a surface above the image, and light it with a light source far away, as seen from straight
+//! above. The slope of the surface is found with [autodiff].
+
+#[cfg(test)]
+mod tests;
+
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+use super::{
+    ast::Expr,
+    autodiff,
+    evaluator::{self, Color, Observer, Result},
+};
+
+/// Light that reaches every part of the surface.
+const AMBIENT: f64 = 0.1;
+
+/// Strength of the light reflected equally in all directions (Lambert).
+const DIFFUSE: f64 = 0.8;
+
+/// Strength of the light reflected towards the viewer (Phong).
+const SPECULAR: f64 = 0.5;
+
+/// How sharp the specular highlights are.
+const SHININESS: i32 = 16;
+
+/// Largest slope that is considered, so the normal can be computed without overflowing.
+const MAX_SLOPE: f64 = 1e100;
+
+/// Unit normal of a surface with the given slopes in the x and y directions.
+fn surface_normal(dx: f64, dy: f64) -> [f64; 3] {
+    let (dx, dy) = (
+        dx.clamp(-MAX_SLOPE, MAX_SLOPE),
+        dy.clamp(-MAX_SLOPE, MAX_SLOPE),
+    );
+    let len = (dx * dx + dy * dy + 1.0).sqrt();
+    [-dx / len, -dy / len, 1.0 / len]
+}
+
+/// Unit vector pointing towards the light. The light comes from the angle `angle * pi` in the image
+/// plane, like the `a` built-in, and `elevation * pi` above the surface, or 45 degrees above it
+/// without an elevation.
+fn light_direction(angle: f64, elevation: Option<f64>) -> [f64; 3] {
+    let (sin, cos) = (angle * PI).sin_cos();
+    let (up, across) = match elevation {
+        Some(elevation) => (elevation * PI).sin_cos(),
+        None => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    };
+    [cos * across, sin * across, up]
+}
+
+/// Brightness of a surface with the given normal, lit from the given direction.
+fn intensity(normal: [f64; 3], light: [f64; 3]) -> f64 {
+    let diffuse: f64 = normal.iter().zip(light).map(|(n, l)| n * l).sum();
+    if diffuse <= 0.0 {
+        return AMBIENT;
+    }
+    // The viewer looks straight down, so only the z component of the reflected light matters.
+    let reflected = 2.0 * diffuse * normal[2] - light[2];
+    AMBIENT + DIFFUSE * diffuse + SPECULAR * reflected.max(0.0).powi(SHININESS)
+}
+
+/// The surface normal of a height field as a color `{x, y, z}`. Colors are averaged into a single
+/// height.
+pub(crate) fn normal<O: Observer>(height: &Expr, x: f64, y: f64, obs: &mut O) -> Result {
+    let d = autodiff::eval_derivatives(height, x, y, obs);
+    let [r, g, b] = surface_normal(d.dx.as_number(), d.dy.as_number());
+    Result::Color(Color { r, g, b })
+}
+
+/// The brightness of a height field lit from the angle `light` and the given `elevation`. Every
+/// channel of a color is shaded as a separate height field.
+pub(crate) fn shade<O: Observer>(
+    height: &Expr,
+    light: &Expr,
+    elevation: Option<&Expr>,
+    x: f64,
+    y: f64,
+    obs: &mut O,
+) -> Result {
+    let d = autodiff::eval_derivatives(height, x, y, obs);
+    let angle = evaluator::eval_expr_observed(light, x, y, obs).as_number();
+    let elevation = elevation.map(|e| evaluator::eval_expr_observed(e, x, y, obs).as_number());
+    let light = light_direction(angle, elevation);
+    let shade = |dx, dy| intensity(surface_normal(dx, dy), light);
+    match d.value {
+        Result::Color(_) => {
+            let (dx, dy) = (d.dx.as_color(), d.dy.as_color());
+            Result::Color(Color {
+                r: shade(dx.r, dy.r),
+                g: shade(dx.g, dy.g),
+                b: shade(dx.b, dy.b),
+            })
+        }
+        _ => Result::Number(shade(d.dx.as_number(), d.dy.as_number())),
+    }
+}
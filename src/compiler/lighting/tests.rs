@@ -0,0 +1,80 @@
+use super::{intensity, light_direction, AMBIENT};
+use crate::compiler::{
+    evaluator::{self, Color, Result},
+    parser,
+};
+
+fn eval(source: &str, x: f64, y: f64) -> Result {
+    evaluator::eval_expr(&parser::parse_source(source.to_string()), x, y)
+}
+
+fn number(r: Result) -> f64 {
+    match r {
+        Result::Number(n) => n,
+        r => panic!("Expected a number, got {r:?}"),
+    }
+}
+
+fn color(r: Result) -> Color {
+    match r {
+        Result::Color(c) => c,
+        r => panic!("Expected a color, got {r:?}"),
+    }
+}
+
+#[test]
+fn flat_normal() {
+    let n = color(eval("normal(0.5)", 0.3, -0.2));
+    assert_eq!((n.r, n.g, n.b), (0.0, 0.0, 1.0));
+}
+
+#[test]
+fn sloped_normal() {
+    let n = color(eval("normal(x)", 0.3, -0.2));
+    let s = 0.5f64.sqrt();
+    assert!((n.r + s).abs() < 1e-12);
+    assert_eq!(n.g, 0.0);
+    assert!((n.b - s).abs() < 1e-12);
+}
+
+#[test]
+fn flat_shade() {
+    let s = number(eval("shade(0.5, 0.25)", 0.1, 0.1));
+    assert_eq!(s, intensity([0.0, 0.0, 1.0], light_direction(0.25, None)));
+    assert!(s > AMBIENT);
+}
+
+#[test]
+fn shade_faces_light() {
+    // The light comes from the right, so surfaces rising to the left face it.
+    let towards = number(eval("shade(0.0 - x, 0.0)", 0.1, 0.1));
+    let away = number(eval("shade(x, 0.0)", 0.1, 0.1));
+    let flat = number(eval("shade(1.0, 0.0)", 0.1, 0.1));
+    assert!(away < flat && flat < towards);
+}
+
+#[test]
+fn shade_in_shadow() {
+    let s = number(eval("shade(x * 10.0, 0.0)", 0.1, 0.1));
+    assert_eq!(s, AMBIENT);
+}
+
+#[test]
+fn shade_color_channels() {
+    // Color literals are read as `{r, b, g}`.
+    let c = color(eval("shade({x, 0.0, 0.0 - x}, 0.0)", 0.1, 0.1));
+    assert!(c.r < c.b && c.b < c.g);
+}
+
+#[test]
+fn shade_elevation() {
+    let default = number(eval("shade(0.5, 0.25)", 0.1, 0.1));
+    let diagonal = number(eval("shade(0.5, 0.25, 0.25)", 0.1, 0.1));
+    assert!((default - diagonal).abs() < 1e-12);
+
+    // A flat surface lit from straight above reflects all of the light towards the viewer.
+    let above = number(eval("shade(0.5, 0.25, 0.5)", 0.1, 0.1));
+    assert_eq!(above, AMBIENT + 0.8 + 0.5);
+    let low = number(eval("shade(0.5, 0.25, 0.05)", 0.1, 0.1));
+    assert!(AMBIENT < low && low < default);
+}
@@ -0,0 +1,319 @@
+//! Budgets for the size and cost of expressions. Any input is a valid program, so a large file
+//! gives a large expression that can take minutes to render. Expressions over a budget are
+//! truncated instead of rejected, so every input still renders within a predictable time.
+
+#[cfg(test)]
+mod tests;
+
+use super::ast::{BinOp, Expr, ExprKind};
+
+/// Default limit on how deep expressions nest. Every pass over an expression recurses once per
+/// level, so deeper expressions could overflow the stack, especially on wasm.
+pub const MAX_DEPTH: usize = 512;
+
+/// Budgets that the parser and renderer keep expressions within.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Number of tokens parsed. The rest of the input is ignored.
+    pub max_tokens: usize,
+    /// Number of expressions in the whole expression.
+    pub max_nodes: usize,
+    /// Number of expressions on the longest path from the expression down to a leaf. Past the
+    /// limit, the parser reads tokens as single values and stops growing chains of operators.
+    pub max_depth: usize,
+    /// Number of operations estimated by [estimate_ops] to evaluate a pixel.
+    pub max_ops: usize,
+    /// Number of derivatives nested in one another, counting `dx`, `dy`, `normal` and `shade`.
+    /// Each level evaluates its operand five times more, so past the limit, the parser reads
+    /// derivatives as their operand.
+    pub max_derivative_depth: usize,
+}
+
+impl Limits {
+    /// The default limits, which only truncate inputs far larger than programs written by hand.
+    pub const DEFAULT: Self = Self {
+        max_tokens: 100_000,
+        max_nodes: 50_000,
+        max_depth: MAX_DEPTH,
+        max_ops: 50_000,
+        max_derivative_depth: 3,
+    };
+
+    /// No limits, apart from the depth limit that keeps expressions from overflowing the stack.
+    #[allow(dead_code)] // TODO: Find a better solution
+    pub const UNLIMITED: Self = Self {
+        max_tokens: usize::MAX,
+        max_nodes: usize::MAX,
+        max_depth: MAX_DEPTH,
+        max_ops: usize::MAX,
+        max_derivative_depth: usize::MAX,
+    };
+
+    /// Truncate an expression until it fits the node and operation budgets.
+    pub fn apply(&self, expr: Expr) -> Expr {
+        let expr = truncate(expr, self.max_nodes, &|_| (1, 1));
+        truncate(expr, self.max_ops, &ops)
+    }
+
+    /// The maximum of one of the budgets.
+    pub fn max(&self, limit: Limit) -> usize {
+        match limit {
+            Limit::Tokens => self.max_tokens,
+            Limit::Nodes => self.max_nodes,
+            Limit::Depth => self.max_depth,
+            Limit::Ops => self.max_ops,
+            Limit::Derivatives => self.max_derivative_depth,
+        }
+    }
+
+    /// The first budget that an expression is over, if any, before it is truncated by
+    /// [Limits::apply].
+    pub fn exceeded_by(&self, expr: &Expr) -> Option<Limit> {
+        if node_count(expr) > self.max_nodes {
+            Some(Limit::Nodes)
+        } else if estimate_ops(expr) > self.max_ops {
+            Some(Limit::Ops)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// One of the budgets of [Limits].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// [Limits::max_tokens]
+    Tokens,
+    /// [Limits::max_nodes]
+    Nodes,
+    /// [Limits::max_depth]
+    Depth,
+    /// [Limits::max_ops]
+    Ops,
+    /// [Limits::max_derivative_depth]
+    Derivatives,
+}
+
+/// Cost of an expression without its children, and how many times its children are evaluated.
+type Weights = dyn Fn(&Expr) -> (usize, usize);
+
+/// The operations of an expression without its children. Derivatives evaluate their operands with
+/// dual numbers, which carry three values instead of one. Within another derivative, they take
+/// finite differences instead, which evaluate their operands at the pixel and at four offsets, so
+/// each level of nesting is counted five times.
+fn ops(expr: &Expr) -> (usize, usize) {
+    match &expr.kind {
+        ExprKind::Bin(e) => match e.op {
+            BinOp::Pow | BinOp::Mod => (4, 1),
+            BinOp::Add
+            | BinOp::Sub
+            | BinOp::Mul
+            | BinOp::Div
+            | BinOp::Or
+            | BinOp::And
+            | BinOp::LessThan
+            | BinOp::GreaterThan => (1, 1),
+        },
+        ExprKind::Sin(_) | ExprKind::Cos(_) | ExprKind::A => (4, 1),
+        ExprKind::R | ExprKind::TransX(_) | ExprKind::TransY(_) => (2, 1),
+        ExprKind::Dx(_) | ExprKind::Dy(_) | ExprKind::Normal(_) => (4, 5),
+        ExprKind::Shade(_) => (8, 5),
+        // Bilinear sampling blends four pixels.
+        ExprKind::Tex(_) | ExprKind::Pixel(_) => (8, 1),
+        ExprKind::If(_)
+        | ExprKind::Color(_)
+        | ExprKind::Paren(_)
+        | ExprKind::Neg(_)
+        | ExprKind::Abs(_)
+        | ExprKind::Number(_)
+        | ExprKind::X
+        | ExprKind::Y => (1, 1),
+    }
+}
+
+/// What truncation needs to know about an expression.
+#[derive(Debug, Clone, Copy)]
+struct Info {
+    /// Cost of the expression and all of its children.
+    cost: usize,
+    /// Cost of the leaf that the expression is truncated to with the smallest budget.
+    floor: usize,
+    /// Number of expressions in the expression, including itself.
+    size: usize,
+}
+
+/// Index of the child that an expression is replaced with when it does not fit a budget. This is
+/// the child that carries most of the image, like the inner expression of a translation.
+fn main_child(expr: &Expr) -> usize {
+    match &expr.kind {
+        ExprKind::If(_) | ExprKind::TransX(_) | ExprKind::TransY(_) | ExprKind::Tex(_) => 1,
+        _ => 0,
+    }
+}
+
+/// The [Info] of every expression in an expression, in pre-order. The children of the expression
+/// at index `i` start at index `i + 1`, each after all the expressions of the one before it.
+fn infos(expr: &Expr, weights: &Weights) -> Vec<Info> {
+    let mut order = Vec::new();
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        order.push(expr);
+        stack.extend(expr.children().into_iter().rev());
+    }
+
+    // Children come after their parent, so they are done first in reverse.
+    let mut infos = vec![
+        Info {
+            cost: 0,
+            floor: 0,
+            size: 0,
+        };
+        order.len()
+    ];
+    for (i, expr) in order.iter().enumerate().rev() {
+        let (own, factor) = weights(expr);
+        let mut info = Info {
+            cost: 0,
+            floor: own,
+            size: 1,
+        };
+        let mut child = i + 1;
+        for n in 0..expr.children().len() {
+            if n == main_child(expr) {
+                info.floor = infos[child].floor;
+            }
+            info.cost = info.cost.saturating_add(infos[child].cost);
+            info.size += infos[child].size;
+            child += infos[child].size;
+        }
+        info.cost = own.saturating_add(factor.saturating_mul(info.cost));
+        infos[i] = info;
+    }
+    infos
+}
+
+/// Number of expressions in an expression, including itself.
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn node_count(expr: &Expr) -> usize {
+    infos(expr, &|_| (1, 1))[0].size
+}
+
+/// Estimated number of operations to evaluate an expression at a pixel. Both branches of
+/// conditionals are counted, so this is an upper bound on the work of any pixel.
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn estimate_ops(expr: &Expr) -> usize {
+    infos(expr, &ops)[0].cost
+}
+
+/// Truncate an expression until its cost fits the budget. Children are given what is left of the
+/// budget from left to right, and expressions whose children can not fit at all are replaced by
+/// their main child. The result fits the budget unless even its cheapest leaf does not.
+///
+/// The expression is walked with an explicit stack, so its depth is not limited by the size of
+/// the call stack.
+fn truncate(expr: Expr, budget: usize, weights: &Weights) -> Expr {
+    /// An expression whose children are being truncated.
+    struct Frame {
+        /// The expression, with placeholders for its children.
+        expr: Expr,
+        own: usize,
+        factor: usize,
+        /// Children that are left to truncate, with their index in `infos`.
+        children: std::vec::IntoIter<(Expr, usize)>,
+        /// Truncated children.
+        done: Vec<Expr>,
+        /// Cost of the truncated children.
+        cost: usize,
+        /// What is left of the budget of the children.
+        left: usize,
+        /// Part of `left` reserved for the cheapest leaves of the children left to truncate.
+        reserved: usize,
+    }
+
+    let infos = infos(&expr, weights);
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut task = (expr, 0, budget);
+    loop {
+        let (expr, i, budget) = task;
+        let mut result = None;
+
+        if infos[i].cost <= budget || infos[i].size == 1 {
+            result = Some((expr, infos[i].cost));
+        } else {
+            let (own, factor) = weights(&expr);
+            let (expr, children) = expr.split_children();
+            let mut indices = Vec::with_capacity(children.len());
+            let mut child = i + 1;
+            for _ in 0..children.len() {
+                indices.push(child);
+                child += infos[child].size;
+            }
+
+            let reserved = indices
+                .iter()
+                .fold(0, |sum: usize, &c| sum.saturating_add(infos[c].floor));
+            let fits = own
+                .checked_add(factor.saturating_mul(reserved))
+                .is_some_and(|needed| needed <= budget);
+            if !fits {
+                let main = main_child(&expr);
+                task = (
+                    children.into_iter().nth(main).unwrap(),
+                    indices[main],
+                    budget,
+                );
+                continue;
+            }
+
+            frames.push(Frame {
+                expr,
+                own,
+                factor,
+                children: children
+                    .into_iter()
+                    .zip(indices)
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+                done: Vec::new(),
+                cost: 0,
+                left: (budget - own) / factor,
+                reserved,
+            });
+        }
+
+        // Pass finished expressions up to their parents until a child is left to truncate.
+        loop {
+            if let Some((expr, cost)) = result.take() {
+                let Some(frame) = frames.last_mut() else {
+                    return expr;
+                };
+                frame.done.push(expr);
+                frame.cost = frame.cost.saturating_add(cost);
+                frame.left -= cost;
+            }
+
+            let frame = frames.last_mut().expect("no expression to truncate");
+            if let Some((child, c)) = frame.children.next() {
+                frame.reserved -= infos[c].floor;
+                task = (child, c, frame.left - frame.reserved);
+                break;
+            }
+
+            let frame = frames.pop().expect("no expression to truncate");
+            let mut done = frame.done.into_iter();
+            let expr = frame
+                .expr
+                .map_children(|_| done.next().expect("missing child"));
+            let cost = frame
+                .own
+                .saturating_add(frame.factor.saturating_mul(frame.cost));
+            result = Some((expr, cost));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::{estimate_ops, node_count, Limits};
+use crate::compiler::{
+    parser::{parse_source, parse_source_with_limits},
+    stats::max_evaluated,
+};
+
+/// Sources of many sizes that use every kind of expression.
+fn sources() -> Vec<String> {
+    let words = "x+y*sin(r)-cos(a)%{x,y,r}^dx(y)>dy(x)<normal(x)|shade(x,y)&tex(0,x,y)?abs(x):-y";
+    (0..40)
+        .map(|n| {
+            (0..n * 13)
+                .map(|i| words.as_bytes()[i % words.len()] as char)
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn expressions_fit_their_budgets() {
+    for max_nodes in [1, 2, 5, 20, 100] {
+        for source in sources() {
+            let limits = Limits {
+                max_nodes,
+                ..Limits::UNLIMITED
+            };
+            let expr = parse_source_with_limits(source.clone(), limits);
+            assert!(node_count(&expr) <= max_nodes, "{source}: {expr}");
+        }
+    }
+    for max_ops in [8, 10, 50, 300] {
+        for source in sources() {
+            let limits = Limits {
+                max_ops,
+                ..Limits::UNLIMITED
+            };
+            let expr = parse_source_with_limits(source.clone(), limits);
+            assert!(estimate_ops(&expr) <= max_ops, "{source}: {expr}");
+        }
+    }
+}
+
+#[test]
+fn tokens_past_the_budget_are_ignored() {
+    let limits = Limits {
+        max_tokens: 3,
+        ..Limits::UNLIMITED
+    };
+    let truncated = parse_source_with_limits("x+y*sin(r)-cos(a)", limits);
+    assert_eq!(truncated, parse_source_with_limits("x+y", limits));
+}
+
+#[test]
+fn expressions_within_budget_are_unchanged() {
+    for source in sources().into_iter().take(10) {
+        assert_eq!(
+            parse_source_with_limits(source.clone(), Limits::UNLIMITED),
+            parse_source(source)
+        );
+    }
+}
+
+#[test]
+fn truncation_is_deterministic() {
+    let limits = Limits {
+        max_nodes: 30,
+        ..Limits::UNLIMITED
+    };
+    for source in sources() {
+        assert_eq!(
+            parse_source_with_limits(source.clone(), limits).to_string(),
+            parse_source_with_limits(source, limits).to_string()
+        );
+    }
+}
+
+#[test]
+fn huge_sources_render_within_the_default_budget() {
+    let source = "sin(x)*cos(y)+".repeat(100_000);
+    let expr = parse_source(source);
+    assert!(node_count(&expr) <= Limits::DEFAULT.max_nodes);
+    assert!(estimate_ops(&expr) <= Limits::DEFAULT.max_ops);
+}
+
+#[test]
+fn nested_derivatives_are_estimated_from_above() {
+    let limits = Limits {
+        max_ops: usize::MAX,
+        max_derivative_depth: usize::MAX,
+        ..Limits::DEFAULT
+    };
+    for depth in 1..=7 {
+        let source = format!("{}sin(x*y*9.0)+r{}", "dx(".repeat(depth), ")".repeat(depth));
+        let expr = parse_source_with_limits(source, limits);
+        assert!(max_evaluated(&expr) <= estimate_ops(&expr), "{expr}");
+    }
+}
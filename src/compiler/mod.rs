@@ -1,15 +1,24 @@
 pub mod ast;
+pub mod autodiff;
 pub mod constant_evaluator;
+#[cfg(test)]
+mod differential;
 pub mod evaluator;
+pub mod formatter;
+pub mod hash;
+pub mod interval;
 pub mod lexer;
+pub mod lighting;
+pub mod limits;
 pub mod parser;
+pub mod simplifier;
+pub mod stats;
 mod text;
+pub mod texture;
+pub mod version;
 
 /// Compile source code into an expression
 #[allow(dead_code)] // TODO: Find a better solution
-pub fn compile_source(source: String) -> ast::Expr {
-    let lexer = lexer::Lexer::new(source);
-    let source = lexer.source();
-    let tokens: Vec<_> = lexer.collect();
-    parser::parse_tokens(tokens, source.clone(), || ())
+pub fn compile_source(source: impl Into<Vec<u8>>) -> ast::Expr {
+    parser::parse_source_with_options(source, &parser::ParseOptions::default())
 }
@@ -1,38 +1,103 @@
 //! Parser implementation for the SSL language
 
-use std::rc::Rc;
+use std::{fmt, rc::Rc};
 
 mod cornelia;
+mod options;
+#[cfg(test)]
+mod tests;
+
+pub use options::{ConstantTest, ParseOptions, RetryReason, MAX_TRIES, SAMPLE_GRID};
 
 use super::{
     ast::{
-        self, AbsExpr, BinExpr, BinOp, ColorExpr, CosExpr, Expr, ExprKind, IfExpr, NegExpr,
-        ParenExpr, SinExpr, TransXExpr, TransYExpr,
+        self, AbsExpr, BinExpr, BinOp, ColorExpr, CosExpr, DxExpr, DyExpr, Expr, ExprKind, IfExpr,
+        NegExpr, NormalExpr, ParenExpr, PixelExpr, ShadeExpr, SinExpr, TexExpr, TransXExpr,
+        TransYExpr,
     },
     constant_evaluator,
     lexer::{self, Token, TokenKind},
+    limits::{Limit, Limits},
     text::Span,
+    version::LanguageVersion,
 };
+use crate::error::{Error, ParseError};
+
+/// The seed counter that the parser starts from for a seed and a retry. Every seed has its own
+/// range of counters for its retries, so the retries of one seed never repeat another seed. Seed 0
+/// starts from 0, which is how inputs have always been parsed.
+fn start_seed(seed: u64, retry: usize) -> u64 {
+    seed.wrapping_mul(MAX_TRIES as u64)
+        .wrapping_add(retry as u64)
+}
 
-#[allow(dead_code)] // TODO: Find a better solution
-const MAX_TRIES: usize = 100;
-
-#[allow(dead_code)] // TODO: Find a better solution
-pub fn parse_tokens(tokens: Vec<Token>, source: Rc<Vec<u8>>, on_retry: impl Fn()) -> ast::Expr {
+/// Parse tokens into an expression, parsing again with other choices while the expression is
+/// constant, as the options decide. The expression is returned as parsed, without folding its
+/// constants. Strict parses fail at the first repair or truncation.
+pub fn try_parse_tokens(
+    tokens: Vec<Token>,
+    source: Rc<Vec<u8>>,
+    options: &ParseOptions,
+) -> Result<ast::Expr, Error> {
     let mut parser = Parser::new(tokens, source);
-    let mut expr = parser.parse_expr();
-    for retry in 1..MAX_TRIES {
-        expr = constant_evaluator::evaluate_constants(expr);
-
-        if !expr.is_constant() {
+    parser.filter = options.filter;
+    parser.limits = options.limits;
+    parser.strict = options.strict;
+    parser.seed = start_seed(options.seed, 0);
+    let mut expr = parser.parse();
+    if let Some(error) = parser.error.take() {
+        return Err(error);
+    }
+    if options.strict {
+        return Ok(expr);
+    }
+    for retry in 1..options.max_tries {
+        let folded = constant_evaluator::evaluate_constants(expr.clone());
+        let Some(reason) = options.retry_reason(&folded) else {
             break;
+        };
+        if let Some(on_retry) = options.on_retry {
+            on_retry(retry, reason);
         }
-        on_retry();
         parser.reset();
-        parser.seed = retry;
-        expr = parser.parse_expr();
+        parser.seed = start_seed(options.seed, retry);
+        expr = parser.parse();
     }
-    expr
+    Ok(expr)
+}
+
+/// Parse tokens into an expression, like [try_parse_tokens] but never strictly.
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn parse_tokens(tokens: Vec<Token>, source: Rc<Vec<u8>>, options: &ParseOptions) -> ast::Expr {
+    let options = ParseOptions {
+        strict: false,
+        ..*options
+    };
+    try_parse_tokens(tokens, source, &options).expect("only strict parses fail")
+}
+
+/// Parse source code into an expression with the given options. Strict parses fail at the first
+/// place where the source would be repaired or truncated.
+pub fn try_parse_source_with_options(
+    source: impl Into<Vec<u8>>,
+    options: &ParseOptions,
+) -> Result<ast::Expr, Error> {
+    let lexer = lexer::Lexer::with_version(source, options.version);
+    let source = lexer.source();
+    // One token more than the limit tells strict parses that tokens were left out.
+    let max_tokens = options.limits.max_tokens.saturating_add(1);
+    let tokens: Vec<_> = lexer.take(max_tokens).collect();
+    try_parse_tokens(tokens, source, options)
+}
+
+/// Parse source code into an expression with the given options, like
+/// [try_parse_source_with_options] but never strictly.
+pub fn parse_source_with_options(source: impl Into<Vec<u8>>, options: &ParseOptions) -> ast::Expr {
+    let options = ParseOptions {
+        strict: false,
+        ..*options
+    };
+    try_parse_source_with_options(source, &options).expect("only strict parses fail")
 }
 
 /// Parser for SSL
@@ -41,15 +106,36 @@ pub struct Parser {
     tokens: Vec<Token>,
     cursor: usize,
     looking_for: Vec<TokenKind>,
-    seed: usize,
+    /// Counter that moves with every choice. It has 64 bits on every platform, so the same seed
+    /// makes the same choices on the command line and on wasm.
+    seed: u64,
     not_number: usize,
+    /// Whether `p` reads the color of the source pixel. Outside of filters, `p` has no meaning.
+    filter: bool,
+    /// Depth of the expression being parsed in the whole expression.
+    depth: usize,
+    /// Depth of the deepest child parsed so far by the primary expression being parsed. Children
+    /// report their depth here, so the depth of an expression is known without walking it.
+    child_depth: usize,
+    /// Number of derivatives around the expression being parsed.
+    derivative_depth: usize,
+    /// Length of `looking_for` at which a comma ends the expression as well, because an optional
+    /// argument may follow it.
+    optional_argument: Option<usize>,
+    /// Budgets the parsed expression is kept within. Past the depth limit, tokens are parsed as
+    /// leaves, and binary expressions stop growing.
+    limits: Limits,
+    /// Whether the first repair or truncation is recorded in `error`.
+    strict: bool,
+    /// The first repair or truncation of a strict parse.
+    error: Option<Error>,
 }
 
 /// Choose an expression based on a weighted choice and a seed number.
 macro_rules! choice {
     ($n:expr, $($w:expr => $res:expr),*$(,)?) => {{ (|| {
         let total: usize = [$($w),*].into_iter().sum();
-        let n = ($n as usize) % total;
+        let n = (($n as u64) % (total as u64)) as usize;
         let mut _index: usize = 0;
         $(
             if n >= _index && n < (_index + $w) {
@@ -63,6 +149,7 @@ macro_rules! choice {
 
 impl Parser {
     fn choose_token(&mut self) -> Expr {
+        self.repair(format_args!("expected an expression"));
         let mut span = Span {
             start: self.source.len(),
             end: self.source.len(),
@@ -71,7 +158,7 @@ impl Parser {
         let (n, f) = match self.current() {
             Some(t) => {
                 span = t.span.clone();
-                (t.kind.as_usize(), t.kind.as_f64())
+                (t.kind.as_usize() as u64, t.kind.as_f64())
             }
             None => (self.seed(), (((self.seed() % 100) as f64) / 100.0) % 1.0),
         };
@@ -88,7 +175,14 @@ impl Parser {
             _ => 0,
         };
 
-        choice! { n + self.seed(),
+        // Past the end of the source, the seed moves in fixed steps. Totals that share a factor
+        // with the step can keep choosing nested `if`s forever, which an odd weight avoids here.
+        let pixel = match self.filter {
+            true => 3,
+            false => 0,
+        };
+
+        choice! { n.wrapping_add(self.seed()),
             10/(l*l) => self.parse_color(),
             2/l => self.parse_parenthesized_expr(),
             1/l => self.parse_sin_expr(),
@@ -99,14 +193,15 @@ impl Parser {
             4 => expr(ExprKind::Y),
             2 => expr(ExprKind::R),
             2 => expr(ExprKind::A),
+            pixel => expr(ExprKind::Pixel(PixelExpr::default())),
             num => expr(ExprKind::Number(f)),
             l.min(2) => self.parse_if_expr(),
             0 => self.parse_neg_expr(),
         }
     }
 
-    fn choose_binop(&mut self, seed: usize) -> BinOp {
-        choice! {seed + self.seed(),
+    fn choose_binop(&mut self, seed: u64) -> BinOp {
+        choice! {seed.wrapping_add(self.seed()),
             7 => BinOp::Add,
             7 => BinOp::Sub,
             9 => BinOp::Mul,
@@ -121,14 +216,55 @@ impl Parser {
     }
 }
 
-/// Parse source code into an expression
+/// Parse source code into an expression. Like the command line, sources are parsed again while
+/// the expression is constant.
 #[allow(dead_code)] // TODO: Find a better solution
-pub fn parse_source(source: String) -> ast::Expr {
-    let lexer = lexer::Lexer::new(source);
-    let source = lexer.source();
-    let tokens: Vec<_> = lexer.collect();
-    let mut parser = Parser::new(tokens, source);
-    parser.parse_expr()
+pub fn parse_source(source: impl Into<Vec<u8>>) -> ast::Expr {
+    parse_source_with_options(source, &ParseOptions::default())
+}
+
+/// Parse source code into an image filter, where `p` is the color of the source pixel. Like the
+/// command line, sources are parsed again until the filter depends on the source pixel.
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn parse_filter(source: impl Into<Vec<u8>>) -> ast::Expr {
+    let options = ParseOptions {
+        filter: true,
+        ..ParseOptions::default()
+    };
+    parse_source_with_options(source, &options)
+}
+
+/// Parse source code into an expression the way a version of the language does. Parsing with
+/// [LanguageVersion::LATEST] is the same as [parse_source].
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn parse_source_with_version(
+    source: impl Into<Vec<u8>>,
+    version: LanguageVersion,
+) -> ast::Expr {
+    parse_source_with_options(source, &ParseOptions::with_version(version))
+}
+
+/// Parse source code into an expression that is kept within the given limits. Parsing with
+/// [Limits::DEFAULT] is the same as [parse_source].
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn parse_source_with_limits(source: impl Into<Vec<u8>>, limits: Limits) -> ast::Expr {
+    let options = ParseOptions {
+        limits,
+        ..ParseOptions::default()
+    };
+    parse_source_with_options(source, &options)
+}
+
+/// Parse source code into an expression with a seed. Every seed makes different choices where the
+/// source is repaired, so one source gives many variations of its image. Parsing with seed 0 is
+/// the same as [parse_source].
+#[allow(dead_code)] // TODO: Find a better solution
+pub fn parse_source_with_seed(source: impl Into<Vec<u8>>, seed: u64) -> ast::Expr {
+    let options = ParseOptions {
+        seed,
+        ..ParseOptions::default()
+    };
+    parse_source_with_options(source, &options)
 }
 
 impl Parser {
@@ -140,6 +276,14 @@ impl Parser {
             looking_for: vec![],
             seed: 0,
             not_number: 0,
+            filter: false,
+            depth: 0,
+            child_depth: 0,
+            derivative_depth: 0,
+            optional_argument: None,
+            limits: Limits::DEFAULT,
+            strict: false,
+            error: None,
         }
     }
 
@@ -148,6 +292,39 @@ impl Parser {
         self.looking_for.clear();
         self.seed = 0;
         self.not_number = 0;
+        self.depth = 0;
+        self.child_depth = 0;
+        self.derivative_depth = 0;
+        self.optional_argument = None;
+        self.error = None;
+    }
+
+    /// Record that the source is repaired at the current token, if the parse is strict and
+    /// nothing was recorded before.
+    fn repair(&mut self, message: fmt::Arguments) {
+        if self.strict && self.error.is_none() {
+            let span = self.current_span();
+            self.error = Some(Error::Parse(ParseError {
+                span: span.start..span.end,
+                message: message.to_string(),
+            }));
+        }
+    }
+
+    /// Record that the expression is truncated to fit a limit, if the parse is strict and nothing
+    /// was recorded before.
+    fn exceed(&mut self, limit: Limit) {
+        if self.strict && self.error.is_none() {
+            let max = self.limits.max(limit);
+            self.error = Some(Error::LimitExceeded { limit, max });
+        }
+    }
+
+    /// Consume a token of the given kind, or repair the source where it is missing.
+    fn expect(&mut self, kind: TokenKind, name: &str) {
+        if self.consume_if(|tk| *tk == kind).is_none() {
+            self.repair(format_args!("expected {name}"));
+        }
     }
 
     fn peak(&self, offset: isize) -> Option<&Token> {
@@ -158,7 +335,7 @@ impl Parser {
         self.tokens.get(cursor as usize)
     }
 
-    fn seed(&mut self) -> usize {
+    fn seed(&mut self) -> u64 {
         self.seed = self.seed.wrapping_add(1);
         self.seed
     }
@@ -170,7 +347,7 @@ impl Parser {
     fn consume_whitespace(&mut self) {
         while self
             .current()
-            .map_or(false, |t| t.kind == TokenKind::Whitespace)
+            .is_some_and(|t| t.kind == TokenKind::Whitespace)
         {
             self.cursor += 1;
         }
@@ -206,25 +383,25 @@ impl Parser {
     fn parse_color(&mut self) -> Expr {
         let start_span = self.current_span();
 
-        self.consume_if(|tk| *tk == TokenKind::Lbrace);
+        self.expect(TokenKind::Lbrace, "`{`");
 
         self.looking_for.push(TokenKind::Comma);
         let r = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Comma);
+        self.expect(TokenKind::Comma, "`,`");
 
         self.looking_for.push(TokenKind::Comma);
         let g = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Comma);
+        self.expect(TokenKind::Comma, "`,`");
 
         self.looking_for.push(TokenKind::Rbrace);
         let b = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Rbrace);
+        self.expect(TokenKind::Rbrace, "`}`");
 
         Expr {
             kind: ExprKind::Color(ColorExpr::new(r, g, b)),
@@ -237,7 +414,7 @@ impl Parser {
 
     fn parse_neg_expr(&mut self) -> Expr {
         let start_span = self.current_span();
-        self.consume_if(|tk| *tk == TokenKind::Minus);
+        self.expect(TokenKind::Minus, "`-`");
         let inner = self.parse_expr();
         let end = self.peak(-1);
 
@@ -253,13 +430,13 @@ impl Parser {
     fn parse_abs_expr(&mut self) -> Expr {
         let start_span = self.current_span();
 
-        self.consume_if(|tk| *tk == TokenKind::Bar);
+        self.expect(TokenKind::Bar, "`|`");
 
         self.looking_for.push(TokenKind::Bar);
         let inner = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Bar);
+        self.expect(TokenKind::Bar, "`|`");
 
         Expr {
             kind: ExprKind::Abs(AbsExpr::new(inner)),
@@ -275,13 +452,13 @@ impl Parser {
         let start_span = self.current_span();
         self.consume(); // Consume function name
 
-        self.consume_if(|tk| *tk == TokenKind::Lparen);
+        self.expect(TokenKind::Lparen, "`(`");
 
         self.looking_for.push(TokenKind::Rparen);
         let inner = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Rparen); // Consume ')'
+        self.expect(TokenKind::Rparen, "`)`"); // Consume ')'
 
         Expr {
             kind: kind(inner),
@@ -297,19 +474,19 @@ impl Parser {
         let start_span = self.current_span();
         self.consume(); // Consume function name
 
-        self.consume_if(|tk| *tk == TokenKind::Lparen);
+        self.expect(TokenKind::Lparen, "`(`");
 
         self.looking_for.push(TokenKind::Comma);
         let inner1 = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Comma);
+        self.expect(TokenKind::Comma, "`,`");
 
         self.looking_for.push(TokenKind::Rparen);
         let inner2 = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Rparen); // Consume ')'
+        self.expect(TokenKind::Rparen, "`)`"); // Consume ')'
 
         Expr {
             kind: kind(inner1, inner2),
@@ -320,6 +497,36 @@ impl Parser {
         }
     }
 
+    /// Parse function with three arguments
+    fn parse_function3(&mut self, kind: impl FnOnce(Expr, Expr, Expr) -> ExprKind) -> Expr {
+        let start_span = self.current_span();
+        self.consume(); // Consume function name
+
+        self.expect(TokenKind::Lparen, "`(`");
+
+        self.looking_for.push(TokenKind::Comma);
+        let inner1 = self.parse_expr();
+        self.expect(TokenKind::Comma, "`,`");
+        let inner2 = self.parse_expr();
+        self.looking_for.pop();
+
+        self.expect(TokenKind::Comma, "`,`");
+
+        self.looking_for.push(TokenKind::Rparen);
+        let inner3 = self.parse_expr();
+        self.looking_for.pop();
+
+        self.expect(TokenKind::Rparen, "`)`"); // Consume ')'
+
+        Expr {
+            kind: kind(inner1, inner2, inner3),
+            span: Span {
+                start: start_span.start,
+                end: self.current_span().end,
+            },
+        }
+    }
+
     fn parse_sin_expr(&mut self) -> Expr {
         self.parse_function1(|e| ExprKind::Sin(SinExpr::new(e)))
     }
@@ -328,6 +535,83 @@ impl Parser {
         self.parse_function1(|e| ExprKind::Cos(CosExpr::new(e)))
     }
 
+    /// Parse a derivative with `parse`. Past the derivative depth limit, the derivative is read as
+    /// its operand in parentheses, as every level of nested derivatives evaluates its operand five
+    /// times more.
+    fn parse_derivative(&mut self, parse: impl FnOnce(&mut Self) -> Expr) -> Expr {
+        self.derivative_depth += 1;
+        let expr = parse(self);
+        self.derivative_depth -= 1;
+        if self.derivative_depth < self.limits.max_derivative_depth {
+            return expr;
+        }
+
+        self.exceed(Limit::Derivatives);
+        let inner = match expr.kind {
+            ExprKind::Dx(e) => e.inner,
+            ExprKind::Dy(e) => e.inner,
+            ExprKind::Normal(e) => e.inner,
+            ExprKind::Shade(e) => e.height,
+            _ => unreachable!("not a derivative"),
+        };
+        Expr {
+            kind: ExprKind::Paren(ParenExpr::new(*inner)),
+            span: expr.span,
+        }
+    }
+
+    fn parse_dx_expr(&mut self) -> Expr {
+        self.parse_derivative(|p| p.parse_function1(|e| ExprKind::Dx(DxExpr::new(e))))
+    }
+
+    fn parse_dy_expr(&mut self) -> Expr {
+        self.parse_derivative(|p| p.parse_function1(|e| ExprKind::Dy(DyExpr::new(e))))
+    }
+
+    fn parse_normal_expr(&mut self) -> Expr {
+        self.parse_derivative(|p| p.parse_function1(|e| ExprKind::Normal(NormalExpr::new(e))))
+    }
+
+    fn parse_shade_expr(&mut self) -> Expr {
+        self.parse_derivative(|p| {
+            let start_span = p.current_span();
+            p.consume(); // Consume function name
+
+            p.expect(TokenKind::Lparen, "`(`");
+
+            p.looking_for.push(TokenKind::Comma);
+            let height = p.parse_expr();
+            p.looking_for.pop();
+
+            p.expect(TokenKind::Comma, "`,`");
+
+            p.looking_for.push(TokenKind::Rparen);
+
+            // The elevation is optional, so a comma ends the light as well.
+            let outer = p.optional_argument.replace(p.looking_for.len());
+            let light = p.parse_expr();
+            p.optional_argument = outer;
+            let elevation = p
+                .consume_if(|tk| *tk == TokenKind::Comma)
+                .map(|_| p.parse_expr());
+            p.looking_for.pop();
+
+            p.expect(TokenKind::Rparen, "`)`"); // Consume ')'
+
+            Expr {
+                kind: ExprKind::Shade(ShadeExpr::new(height, light, elevation)),
+                span: Span {
+                    start: start_span.start,
+                    end: p.current_span().end,
+                },
+            }
+        })
+    }
+
+    fn parse_tex_expr(&mut self) -> Expr {
+        self.parse_function3(|n, u, v| ExprKind::Tex(TexExpr::new(n, u, v)))
+    }
+
     fn parse_translate_x_expr(&mut self) -> Expr {
         self.parse_function2(|x, y| ExprKind::TransX(TransXExpr::new(x, y)))
     }
@@ -339,26 +623,26 @@ impl Parser {
     fn parse_if_expr(&mut self) -> Expr {
         let start_span = self.current_span();
 
-        self.consume_if(|tk| *tk == TokenKind::If);
+        self.expect(TokenKind::If, "`if`");
 
         self.looking_for.push(TokenKind::Then);
         self.not_number += 1;
         let cond = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Then);
+        self.expect(TokenKind::Then, "`then`");
 
         self.looking_for.push(TokenKind::Else);
         let true_expr = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Else);
+        self.expect(TokenKind::Else, "`else`");
 
         self.looking_for.push(TokenKind::End);
         let false_expr = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::End);
+        self.expect(TokenKind::End, "`end`");
 
         Expr {
             kind: ExprKind::If(IfExpr::new(cond, true_expr, false_expr)),
@@ -372,13 +656,13 @@ impl Parser {
     fn parse_parenthesized_expr(&mut self) -> Expr {
         let start_span = self.current_span();
 
-        self.consume_if(|tk| *tk == TokenKind::Lparen);
+        self.expect(TokenKind::Lparen, "`(`");
 
         self.looking_for.push(TokenKind::Rparen);
         let inner = self.parse_expr();
         self.looking_for.pop();
 
-        self.consume_if(|tk| *tk == TokenKind::Rparen);
+        self.expect(TokenKind::Rparen, "`)`");
 
         Expr {
             kind: ExprKind::Paren(ParenExpr::new(inner)),
@@ -391,19 +675,24 @@ impl Parser {
 
     fn parse_cornelia(&mut self) -> Expr {
         let start_span = self.current_span();
+        let mut end = start_span.end;
 
-        let len = "Cornelia".len();
-
-        for _ in 0..len {
-            self.consume();
+        // The caller has checked that the next tokens spell out the name, one letter each.
+        for _ in 0.."Cornelia".len() {
+            if let Some(token) = self.consume() {
+                end = token.span.end;
+            }
         }
 
         let span = Span {
             start: start_span.start,
-            end: start_span.start + len,
+            end,
         };
 
-        cornelia::cornelia_expr(span)
+        // Cornelia is not made of parsed children, so its depth is reported here.
+        let expr = cornelia::cornelia_expr(span);
+        self.child_depth = expr.depth() - 1;
+        expr
     }
 
     fn is_done(&mut self) -> bool {
@@ -411,7 +700,53 @@ impl Parser {
         self.cursor >= self.tokens.len()
     }
 
+    /// Parse the next token as an expression without children. Used where the expression would
+    /// nest deeper than allowed.
+    fn parse_leaf(&mut self) -> Expr {
+        self.exceed(Limit::Depth);
+        let span = self.current_span();
+        let expr = |kind: ExprKind| Expr {
+            kind,
+            span: span.clone(),
+        };
+
+        let (n, f) = match self.consume().map(|t| t.kind) {
+            Some(TokenKind::X) => return expr(ExprKind::X),
+            Some(TokenKind::Y) => return expr(ExprKind::Y),
+            Some(TokenKind::R) => return expr(ExprKind::R),
+            Some(TokenKind::A) => return expr(ExprKind::A),
+            Some(TokenKind::Number(n)) => return expr(ExprKind::Number(n)),
+            Some(TokenKind::Other('p')) if self.filter => {
+                return expr(ExprKind::Pixel(PixelExpr::default()))
+            }
+            Some(kind) => (kind.as_usize() as u64, kind.as_f64()),
+            None => (self.seed(), (((self.seed() % 100) as f64) / 100.0) % 1.0),
+        };
+
+        choice! { n.wrapping_add(self.seed()),
+            1 => expr(ExprKind::X),
+            1 => expr(ExprKind::Y),
+            1 => expr(ExprKind::R),
+            1 => expr(ExprKind::A),
+            1 => expr(ExprKind::Number(f)),
+        }
+    }
+
+    /// Parse a primary expression along with its depth.
+    fn parse_primary(&mut self) -> (Expr, usize) {
+        let outer = std::mem::take(&mut self.child_depth);
+        let expr = self.parse_primary_expr();
+        let depth = self.child_depth + 1;
+        self.child_depth = outer;
+        (expr, depth)
+    }
+
     fn parse_primary_expr(&mut self) -> Expr {
+        // Children would be deeper than the limit.
+        if self.depth + 1 >= self.limits.max_depth {
+            return self.parse_leaf();
+        }
+
         let Some(token) = self.current() else {
             return self.choose_token();
         };
@@ -454,6 +789,15 @@ impl Parser {
             TokenKind::TY => self.parse_translate_y_expr(),
             TokenKind::Sin => self.parse_sin_expr(),
             TokenKind::Cos => self.parse_cos_expr(),
+            TokenKind::DX => self.parse_dx_expr(),
+            TokenKind::DY => self.parse_dy_expr(),
+            TokenKind::Normal => self.parse_normal_expr(),
+            TokenKind::Shade => self.parse_shade_expr(),
+            TokenKind::Tex => self.parse_tex_expr(),
+            TokenKind::Other('p') if self.filter => {
+                self.consume();
+                expr(ExprKind::Pixel(PixelExpr::default()))
+            }
             TokenKind::Other('C') | TokenKind::Other('c')
                 if (
                     self.peak(1).map(|t| &t.kind),
@@ -471,7 +815,8 @@ impl Parser {
                     Some(&TokenKind::Other('l')),
                     Some(&TokenKind::Other('i')),
                     Some(&TokenKind::A),
-                ) =>
+                ) && self.depth + cornelia::cornelia_expr(Span::new(0, 0)).depth()
+                    <= self.limits.max_depth =>
             {
                 self.parse_cornelia()
             }
@@ -485,6 +830,7 @@ impl Parser {
 
     fn get_bin_op(&mut self) -> BinOp {
         let Some(token) = self.current() else {
+            self.repair(format_args!("expected an operator"));
             return self.choose_binop(0);
         };
         match &token.kind {
@@ -498,7 +844,11 @@ impl Parser {
             TokenKind::And => BinOp::And,
             TokenKind::Less => BinOp::LessThan,
             TokenKind::Greater => BinOp::GreaterThan,
-            tk => self.choose_binop(tk.as_usize()),
+            tk => {
+                let seed = tk.as_usize() as u64;
+                self.repair(format_args!("expected an operator"));
+                self.choose_binop(seed)
+            }
         }
     }
 
@@ -510,8 +860,14 @@ impl Parser {
                     return true;
                 }
 
+                if token.kind == TokenKind::Comma
+                    && self.optional_argument == Some(self.looking_for.len())
+                {
+                    return true;
+                }
+
                 if matches!(&token.kind, TokenKind::Other(_)) {
-                    return choice! { token.kind.as_usize() + seed,
+                    return choice! { (token.kind.as_usize() as u64).wrapping_add(seed),
                         1 => true,
                         3 => false,
                     };
@@ -521,19 +877,25 @@ impl Parser {
         false
     }
 
-    /// Parse a binary expression.
+    /// Parse a binary expression with an operator-precedence parser, starting from its left operand
+    /// if it was already parsed. Operands are passed along with their depth, and the depth of the
+    /// expression is returned.
     /// https://en.wikipedia.org/wiki/Operator-precedence_parser
-    fn parse_binary_expr(&mut self, left: Option<Expr>, min_precedence: u8) -> Expr {
-        let mut left = match left {
-            Some(e) => e,
-            None => self.parse_primary_expr(),
+    fn parse_binary_expr(
+        &mut self,
+        left: Option<(Expr, usize)>,
+        min_precedence: u8,
+    ) -> (Expr, usize) {
+        let (mut left, mut left_depth) = match left {
+            Some(left) => left,
+            None => self.parse_primary(),
         };
 
         let start_span = left.span.clone();
 
         while !self.is_done() {
             if self.is_at_interest() {
-                return left;
+                return (left, left_depth);
             }
 
             let op = self.get_bin_op();
@@ -542,34 +904,21 @@ impl Parser {
                 break;
             }
 
-            self.consume(); // Consume operator
-
-            let mut right = self.parse_primary_expr();
-
-            while !self.is_done() {
-                if self.is_at_interest() {
-                    let span = Span::from_spans(&start_span, &right.span);
-                    return Expr {
-                        kind: ExprKind::Bin(BinExpr {
-                            op,
-                            lhs: Box::new(left),
-                            rhs: Box::new(right),
-                        }),
-                        span,
-                    };
-                }
+            // The chain stops growing at the depth limit, leaving the rest of the tokens to the
+            // expressions around it.
+            if self.depth + left_depth + 1 > self.limits.max_depth {
+                self.exceed(Limit::Depth);
+                break;
+            }
 
-                let right_op = self.get_bin_op();
-                if right_op.precedence() <= op.precedence() {
-                    break;
-                }
-                right = self.parse_binary_expr(Some(right), right_op.precedence());
+            self.consume(); // Consume operator
 
-                if self.is_at_interest() {
-                    break;
-                }
-            }
+            // The right operand is a child of the binary expression.
+            self.depth += 1;
+            let (right, right_depth, done) = self.parse_right_operand(op.precedence());
+            self.depth -= 1;
 
+            left_depth = left_depth.max(right_depth) + 1;
             let span = Span::from_spans(&start_span, &right.span);
             left = Expr {
                 kind: ExprKind::Bin(BinExpr {
@@ -579,12 +928,65 @@ impl Parser {
                 }),
                 span,
             };
+
+            if done {
+                return (left, left_depth);
+            }
+        }
+
+        (left, left_depth)
+    }
+
+    /// Parse the right operand of a binary operator, including the operators that bind tighter
+    /// than it. Also returns the depth of the operand, and whether the parser reached a token it
+    /// is looking for.
+    fn parse_right_operand(&mut self, precedence: u8) -> (Expr, usize, bool) {
+        let mut right = self.parse_primary();
+
+        while !self.is_done() {
+            if self.is_at_interest() {
+                return (right.0, right.1, true);
+            }
+
+            let right_op = self.get_bin_op();
+            if right_op.precedence() <= precedence {
+                break;
+            }
+            // At the depth limit the operator is left to the expressions around this one.
+            if self.depth + right.1 + 1 > self.limits.max_depth {
+                self.exceed(Limit::Depth);
+                break;
+            }
+            right = self.parse_binary_expr(Some(right), right_op.precedence());
+
+            if self.is_at_interest() {
+                break;
+            }
         }
 
-        left
+        (right.0, right.1, false)
+    }
+
+    /// Parse the tokens into an expression within the limits of the parser.
+    pub fn parse(&mut self) -> Expr {
+        if self.tokens.len() > self.limits.max_tokens {
+            self.exceed(Limit::Tokens);
+        }
+        self.tokens.truncate(self.limits.max_tokens);
+        let expr = self.parse_expr();
+        if self.strict && self.error.is_none() {
+            if let Some(limit) = self.limits.exceeded_by(&expr) {
+                self.exceed(limit);
+            }
+        }
+        self.limits.apply(expr)
     }
 
     pub fn parse_expr(&mut self) -> Expr {
-        self.parse_binary_expr(None, 0)
+        self.depth += 1;
+        let (expr, depth) = self.parse_binary_expr(None, 0);
+        self.depth -= 1;
+        self.child_depth = self.child_depth.max(depth);
+        expr
     }
 }
@@ -0,0 +1,183 @@
+//! Options that control how source is parsed, and when it is parsed again. The forgiving parser
+//! turns some inputs into constant expressions, which render as a single color. Those are parsed
+//! again with other choices until the expression is worth looking at.
+
+use std::fmt::{self, Debug, Display, Formatter};
+
+use crate::compiler::{
+    ast::{Expr, ExprKind},
+    evaluator,
+    limits::Limits,
+    version::LanguageVersion,
+};
+
+/// Number of times a source is parsed at most when no other number is given.
+pub const MAX_TRIES: usize = 100;
+
+/// Width and height of the grid of points that [ConstantTest::Sampled] evaluates when no other
+/// size is given.
+pub const SAMPLE_GRID: u32 = 8;
+
+/// How expressions are found to be constant.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConstantTest {
+    /// Expressions are constant when they fold to a constant.
+    #[default]
+    Folded,
+    /// Expressions are also constant when the colors evaluated on a grid of points over the image
+    /// differ by less than `tolerance` in every channel, where channels range from 0 to 1.
+    /// Expressions that read textures or the source pixel are only tested by folding, since their
+    /// images are not bound while parsing.
+    Sampled {
+        /// Width and height of the grid of points.
+        grid: u32,
+        /// Largest difference of a channel that is still constant.
+        tolerance: f64,
+    },
+}
+
+/// Why a source is parsed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryReason {
+    /// The expression folds to a constant.
+    Constant,
+    /// The colors of the expression barely change over the image.
+    NearConstant,
+    /// The expression is a filter that does not read the source pixel.
+    IgnoresPixel,
+}
+
+impl Display for RetryReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RetryReason::Constant => write!(f, "Expression returned constant"),
+            RetryReason::NearConstant => write!(f, "Expression is nearly constant"),
+            RetryReason::IgnoresPixel => write!(f, "Filter does not read the source pixel"),
+        }
+    }
+}
+
+/// How source is parsed. The default options parse a program of the latest version the way the
+/// command line does.
+#[derive(Clone, Copy)]
+pub struct ParseOptions<'a> {
+    /// Version of the language whose keywords the source is lexed with.
+    pub version: LanguageVersion,
+    /// Budgets the expression is kept within.
+    pub limits: Limits,
+    /// Seed for the choices made where the source is repaired. Seed 0 gives the usual expression.
+    pub seed: u64,
+    /// Parse the source as a filter, where `p` is the color of the source pixel. Filters are
+    /// parsed again until they read the source pixel.
+    pub filter: bool,
+    /// Number of times the source is parsed at most. With 1, the source is never parsed again.
+    pub max_tries: usize,
+    /// How expressions are found to be constant.
+    pub constant_test: ConstantTest,
+    /// Called with the number of the retry and its reason before the source is parsed again.
+    pub on_retry: Option<&'a dyn Fn(usize, RetryReason)>,
+    /// Reject sources that would be repaired or truncated, instead of repairing them. Only the
+    /// `try_*` functions report what was rejected, the others ignore this. Strict sources are
+    /// parsed once, since they have no repairs where other choices could be made.
+    pub strict: bool,
+}
+
+impl Default for ParseOptions<'_> {
+    fn default() -> Self {
+        Self {
+            version: LanguageVersion::LATEST,
+            limits: Limits::DEFAULT,
+            seed: 0,
+            filter: false,
+            max_tries: MAX_TRIES,
+            constant_test: ConstantTest::Folded,
+            on_retry: None,
+            strict: false,
+        }
+    }
+}
+
+impl Debug for ParseOptions<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ParseOptions")
+            .field("version", &self.version)
+            .field("limits", &self.limits)
+            .field("seed", &self.seed)
+            .field("filter", &self.filter)
+            .field("max_tries", &self.max_tries)
+            .field("constant_test", &self.constant_test)
+            .field("strict", &self.strict)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ParseOptions<'_> {
+    /// The options that a version of the language parses sources with. [LanguageVersion::V1]
+    /// parsed every source once, and only kept it within the depth limit.
+    pub fn with_version(version: LanguageVersion) -> Self {
+        match version {
+            LanguageVersion::V1 => Self {
+                version,
+                limits: Limits::UNLIMITED,
+                max_tries: 1,
+                ..Self::default()
+            },
+            LanguageVersion::V2 => Self {
+                version,
+                ..Self::default()
+            },
+        }
+    }
+
+    /// Why an expression with folded constants has to be parsed again, if it has to.
+    pub(crate) fn retry_reason(&self, expr: &Expr) -> Option<RetryReason> {
+        if expr.is_constant() {
+            return Some(RetryReason::Constant);
+        }
+        // A filter has to look at the image it is filtering.
+        if self.filter && !expr.uses_pixel() {
+            return Some(RetryReason::IgnoresPixel);
+        }
+        match self.constant_test {
+            ConstantTest::Sampled { grid, tolerance }
+                if !reads_images(expr) && is_near_constant(expr, grid, tolerance, self.version) =>
+            {
+                Some(RetryReason::NearConstant)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether an expression reads a texture or the source pixel anywhere.
+fn reads_images(expr: &Expr) -> bool {
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        if let ExprKind::Tex(_) | ExprKind::Pixel(_) = expr.kind {
+            return true;
+        }
+        stack.extend(expr.children());
+    }
+    false
+}
+
+/// Whether the colors of an expression on a grid of points over the image differ by less than the
+/// tolerance in every channel.
+fn is_near_constant(expr: &Expr, grid: u32, tolerance: f64, version: LanguageVersion) -> bool {
+    let grid = grid.max(1);
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [f64::NEG_INFINITY; 3];
+    for i in 0..grid {
+        for j in 0..grid {
+            // The centers of the cells of the grid, in normalized coordinates.
+            let x = (i as f64 + 0.5) / grid as f64 * 2.0 - 1.0;
+            let y = (j as f64 + 0.5) / grid as f64 * 2.0 - 1.0;
+            let c = evaluator::eval_with_version(expr, x, y, version);
+            for (k, channel) in [c.r, c.g, c.b].into_iter().enumerate() {
+                min[k] = min[k].min(channel);
+                max[k] = max[k].max(channel);
+            }
+        }
+    }
+    (0..3).all(|k| max[k] - min[k] < tolerance)
+}
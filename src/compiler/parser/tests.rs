@@ -0,0 +1,376 @@
+use std::cell::RefCell;
+
+use super::{
+    parse_filter, parse_source, parse_source_with_limits, parse_source_with_options,
+    parse_source_with_seed, parse_source_with_version, parse_tokens, start_seed,
+    try_parse_source_with_options, ConstantTest, ParseOptions, RetryReason, MAX_TRIES,
+};
+use crate::{
+    compiler::{
+        ast::ExprKind,
+        constant_evaluator, evaluator,
+        lexer::{self, TokenKind},
+        limits::{Limit, Limits, MAX_DEPTH},
+        version::LanguageVersion,
+    },
+    error::{Error, ParseError},
+};
+
+/// Parse a source with a depth limit and no other limits.
+fn parse_source_with_max_depth(source: String, max_depth: usize) -> crate::compiler::ast::Expr {
+    let limits = Limits {
+        max_depth,
+        ..Limits::UNLIMITED
+    };
+    parse_source_with_limits(source, limits)
+}
+
+/// Sources that nest or chain expressions `n` times.
+fn deep_sources(n: usize) -> Vec<String> {
+    vec![
+        "(".repeat(n) + "x",
+        "-".repeat(n) + "x",
+        "sin(".repeat(n) + "x",
+        "x+".repeat(n) + "x",
+        "x^".repeat(n) + "x",
+        "x+y*".repeat(n) + "x",
+        "{x,".repeat(n) + "x",
+        "?x:".repeat(n) + "x",
+        "x+y*z".repeat(n),
+        "x<y+z*x^y".repeat(n),
+    ]
+}
+
+#[test]
+fn deep_sources_stay_within_the_depth_limit() {
+    for source in deep_sources(100_000) {
+        let expr = parse_source(source.clone());
+        assert!(expr.depth() <= MAX_DEPTH, "{source:.20}: {}", expr.depth());
+
+        let expr = constant_evaluator::evaluate_constants(expr);
+        evaluator::eval(&expr, 0.5, -0.5);
+    }
+}
+
+#[test]
+fn depth_limits_are_configurable() {
+    for max_depth in [1, 2, 8, 100] {
+        for source in deep_sources(1000) {
+            let expr = parse_source_with_max_depth(source.clone(), max_depth);
+            assert!(expr.depth() <= max_depth, "{source:.20}: {}", expr.depth());
+        }
+    }
+}
+
+#[test]
+fn depth_limits_are_deterministic() {
+    for source in deep_sources(2000) {
+        let a = parse_source_with_max_depth(source.clone(), 32);
+        let b = parse_source_with_max_depth(source, 32);
+        assert_eq!(a.to_string(), b.to_string());
+    }
+}
+
+#[test]
+fn shallow_sources_are_not_limited() {
+    let source = "x+".repeat(100) + "x";
+    assert_eq!(
+        parse_source(source.clone()).to_string(),
+        parse_source_with_max_depth(source, usize::MAX).to_string()
+    );
+}
+
+#[test]
+fn seed_zero_is_the_default() {
+    for source in ["x + y", "Hello, I am a random string!", "if ?? then", ""] {
+        assert_eq!(parse_source_with_seed(source, 0), parse_source(source));
+    }
+}
+
+#[test]
+fn seeds_give_variations() {
+    let source = "Hello, I am a random string!";
+    let exprs: Vec<_> = (0..20)
+        .map(|seed| parse_source_with_seed(source, seed).to_string())
+        .collect();
+    for (seed, expr) in exprs.iter().enumerate() {
+        assert_eq!(
+            *expr,
+            parse_source_with_seed(source, seed as u64).to_string()
+        );
+    }
+
+    let mut distinct = exprs.clone();
+    distinct.sort();
+    distinct.dedup();
+    assert!(distinct.len() > 15, "{distinct:?}");
+}
+
+#[test]
+fn seeds_do_not_change_programs_without_choices() {
+    for seed in [1, 2, u64::MAX] {
+        assert_eq!(
+            parse_source_with_seed("x * sin(y)", seed),
+            parse_source("x * sin(y)")
+        );
+    }
+}
+
+#[test]
+fn large_seeds_wrap_around() {
+    let lexer = lexer::Lexer::new("?!?");
+    let source = lexer.source();
+    let tokens: Vec<_> = lexer.collect();
+    for seed in [u64::MAX, u64::MAX / 3, 1 << 63] {
+        let options = ParseOptions {
+            seed,
+            ..ParseOptions::default()
+        };
+        let parse = || parse_tokens(tokens.clone(), source.clone(), &options);
+        assert_eq!(parse(), parse());
+    }
+}
+
+#[test]
+fn seeds_are_mixed_in_full() {
+    for retry in 0..MAX_TRIES {
+        assert_eq!(start_seed(0, retry), retry as u64);
+    }
+    // The counter has 64 bits everywhere, so seeds that only differ in their high bits differ on
+    // every platform.
+    for seed in [1 << 32, 1 << 40, u64::MAX] {
+        assert_ne!(start_seed(seed, 0), start_seed(0, 0), "{seed}");
+    }
+}
+
+/// Parse a source with the given options, returning the expression and every retry.
+fn parse_with_retries(
+    source: &str,
+    options: ParseOptions,
+) -> (crate::compiler::ast::Expr, Vec<(usize, RetryReason)>) {
+    let retries = RefCell::new(Vec::new());
+    let on_retry = |retry, reason| retries.borrow_mut().push((retry, reason));
+    let options = ParseOptions {
+        on_retry: Some(&on_retry),
+        ..options
+    };
+    let expr = parse_source_with_options(source, &options);
+    (expr, retries.into_inner())
+}
+
+#[test]
+fn constant_sources_are_parsed_again() {
+    let (expr, retries) = parse_with_retries("12", ParseOptions::default());
+    assert_eq!(retries, [(1, RetryReason::Constant)]);
+    assert_eq!(expr, parse_source("12"));
+    assert!(!constant_evaluator::evaluate_constants(expr).is_constant());
+
+    let once = ParseOptions {
+        max_tries: 1,
+        ..ParseOptions::default()
+    };
+    let (expr, retries) = parse_with_retries("12", once);
+    assert!(retries.is_empty());
+    assert!(constant_evaluator::evaluate_constants(expr).is_constant());
+}
+
+#[test]
+fn the_first_version_parses_sources_once() {
+    let v1 = parse_source_with_version("12", LanguageVersion::V1);
+    assert!(constant_evaluator::evaluate_constants(v1).is_constant());
+    let latest = parse_source_with_version("12", LanguageVersion::LATEST);
+    assert_eq!(latest, parse_source("12"));
+}
+
+#[test]
+fn retries_stop_at_the_maximum() {
+    let options = ParseOptions {
+        max_tries: 4,
+        ..ParseOptions::default()
+    };
+    let (_, retries) = parse_with_retries("1.0", options);
+    let expected: Vec<_> = (1..4).map(|i| (i, RetryReason::Constant)).collect();
+    assert_eq!(retries, expected);
+}
+
+#[test]
+fn filters_are_parsed_again_until_they_read_the_pixel() {
+    let options = ParseOptions {
+        filter: true,
+        max_tries: 3,
+        ..ParseOptions::default()
+    };
+    let (_, retries) = parse_with_retries("x * y", options);
+    assert_eq!(retries[0], (1, RetryReason::IgnoresPixel));
+    assert!(parse_filter("x * y + p").uses_pixel());
+}
+
+#[test]
+fn near_constant_sources_are_found_by_sampling() {
+    let sampled = |tolerance| ParseOptions {
+        max_tries: 2,
+        constant_test: ConstantTest::Sampled { grid: 4, tolerance },
+        ..ParseOptions::default()
+    };
+
+    let (_, retries) = parse_with_retries("x * 0.001", sampled(0.05));
+    assert_eq!(retries, [(1, RetryReason::NearConstant)]);
+
+    for source in ["x * 0.5", "tex(0.0, x * 0.001, y)"] {
+        let (_, retries) = parse_with_retries(source, sampled(0.05));
+        assert!(retries.is_empty(), "{source}");
+    }
+    let (_, retries) = parse_with_retries("x * 0.001", ParseOptions::default());
+    assert!(retries.is_empty());
+}
+
+#[test]
+fn keywords_are_whole_words() {
+    let kinds = |source| -> Vec<_> { lexer::Lexer::new(source).map(|t| t.kind).collect() };
+    assert_eq!(kinds("tex(")[0], TokenKind::Tex);
+    assert_eq!(kinds("(shade)")[1], TokenKind::Shade);
+    assert_eq!(kinds("x+dy(x)")[2], TokenKind::DY);
+    for source in [
+        "text", "context", "shaded", "abnormal", "tex2", "index", "dye", "dxf",
+    ] {
+        assert!(
+            kinds(source).iter().all(|k| !matches!(
+                k,
+                TokenKind::Tex
+                    | TokenKind::Shade
+                    | TokenKind::Normal
+                    | TokenKind::DX
+                    | TokenKind::DY
+            )),
+            "{source}"
+        );
+    }
+}
+
+#[test]
+fn builtins_only_in_v2() {
+    let parse = |version| parse_source_with_version("shade(x, y, r)", version).kind;
+    assert!(!matches!(parse(LanguageVersion::V1), ExprKind::Shade(_)));
+    match parse(LanguageVersion::V2) {
+        ExprKind::Shade(e) => assert_eq!(e.elevation.map(|e| e.kind), Some(ExprKind::R)),
+        kind => panic!("{kind:?}"),
+    }
+
+    let v1 = lexer::Lexer::with_version("dx normal tex", LanguageVersion::V1);
+    assert!(v1.map(|t| t.kind).all(|k| matches!(
+        k,
+        TokenKind::Other(_) | TokenKind::X | TokenKind::R | TokenKind::A | TokenKind::Whitespace
+    )));
+}
+
+/// Parse a source strictly with the given limits.
+fn parse_strict(source: &str, limits: Limits) -> Result<crate::compiler::ast::Expr, Error> {
+    let options = ParseOptions {
+        limits,
+        strict: true,
+        ..ParseOptions::default()
+    };
+    try_parse_source_with_options(source, &options)
+}
+
+#[test]
+fn strict_parses_accept_well_formed_sources() {
+    let sources = [
+        "x + y * r",
+        "if x < y then {x, y, r} else -|sin(a)| end",
+        "tex(1.0, x, y) + shade(dx(x), normal(y))",
+        "shade(x * y, a, 0.1)",
+        "Cornelia",
+        "2.0 * (x - y) % 3.0 ^ 2.0",
+    ];
+    for source in sources {
+        let expr = parse_strict(source, Limits::DEFAULT).expect(source);
+        assert_eq!(expr, parse_source(source), "{source}");
+    }
+}
+
+#[test]
+fn strict_parses_reject_repairs() {
+    let error = |source| match parse_strict(source, Limits::DEFAULT) {
+        Err(Error::Parse(e)) => e,
+        other => panic!("{source}: {other:?}"),
+    };
+
+    assert_eq!(
+        error("x+ (y*r"),
+        ParseError {
+            span: 7..7,
+            message: "expected `)`".to_string(),
+        }
+    );
+    assert_eq!(error("x y").span, 2..3);
+    assert_eq!(error("x y").message, "expected an operator");
+    assert_eq!(error("x + ?").message, "expected an expression");
+    assert_eq!(error("sin x").message, "expected `(`");
+    assert_eq!(error("if x then y").message, "expected `else`");
+    // Numbers have a dot, so digits alone are not an expression.
+    assert_eq!(error("x + 12").message, "expected an expression");
+    // Outside of filters, `p` has no meaning.
+    assert_eq!(error("x + p").span, 4..5);
+}
+
+#[test]
+fn strict_parses_reject_truncation() {
+    let exceeded = |source, limits| match parse_strict(source, limits) {
+        Err(Error::LimitExceeded { limit, max }) => (limit, max),
+        other => panic!("{source}: {other:?}"),
+    };
+    let limits = |max_tokens, max_nodes, max_depth, max_ops| Limits {
+        max_tokens,
+        max_nodes,
+        max_depth,
+        max_ops,
+        ..Limits::UNLIMITED
+    };
+
+    let source = "x + y + r";
+    assert_eq!(
+        exceeded(source, limits(3, 100, 100, 100)),
+        (Limit::Tokens, 3)
+    );
+    assert_eq!(
+        exceeded(source, limits(100, 4, 100, 100)),
+        (Limit::Nodes, 4)
+    );
+    assert_eq!(
+        exceeded(source, limits(100, 100, 2, 100)),
+        (Limit::Depth, 2)
+    );
+    assert_eq!(exceeded(source, limits(100, 100, 100, 3)), (Limit::Ops, 3));
+    assert!(parse_strict(source, limits(9, 5, 4, 100)).is_ok());
+    assert_eq!(
+        exceeded("((((x))))", limits(100, 100, 3, 100)),
+        (Limit::Depth, 3)
+    );
+    let shallow = Limits {
+        max_derivative_depth: 1,
+        ..Limits::UNLIMITED
+    };
+    assert_eq!(exceeded("dx(dy(x))", shallow), (Limit::Derivatives, 1));
+    assert!(parse_strict("dx(x) + dy(x)", shallow).is_ok());
+}
+
+#[test]
+fn strict_parses_are_not_parsed_again() {
+    let retries = RefCell::new(0);
+    let on_retry = |_, _| *retries.borrow_mut() += 1;
+    let strict = ParseOptions {
+        strict: true,
+        on_retry: Some(&on_retry),
+        ..ParseOptions::default()
+    };
+    let expr = try_parse_source_with_options("1.2", &strict).unwrap();
+    assert_eq!(*retries.borrow(), 0);
+    assert!(constant_evaluator::evaluate_constants(expr).is_constant());
+
+    // Functions that can not fail ignore strictness.
+    assert_eq!(
+        parse_source_with_options("x y", &strict),
+        parse_source("x y")
+    );
+}
@@ -0,0 +1,286 @@
+//! Rule-based algebraic simplifier. Rewrites expressions into simpler expressions that evaluate to
+//! the same image. Constant folding is left to the [constant_evaluator].
+
+mod tests;
+
+use super::{
+    ast::{AbsExpr, BinExpr, BinOp, ColorExpr, CosExpr, Expr, ExprKind, NegExpr, SinExpr},
+    ast::{TransXExpr, TransYExpr, ValueType},
+    constant_evaluator,
+};
+
+/// Maximum number of times the rules are applied to the whole expression.
+const MAX_PASSES: usize = 32;
+
+/// A rewrite rule. Returns [None] if the rule does not apply to the expression.
+type Rule = fn(&Expr) -> Option<Expr>;
+
+/// All rewrite rules. They are tried in order on every node of the expression.
+const RULES: &[Rule] = &[
+    flatten_paren,
+    double_negation,
+    negate_constant,
+    subtract_self,
+    nested_abs,
+    sin_of_negation,
+    cos_of_negation,
+    independent_trans_x,
+    independent_trans_y,
+    nested_trans_x,
+    nested_trans_y,
+];
+
+/// Simplify an expression by applying all rewrite rules and constant folding until the
+/// expression no longer changes. The simplified expression renders the same image.
+pub fn simplify(expr: Expr) -> Expr {
+    let mut expr = constant_evaluator::evaluate_constants(expr);
+    for _ in 0..MAX_PASSES {
+        let mut changed = false;
+        expr = rewrite(expr, &mut changed);
+        if !changed {
+            break;
+        }
+        expr = constant_evaluator::evaluate_constants(expr);
+    }
+    expr
+}
+
+/// Rewrite an expression bottom-up, setting `changed` if any rule was applied.
+fn rewrite(expr: Expr, changed: &mut bool) -> Expr {
+    expr.transform_up(|mut expr| {
+        while let Some(e) = RULES.iter().find_map(|rule| rule(&expr)) {
+            *changed = true;
+            expr = e;
+        }
+        expr
+    })
+}
+
+/// Create an expression with the span of another expression.
+fn with_span(expr: &Expr, kind: ExprKind) -> Expr {
+    Expr {
+        kind,
+        span: expr.span.clone(),
+    }
+}
+
+/// `(e) => e`
+fn flatten_paren(expr: &Expr) -> Option<Expr> {
+    match &expr.kind {
+        ExprKind::Paren(e) => Some(*e.inner.clone()),
+        _ => None,
+    }
+}
+
+/// `--e => e`
+///
+/// Negating a boolean produces a number, so booleans are left alone.
+fn double_negation(expr: &Expr) -> Option<Expr> {
+    let ExprKind::Neg(NegExpr { inner }) = &expr.kind else {
+        return None;
+    };
+    match &inner.kind {
+        ExprKind::Neg(NegExpr { inner })
+            if matches!(
+                inner.value_type(),
+                Some(ValueType::Number | ValueType::Color)
+            ) =>
+        {
+            Some(*inner.clone())
+        }
+        _ => None,
+    }
+}
+
+/// `-1.0 => -1.0` and `-{1.0, 2.0, 3.0} => {-1.0, -2.0, -3.0}`
+fn negate_constant(expr: &Expr) -> Option<Expr> {
+    let ExprKind::Neg(NegExpr { inner }) = &expr.kind else {
+        return None;
+    };
+    let negate = |e: &Expr| match e.kind {
+        ExprKind::Number(n) => Some(with_span(e, ExprKind::Number(-n))),
+        _ => None,
+    };
+    let kind = match &inner.kind {
+        ExprKind::Number(n) => ExprKind::Number(-n),
+        ExprKind::Color(ColorExpr { r, g, b }) => {
+            ExprKind::Color(ColorExpr::new(negate(r)?, negate(g)?, negate(b)?))
+        }
+        _ => return None,
+    };
+    Some(with_span(expr, kind))
+}
+
+/// `e - e => 0.0`
+///
+/// Only applies to numbers, as subtracting two colors produces a color.
+fn subtract_self(expr: &Expr) -> Option<Expr> {
+    match &expr.kind {
+        ExprKind::Bin(BinExpr {
+            op: BinOp::Sub,
+            lhs,
+            rhs,
+        }) if lhs == rhs && lhs.value_type() == Some(ValueType::Number) => {
+            Some(with_span(expr, ExprKind::Number(0.0)))
+        }
+        _ => None,
+    }
+}
+
+/// `||e|| => |e|` and `|-e| => |e|`
+fn nested_abs(expr: &Expr) -> Option<Expr> {
+    let ExprKind::Abs(AbsExpr { inner }) = &expr.kind else {
+        return None;
+    };
+    match &inner.kind {
+        ExprKind::Abs(_) => Some(*inner.clone()),
+        ExprKind::Neg(NegExpr { inner }) => {
+            Some(with_span(expr, ExprKind::Abs(AbsExpr::new(*inner.clone()))))
+        }
+        _ => None,
+    }
+}
+
+/// Check if an expression can never evaluate to an infinite value.
+fn is_finite(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Number(n) => n.is_finite(),
+        ExprKind::X | ExprKind::Y | ExprKind::R | ExprKind::A => true,
+        // NaN is turned into zero, so trigonometric functions are always finite.
+        ExprKind::Sin(_) | ExprKind::Cos(_) => true,
+        ExprKind::Dx(_) | ExprKind::Dy(_) => false,
+        // Normals have unit length and shading is bounded.
+        ExprKind::Normal(_) | ExprKind::Shade(_) => true,
+        ExprKind::Tex(_) | ExprKind::Pixel(_) => true,
+        ExprKind::Bin(e) => matches!(e.op, BinOp::LessThan | BinOp::GreaterThan),
+        ExprKind::Paren(e) => is_finite(&e.inner),
+        ExprKind::Neg(e) => is_finite(&e.inner),
+        ExprKind::Abs(e) => is_finite(&e.inner),
+        ExprKind::TransX(e) => is_finite(&e.inner),
+        ExprKind::TransY(e) => is_finite(&e.inner),
+        ExprKind::If(e) => is_finite(&e.true_expr) && is_finite(&e.false_expr),
+        ExprKind::Color(e) => is_finite(&e.r) && is_finite(&e.g) && is_finite(&e.b),
+    }
+}
+
+/// `sin(-e) => -sin(e)`
+///
+/// Only applies if `e` is finite, as `sin(-inf)` is turned into `0.0` and `-sin(inf)` into `-0.0`.
+fn sin_of_negation(expr: &Expr) -> Option<Expr> {
+    let ExprKind::Sin(SinExpr { inner }) = &expr.kind else {
+        return None;
+    };
+    match &inner.kind {
+        ExprKind::Neg(NegExpr { inner }) if is_finite(inner) => {
+            let sin = with_span(expr, ExprKind::Sin(SinExpr::new(*inner.clone())));
+            Some(with_span(expr, ExprKind::Neg(NegExpr::new(sin))))
+        }
+        _ => None,
+    }
+}
+
+/// `cos(-e) => cos(e)`
+fn cos_of_negation(expr: &Expr) -> Option<Expr> {
+    let ExprKind::Cos(CosExpr { inner }) = &expr.kind else {
+        return None;
+    };
+    match &inner.kind {
+        ExprKind::Neg(NegExpr { inner }) => {
+            Some(with_span(expr, ExprKind::Cos(CosExpr::new(*inner.clone()))))
+        }
+        _ => None,
+    }
+}
+
+/// Check if an expression or any of its sub-expressions is of a kind.
+fn contains(expr: &Expr, kind: impl Fn(&ExprKind) -> bool) -> bool {
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        if kind(&expr.kind) {
+            return true;
+        }
+        stack.extend(expr.children());
+    }
+    false
+}
+
+/// Check if an expression depends on the x coordinate.
+fn depends_on_x(expr: &Expr) -> bool {
+    contains(expr, |kind| {
+        matches!(
+            kind,
+            ExprKind::X | ExprKind::R | ExprKind::A | ExprKind::Pixel(_)
+        )
+    })
+}
+
+/// Check if an expression depends on the y coordinate.
+fn depends_on_y(expr: &Expr) -> bool {
+    contains(expr, |kind| {
+        matches!(
+            kind,
+            ExprKind::Y | ExprKind::R | ExprKind::A | ExprKind::Pixel(_)
+        )
+    })
+}
+
+/// `tx(t, e) => e` when `e` does not depend on x.
+fn independent_trans_x(expr: &Expr) -> Option<Expr> {
+    match &expr.kind {
+        ExprKind::TransX(e) if !depends_on_x(&e.inner) => Some(*e.inner.clone()),
+        _ => None,
+    }
+}
+
+/// `ty(t, e) => e` when `e` does not depend on y.
+fn independent_trans_y(expr: &Expr) -> Option<Expr> {
+    match &expr.kind {
+        ExprKind::TransY(e) if !depends_on_y(&e.inner) => Some(*e.inner.clone()),
+        _ => None,
+    }
+}
+
+/// Sum two constant translation offsets, if translating by the sum is the same as translating by
+/// both.
+///
+/// Wrapping the coordinate twice only differs from wrapping it once where it reaches the edges,
+/// as `-1.0` wraps to `1.0` while `1.0` wraps to `-1.0`. Both reach the same edge when the sum
+/// stays inside `(-1, 1)` and has the sign of the inner offset.
+fn sum_offsets(outer: &Expr, inner: &Expr) -> Option<Expr> {
+    match (&outer.kind, &inner.kind) {
+        (ExprKind::Number(a), ExprKind::Number(b)) if (a + b).abs() < 1.0 && (a + b) * b > 0.0 => {
+            Some(with_span(outer, ExprKind::Number(a + b)))
+        }
+        _ => None,
+    }
+}
+
+/// `tx(a, tx(b, e)) => tx(a + b, e)` when `a` and `b` are constants that can be summed.
+fn nested_trans_x(expr: &Expr) -> Option<Expr> {
+    let ExprKind::TransX(outer) = &expr.kind else {
+        return None;
+    };
+    let ExprKind::TransX(inner) = &outer.inner.kind else {
+        return None;
+    };
+    let trans = sum_offsets(&outer.trans, &inner.trans)?;
+    Some(with_span(
+        expr,
+        ExprKind::TransX(TransXExpr::new(trans, *inner.inner.clone())),
+    ))
+}
+
+/// `ty(a, ty(b, e)) => ty(a + b, e)` when `a` and `b` are constants that can be summed.
+fn nested_trans_y(expr: &Expr) -> Option<Expr> {
+    let ExprKind::TransY(outer) = &expr.kind else {
+        return None;
+    };
+    let ExprKind::TransY(inner) = &outer.inner.kind else {
+        return None;
+    };
+    let trans = sum_offsets(&outer.trans, &inner.trans)?;
+    Some(with_span(
+        expr,
+        ExprKind::TransY(TransYExpr::new(trans, *inner.inner.clone())),
+    ))
+}
@@ -0,0 +1,50 @@
+macro_rules! test_syntax {
+    ($name:ident: $input:literal => $output:literal) => {
+        #[test]
+        fn $name() {
+            let input_expr = $crate::compiler::parser::parse_source($input.to_string());
+            let output_expr = $crate::compiler::parser::parse_source($output.to_string());
+            println!("{}: {} => {}", stringify!($name), input_expr, output_expr);
+            let input_expr = super::simplify(input_expr);
+            let output_expr = super::simplify(output_expr);
+            assert_eq!(input_expr, output_expr);
+        }
+    };
+}
+
+// Parentheses
+test_syntax!( paren_nested: "((x))"           => "x"             );
+test_syntax!( paren_bin:    "(x + y) * (r)"   => "(x + y) * r"   );
+
+// Negation
+test_syntax!( neg_double:       "--x"                    => "x"                        );
+test_syntax!( neg_quadruple:    "-(-(-(-y)))"            => "y"                        );
+test_syntax!( neg_double_bool:  "-(-(x < y))"            => "-(-(x < y))"              );
+test_syntax!( neg_number:       "-(2.0)"                 => "0.0 - 2.0"                );
+test_syntax!( neg_color:        "-{1.0, 2.0, 2.0}"       => "{0.0-1.0, 0.0-2.0, 0.0-2.0}" );
+
+// Self subtraction
+test_syntax!( sub_self_x:     "x - x"                   => "0.0"                   );
+test_syntax!( sub_self_sin:   "sin(y*2.0) - sin(y*2.0)" => "0.0"                   );
+test_syntax!( sub_self_color: "{x, y, r} - {x, y, r}"   => "{x, y, r} - {x, y, r}" );
+test_syntax!( sub_other:      "x - y"                   => "x - y"                 );
+
+// Absolute value
+test_syntax!( abs_abs: "||x||"     => "|x|"     );
+test_syntax!( abs_neg: "|-x|"      => "|x|"     );
+test_syntax!( abs_mix: "|-(|-x|)|" => "|x|"     );
+
+// Trig functions
+test_syntax!( sin_neg:        "sin(-x)"        => "-sin(x)" );
+test_syntax!( sin_neg_double: "sin(-(-(-x)))"  => "-sin(x)" );
+test_syntax!( cos_neg:        "cos(-x)"        => "cos(x)"  );
+
+// Translate operations
+test_syntax!( trans_x_nested: "tx(0.25, tx(0.5, x))"     => "tx(0.75, x)"          );
+test_syntax!( trans_y_nested: "ty(0.25, ty(0.5, ty(0.25, y)))" => "ty(0.25, ty(0.75, y))" );
+test_syntax!( trans_x_edge:   "tx(0.5, tx(0.5, x))"      => "tx(0.5, tx(0.5, x))"  );
+test_syntax!( trans_x_neg:    "tx(0.25, tx(-0.5, x))"    => "tx(0.0-0.25, x)"      );
+test_syntax!( trans_x_sign:   "tx(-0.75, tx(0.5, x))"    => "tx(0.0-0.75, tx(0.5, x))" );
+test_syntax!( trans_x_indep:  "tx(x, ty(0.5, y*2.0))"    => "ty(0.5, y*2.0)"       );
+test_syntax!( trans_y_indep:  "ty(0.25, ty(0.5, {x, 0.5, x}))" => "{x, 0.5, x}"    );
+test_syntax!( trans_x_r:      "tx(0.5, r)"               => "tx(0.5, r)"           );
@@ -0,0 +1,220 @@
+//! Statistics about an expression and about how it is evaluated while rendering, to understand why
+//! some programs render slowly. The static part counts the expressions and estimates their cost,
+//! and a [Recorder] counts what the evaluator actually does.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Formatter},
+    marker::PhantomData,
+    ops::Range,
+};
+
+use super::{
+    ast::{Expr, ExprKind, IfExpr},
+    evaluator::Observer,
+    limits::estimate_ops,
+};
+
+/// How often a conditional picked each of its branches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// Bytes of the source that the conditional was parsed from.
+    pub span: Range<usize>,
+    /// Number of evaluations that picked the `then` branch.
+    pub taken: usize,
+    /// Number of evaluations that picked the `else` branch.
+    pub not_taken: usize,
+}
+
+/// Statistics of an expression and of its renders.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of expressions of each kind, by the name of the kind, like `If` or `TransX`.
+    pub kinds: BTreeMap<&'static str, usize>,
+    /// Number of expressions, including the expression itself.
+    pub nodes: usize,
+    /// Number of expressions on the longest path from the expression down to a leaf.
+    pub depth: usize,
+    /// Operations per pixel, as estimated by [estimate_ops].
+    pub ops: usize,
+    /// Number of pixels rendered.
+    pub pixels: usize,
+    /// Number of expressions evaluated over all pixels. Pixels in tiles of a uniform color are not
+    /// evaluated at all.
+    pub evaluated: usize,
+    /// Every conditional, in the order they appear in the expression.
+    pub branches: Vec<Branch>,
+}
+
+/// Every expression in an expression with its depth, in pre-order.
+fn walk(expr: &Expr) -> Vec<(&Expr, usize)> {
+    let mut order = Vec::new();
+    let mut stack = vec![(expr, 1)];
+    while let Some((expr, depth)) = stack.pop() {
+        order.push((expr, depth));
+        stack.extend(expr.children().into_iter().rev().map(|c| (c, depth + 1)));
+    }
+    order
+}
+
+/// The statistics of an expression that are known without rendering it.
+pub fn analyze(expr: &Expr) -> Stats {
+    let mut stats = Stats {
+        ops: estimate_ops(expr),
+        ..Stats::default()
+    };
+    for (expr, depth) in walk(expr) {
+        *stats.kinds.entry(expr.kind.name()).or_default() += 1;
+        stats.nodes += 1;
+        stats.depth = stats.depth.max(depth);
+        if let ExprKind::If(_) = expr.kind {
+            stats.branches.push(Branch {
+                span: expr.span.start..expr.span.end,
+                taken: 0,
+                not_taken: 0,
+            });
+        }
+    }
+    stats
+}
+
+/// Number of points a finite difference evaluates its operand at: the point itself and four
+/// offsets.
+const DIFFERENCE_POINTS: usize = 5;
+
+/// Largest number of expressions that are evaluated for a pixel. Only one branch of every
+/// conditional is evaluated. Derivatives and lighting evaluate their operands with dual numbers,
+/// where nested derivatives, lighting and textures take finite differences.
+pub fn max_evaluated(expr: &Expr) -> usize {
+    // In reverse pre-order, the counts of the children of an expression are on top of the stack,
+    // with the first child on top. Every expression has a count for the evaluator and one for
+    // dual numbers.
+    let mut counts: Vec<(usize, usize)> = Vec::new();
+    for (expr, _) in walk(expr).into_iter().rev() {
+        let start = counts.len() - expr.children().len();
+        let children: Vec<_> = counts.drain(start..).rev().collect();
+        let sum = |count: fn(&(usize, usize)) -> usize| {
+            children
+                .iter()
+                .map(count)
+                .fold(0, |sum: usize, n| sum.saturating_add(n))
+        };
+        let differences = |count: usize| DIFFERENCE_POINTS.saturating_mul(count);
+        let (plain, dual) = match expr.kind {
+            ExprKind::If(_) => {
+                let cond = children[0].0;
+                let plain = children[1].0.max(children[2].0);
+                let dual = children[1].1.max(children[2].1);
+                (cond.saturating_add(plain), cond.saturating_add(dual))
+            }
+            ExprKind::Dx(_) | ExprKind::Dy(_) => (children[0].1, differences(children[0].1)),
+            ExprKind::Normal(_) => (children[0].1, differences(children[0].1.saturating_add(1))),
+            ExprKind::Shade(_) => {
+                let plain = children[1..]
+                    .iter()
+                    .fold(children[0].1, |sum, c| sum.saturating_add(c.0));
+                (plain, differences(plain.saturating_add(1)))
+            }
+            ExprKind::Tex(_) | ExprKind::Pixel(_) => {
+                (sum(|c| c.0), differences(sum(|c| c.0).saturating_add(1)))
+            }
+            _ => (sum(|c| c.0), sum(|c| c.1)),
+        };
+        counts.push((plain.saturating_add(1), dual.saturating_add(1)));
+    }
+    counts.pop().expect("missing count").0
+}
+
+impl Stats {
+    /// Add the counts of another render of the same expression, like a part of the image that
+    /// was rendered by another thread.
+    pub fn merge(&mut self, other: &Stats) {
+        self.pixels += other.pixels;
+        self.evaluated += other.evaluated;
+        for (branch, other) in self.branches.iter_mut().zip(&other.branches) {
+            branch.taken += other.taken;
+            branch.not_taken += other.not_taken;
+        }
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Expressions: {} (depth {})", self.nodes, self.depth)?;
+        for (kind, count) in &self.kinds {
+            writeln!(f, "\t{kind}: {count}")?;
+        }
+        writeln!(f, "Estimated operations per pixel: {}", self.ops)?;
+        if self.pixels > 0 {
+            let per_pixel = self.evaluated as f64 / self.pixels as f64;
+            writeln!(f, "Pixels: {}", self.pixels)?;
+            writeln!(f, "Expressions evaluated per pixel: {per_pixel:.2}")?;
+        }
+        if !self.branches.is_empty() {
+            writeln!(f, "Branches taken (then/else):")?;
+        }
+        for branch in &self.branches {
+            let Range { start, end } = branch.span;
+            writeln!(f, "\t{start}..{end}: {}/{}", branch.taken, branch.not_taken)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts what the evaluator does while rendering an expression.
+pub struct Recorder<'a> {
+    stats: Stats,
+    /// Index in the branches of the conditionals of the expression.
+    conditionals: HashMap<*const IfExpr, usize>,
+    expr: PhantomData<&'a Expr>,
+}
+
+impl<'a> Recorder<'a> {
+    /// Create a recorder for renders of an expression. Conditionals of other expressions are
+    /// ignored.
+    pub fn new(expr: &'a Expr) -> Self {
+        let conditionals = walk(expr)
+            .into_iter()
+            .filter_map(|(expr, _)| match &expr.kind {
+                ExprKind::If(e) => Some(e as *const IfExpr),
+                _ => None,
+            })
+            .enumerate()
+            .map(|(i, e)| (e, i))
+            .collect();
+        Self {
+            stats: analyze(expr),
+            conditionals,
+            expr: PhantomData,
+        }
+    }
+
+    /// Count rendered pixels, whether they were evaluated or not.
+    pub fn add_pixels(&mut self, pixels: usize) {
+        self.stats.pixels += pixels;
+    }
+
+    /// The statistics recorded so far.
+    pub fn finish(self) -> Stats {
+        self.stats
+    }
+}
+
+impl Observer for Recorder<'_> {
+    fn visit(&mut self, _expr: &Expr) {
+        self.stats.evaluated += 1;
+    }
+
+    fn branch(&mut self, expr: &IfExpr, taken: bool) {
+        if let Some(&i) = self.conditionals.get(&(expr as *const IfExpr)) {
+            let branch = &mut self.stats.branches[i];
+            match taken {
+                true => branch.taken += 1,
+                false => branch.not_taken += 1,
+            }
+        }
+    }
+}
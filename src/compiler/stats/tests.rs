@@ -0,0 +1,129 @@
+use super::{analyze, max_evaluated, Stats};
+use crate::{
+    compiler::parser::parse_source,
+    renderer::{render, render_heatmap, render_subimage_with_stats, render_with_stats},
+};
+
+#[test]
+fn counts_expressions_by_kind() {
+    let expr = parse_source("if x < 0.0 then sin(y) else {x, y, r} end");
+    let stats = analyze(&expr);
+
+    assert_eq!(stats.nodes, 10);
+    assert_eq!(stats.depth, 3);
+    assert_eq!(stats.kinds["If"], 1);
+    assert_eq!(stats.kinds["X"], 2);
+    assert_eq!(stats.kinds["Y"], 2);
+    assert_eq!(stats.kinds.values().sum::<usize>(), stats.nodes);
+    assert_eq!(stats.branches.len(), 1);
+    assert_eq!(stats.branches[0].span, expr.span.start..expr.span.end);
+    assert_eq!(stats.pixels, 0);
+}
+
+#[test]
+fn counts_branches_taken() {
+    // Neither half of the image has a uniform color, so every pixel is evaluated.
+    let expr = parse_source("if x < 0.0 then y else r end");
+    let (_, stats) = render_with_stats(&expr, 32, 32);
+
+    assert_eq!(stats.pixels, 32 * 32);
+    assert_eq!(stats.branches[0].taken, 16 * 32);
+    assert_eq!(stats.branches[0].not_taken, 16 * 32);
+    // The condition has three expressions, and every branch has one.
+    assert_eq!(stats.evaluated, 32 * 32 * 5);
+}
+
+#[test]
+fn derivatives_report_their_operands() {
+    // `dx` evaluates `x * y` once with dual numbers, and `shade` evaluates both operands once.
+    // Nested in `dx`, `dy` evaluates its operand for each of the five points of a finite
+    // difference.
+    for (source, evaluated) in [("dx(x * y) + shade(r, a)", 8), ("dx(dy(x * y * r))", 27)] {
+        let (_, stats) = render_with_stats(&parse_source(source), 16, 16);
+        assert_eq!(stats.evaluated, 16 * 16 * evaluated, "{source}");
+    }
+}
+
+#[test]
+fn uniform_tiles_are_not_evaluated() {
+    let expr = parse_source("if x < 2.0 then {1.0, 0.0, 0.0} else y end");
+    let (_, stats) = render_with_stats(&expr, 32, 32);
+
+    assert_eq!(stats.pixels, 32 * 32);
+    assert_eq!(stats.evaluated, 0);
+    assert_eq!(stats.branches[0].taken, 0);
+}
+
+#[test]
+fn statistics_do_not_change_the_image() {
+    for source in [
+        "x * y",
+        "if x > y then sin(r * 9.0) else a end",
+        "tx(r, y) ^ x",
+    ] {
+        let expr = parse_source(source);
+        let (image, _) = render_with_stats(&expr, 48, 48);
+        assert_eq!(image, render(&expr, 48, 48), "{source}");
+    }
+}
+
+#[test]
+fn merged_parts_match_the_whole_image() {
+    let expr = parse_source("if x * y < 0.1 then if r > 0.5 then x else y end else a end");
+    let (_, whole) = render_with_stats(&expr, 40, 40);
+
+    let mut merged = analyze(&expr);
+    for (start, end) in [(0, 13), (13, 27), (27, 40)] {
+        let (_, part) = render_subimage_with_stats(&expr, (0, 40), (start, end), 40, 40);
+        merged.merge(&part);
+    }
+    assert_eq!(merged, whole);
+    assert_ne!(merged, Stats::default());
+}
+
+#[test]
+fn max_evaluated_takes_the_larger_branch() {
+    let expr = parse_source("if x < 0.0 then y else {x * y, r, a} end");
+    assert_eq!(max_evaluated(&expr), 10);
+
+    let expr = parse_source("dx(x * y) + if x < y then dy(x ^ 2.0) else 1.0 end");
+    assert_eq!(max_evaluated(&expr), 13);
+}
+
+#[test]
+fn max_evaluated_counts_derivatives_and_lighting() {
+    // Without conditionals, every pixel evaluates the most that a pixel can.
+    for source in [
+        "dx(x * y) + shade(r, a)",
+        "dx(dy(x * y * r))",
+        "normal(dx(tex(0.0, x, y)) + shade(y, x))",
+    ] {
+        let expr = parse_source(source);
+        let (_, stats) = render_with_stats(&expr, 16, 16);
+        assert_eq!(stats.evaluated, 16 * 16 * max_evaluated(&expr), "{source}");
+    }
+
+    let expr = parse_source("dy(if x < y then normal(x) else shade(r, 0.5) end)");
+    let (_, stats) = render_with_stats(&expr, 16, 16);
+    assert!(stats.evaluated < 16 * 16 * max_evaluated(&expr));
+    let heatmap = render_heatmap(&expr, 16, 16);
+    assert!(heatmap.pixels().any(|p| p.0 == [255; 3]));
+}
+
+#[test]
+fn heatmap_shows_evaluated_expressions() {
+    // The left half evaluates 5 expressions, and the right half the most that a pixel can.
+    let expr = parse_source("if x < 0.0 then y else {x * y, r, a} end");
+    let heatmap = render_heatmap(&expr, 32, 32);
+
+    assert_eq!(heatmap.get_pixel(3, 20).0, [(5 * 255 / 10) as u8; 3]);
+    assert_eq!(heatmap.get_pixel(28, 20).0, [255; 3]);
+}
+
+#[test]
+fn heatmap_is_black_for_uniform_tiles() {
+    let expr = parse_source("if x < 2.0 then {1.0, 0.0, 0.0} else y end");
+    let heatmap = render_heatmap(&expr, 32, 32);
+
+    assert!(heatmap.pixels().all(|p| p.0 == [0; 3]));
+}
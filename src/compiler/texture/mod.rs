@@ -0,0 +1,219 @@
+//! Image textures that SSL programs can sample with the `tex(n, u, v)` built-in. Textures are bound
+//! to an expression after it is parsed, so the expression can still be rendered like any other.
+//!
+//! Texture coordinates use the same space as `x` and `y`: `-1.0` is the left (or top) edge and
+//! `1.0` is the right (or bottom) edge. The centers of the texels are placed where the renderer
+//! samples pixels, so an image sampled at `(x, y)` with the same size as the output is reproduced
+//! exactly.
+//!
+//! Colors are returned in the same space as every other value, so they are put through the inverse
+//! of the normalization the renderer applies. This way `tex(0.0, x, y)` renders the image itself,
+//! with white one step darker, and arithmetic on samples behaves like arithmetic on color literals.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+use image::RgbImage;
+
+use super::{
+    ast::{Expr, ExprKind, PixelExpr, TexExpr},
+    evaluator::Color,
+};
+
+/// Convert a channel in [0, 1] to the value that the renderer turns back into the same byte.
+///
+/// The renderer only gives white for values that normalize to exactly one, which are far from the
+/// values of every other byte, so white is read at the upper end of their range instead. It stays
+/// a color like any other in arithmetic, and renders one step darker.
+pub(crate) fn value(c: f64) -> f64 {
+    let byte = (c * u8::MAX as f64)
+        .round()
+        .clamp(0.0, (u8::MAX - 1) as f64);
+    // Aim for the middle of the byte, so rounding errors do not change it.
+    let c = (byte + 0.5) / u8::MAX as f64;
+    (0.5 - f64::ln(1.0 / c - 1.0)) / 2.0
+}
+
+/// How colors are read between texels.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Filter {
+    /// Use the color of the closest texel
+    Nearest,
+    /// Blend the colors of the four closest texels
+    #[default]
+    Bilinear,
+}
+
+/// How coordinates outside of the texture are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Address {
+    /// Repeat the texture in every direction
+    #[default]
+    Wrap,
+    /// Use the color of the closest edge
+    Clamp,
+}
+
+/// Settings for sampling a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Sampler {
+    /// How colors are read between texels
+    pub filter: Filter,
+    /// How coordinates outside of the texture are handled
+    pub address: Address,
+}
+
+/// An image that can be sampled by SSL programs.
+#[derive(Clone)]
+pub struct Texture {
+    image: RgbImage,
+    sampler: Sampler,
+}
+
+impl Debug for Texture {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Texture")
+            .field("width", &self.image.width())
+            .field("height", &self.image.height())
+            .field("sampler", &self.sampler)
+            .finish()
+    }
+}
+
+impl Texture {
+    /// Create a texture from an image.
+    pub fn new(image: RgbImage, sampler: Sampler) -> Self {
+        Self { image, sampler }
+    }
+
+    /// Get the color of a texel, applying the addressing mode to coordinates outside the image.
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let address = |i: i64, size: u32| match self.sampler.address {
+            Address::Wrap => i.rem_euclid(size as i64) as u32,
+            Address::Clamp => i.clamp(0, size as i64 - 1) as u32,
+        };
+        let [r, g, b] = self
+            .image
+            .get_pixel(
+                address(x, self.image.width()),
+                address(y, self.image.height()),
+            )
+            .0
+            .map(|c| c as f64 / u8::MAX as f64);
+        Color { r, g, b }
+    }
+
+    /// Sample the texture at a point.
+    pub(crate) fn sample(&self, u: f64, v: f64) -> Color {
+        let c = self.color(u, v);
+        Color {
+            r: value(c.r),
+            g: value(c.g),
+            b: value(c.b),
+        }
+    }
+
+    /// The color of the texture at a point, with channels in [0, 1].
+    fn color(&self, u: f64, v: f64) -> Color {
+        if self.image.width() == 0 || self.image.height() == 0 {
+            return Color {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0,
+            };
+        }
+
+        // Position in texels, with the texel centers at whole numbers.
+        let tx = (u + 1.0) / 2.0 * self.image.width() as f64;
+        let ty = (v + 1.0) / 2.0 * self.image.height() as f64;
+
+        match self.sampler.filter {
+            Filter::Nearest => self.texel(tx.round() as i64, ty.round() as i64),
+            Filter::Bilinear => {
+                let (x0, y0) = (tx.floor() as i64, ty.floor() as i64);
+                let (fx, fy) = (tx - tx.floor(), ty - ty.floor());
+                let (x1, y1) = (x0.saturating_add(1), y0.saturating_add(1));
+                let mix = |a: Color, b: Color, t: f64| Color {
+                    r: a.r + (b.r - a.r) * t,
+                    g: a.g + (b.g - a.g) * t,
+                    b: a.b + (b.b - a.b) * t,
+                };
+                let top = mix(self.texel(x0, y0), self.texel(x1, y0), fx);
+                let bottom = mix(self.texel(x0, y1), self.texel(x1, y1), fx);
+                mix(top, bottom, fy)
+            }
+        }
+    }
+}
+
+/// The textures bound to an expression. Cheap to clone, so every `tex` expression can hold them.
+#[derive(Clone, Default)]
+pub struct Textures(Arc<Vec<Texture>>);
+
+impl Debug for Textures {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_list().entries(self.0.iter()).finish()
+    }
+}
+
+/// Textures are equal if they are the same binding, as comparing the images would be slow.
+impl PartialEq for Textures {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || (self.0.is_empty() && other.0.is_empty())
+    }
+}
+
+impl Textures {
+    /// Sample texture number `n`. The number is rounded down and wraps around the number of
+    /// textures. Without any textures, everything is black.
+    pub(crate) fn sample(&self, n: f64, u: f64, v: f64) -> Color {
+        if self.0.is_empty() {
+            let black = value(0.0);
+            return Color {
+                r: black,
+                g: black,
+                b: black,
+            };
+        }
+        let index = (n.floor() as i64).rem_euclid(self.0.len() as i64) as usize;
+        self.0[index].sample(u, v)
+    }
+}
+
+/// Bind textures to every `tex` expression in an expression. The index of a texture is its position
+/// in the list, and binding replaces any textures that were bound before.
+pub fn bind_textures(expr: Expr, textures: Vec<Texture>) -> Expr {
+    let textures = Textures(Arc::new(textures));
+    bind(expr, &mut |kind| match kind {
+        ExprKind::Tex(e) => ExprKind::Tex(TexExpr {
+            textures: textures.clone(),
+            ..e
+        }),
+        kind => kind,
+    })
+}
+
+/// Bind the image that is filtered to every `p` expression in an expression.
+pub fn bind_source(expr: Expr, source: Texture) -> Expr {
+    let source = Textures(Arc::new(vec![source]));
+    bind(expr, &mut |kind| match kind {
+        ExprKind::Pixel(_) => ExprKind::Pixel(PixelExpr {
+            source: source.clone(),
+        }),
+        kind => kind,
+    })
+}
+
+/// Apply `f` to every expression in an expression, from the leaves up.
+fn bind(expr: Expr, f: &mut impl FnMut(ExprKind) -> ExprKind) -> Expr {
+    let expr = expr.map_children(|e| bind(e, f));
+    Expr {
+        kind: f(expr.kind),
+        span: expr.span,
+    }
+}
@@ -0,0 +1,193 @@
+use image::{Rgb, RgbImage};
+
+use super::{bind_source, bind_textures, value, Address, Filter, Sampler, Texture};
+use crate::compiler::{evaluator, interval, parser, simplifier};
+
+/// A 2x2 texture with a different color in each texel.
+fn checker(filter: Filter, address: Address) -> Texture {
+    let mut image = RgbImage::new(2, 2);
+    image.put_pixel(0, 0, Rgb([255, 0, 0]));
+    image.put_pixel(1, 0, Rgb([0, 255, 0]));
+    image.put_pixel(0, 1, Rgb([0, 0, 255]));
+    image.put_pixel(1, 1, Rgb([255, 255, 255]));
+    Texture::new(image, Sampler { filter, address })
+}
+
+fn rgb(c: evaluator::Color) -> [f64; 3] {
+    [c.r, c.g, c.b]
+}
+
+#[test]
+fn nearest_texel_centers() {
+    let tex = checker(Filter::Nearest, Address::Wrap);
+    // Texel centers are where the renderer samples pixels.
+    assert_eq!(rgb(tex.color(-1.0, -1.0)), [1.0, 0.0, 0.0]);
+    assert_eq!(rgb(tex.color(0.0, -1.0)), [0.0, 1.0, 0.0]);
+    assert_eq!(rgb(tex.color(-1.0, 0.0)), [0.0, 0.0, 1.0]);
+    assert_eq!(rgb(tex.color(0.1, 0.1)), [1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn bilinear_blends_texels() {
+    let tex = checker(Filter::Bilinear, Address::Clamp);
+    assert_eq!(rgb(tex.color(-0.5, -1.0)), [0.5, 0.5, 0.0]);
+    assert_eq!(rgb(tex.color(-0.5, -0.5)), [0.5, 0.5, 0.5]);
+    assert_eq!(rgb(tex.color(-1.0, -1.0)), [1.0, 0.0, 0.0]);
+}
+
+#[test]
+fn addressing() {
+    let wrap = checker(Filter::Nearest, Address::Wrap);
+    let clamp = checker(Filter::Nearest, Address::Clamp);
+    assert_eq!(rgb(wrap.color(1.0, -1.0)), [1.0, 0.0, 0.0]);
+    assert_eq!(rgb(clamp.color(1.0, -1.0)), [0.0, 1.0, 0.0]);
+    assert_eq!(rgb(wrap.color(-2.0, -1.0)), [0.0, 1.0, 0.0]);
+    assert_eq!(rgb(clamp.color(-7.0, -1.0)), [1.0, 0.0, 0.0]);
+    assert_eq!(rgb(clamp.color(1e300, 1e300)), [1.0, 1.0, 1.0]);
+
+    // Bilinear wraps between the last and the first texel.
+    let wrap = checker(Filter::Bilinear, Address::Wrap);
+    assert_eq!(rgb(wrap.color(0.5, -1.0)), [0.5, 0.5, 0.0]);
+}
+
+#[test]
+fn values_render_as_bytes() {
+    let render = |byte: u8| {
+        let c = evaluator::norm(value(byte as f64 / u8::MAX as f64));
+        (c * u8::MAX as f64) as u8
+    };
+    for byte in 0..u8::MAX {
+        assert_eq!(render(byte), byte);
+    }
+    // White renders one step darker.
+    assert_eq!(render(u8::MAX), u8::MAX - 1);
+}
+
+#[test]
+fn white_is_not_an_outlier() {
+    // A blur of one white and two black pixels is darker than gray.
+    let blur = (value(1.0) + 2.0 * value(0.0)) / 3.0;
+    assert!(evaluator::norm(blur) < 0.5);
+}
+
+#[test]
+fn render_reproduces_texture() {
+    let image = RgbImage::from_fn(7, 5, |x, y| Rgb([x as u8 * 40, y as u8 * 60, 254]));
+    for filter in [Filter::Nearest, Filter::Bilinear] {
+        let tex = Texture::new(
+            image.clone(),
+            Sampler {
+                filter,
+                ..Default::default()
+            },
+        );
+        let expr = parser::parse_source("tex(0.0, x, y)".to_string());
+        let expr = bind_textures(expr, vec![tex]);
+        assert_eq!(crate::renderer::render(&expr, 7, 5), image, "{filter:?}");
+    }
+}
+
+#[test]
+fn texture_index_wraps() {
+    let red = Texture::new(
+        RgbImage::from_pixel(1, 1, Rgb([255, 0, 0])),
+        Sampler::default(),
+    );
+    let blue = Texture::new(
+        RgbImage::from_pixel(1, 1, Rgb([0, 0, 255])),
+        Sampler::default(),
+    );
+    let expr = parser::parse_source("tex(x * 4.0, 0.0, 0.0)".to_string());
+    let expr = bind_textures(expr, vec![red, blue]);
+    let color = |x| {
+        let c = evaluator::eval_expr(&expr, x, 0.0).as_color();
+        (c.r > c.b, c.b > c.r)
+    };
+    assert_eq!(color(0.1), (true, false));
+    assert_eq!(color(0.3), (false, true));
+    assert_eq!(color(0.5), (true, false));
+    assert_eq!(color(-0.1), (false, true));
+}
+
+#[test]
+fn unbound_textures_are_black() {
+    let expr = parser::parse_source("tex(0.0, x, y)".to_string());
+    let c = evaluator::eval(&expr, 0.3, 0.4);
+    assert!(c.r < 1.0 / 255.0 && c.g < 1.0 / 255.0 && c.b < 1.0 / 255.0);
+}
+
+#[test]
+fn textures_are_not_folded() {
+    let expr = parser::parse_source("tex(1.0, 0.5, 0.5) * 2.0".to_string());
+    assert!(matches!(expr.kind, crate::compiler::ast::ExprKind::Bin(_)));
+    let bounds = interval::eval_bounds(
+        &expr,
+        interval::Interval::new(-1.0, 1.0),
+        interval::Interval::new(-1.0, 1.0),
+    );
+    assert!(matches!(bounds, interval::Bounds::Color(_)));
+}
+
+#[test]
+fn filter_reproduces_source() {
+    let image = RgbImage::from_fn(9, 6, |x, y| Rgb([x as u8 * 25, 0, y as u8 * 40]));
+    let expr = parser::parse_filter("p".to_string());
+    let expr = bind_source(expr, Texture::new(image.clone(), Sampler::default()));
+    assert_eq!(crate::renderer::render(&expr, 9, 6), image);
+}
+
+#[test]
+fn pixel_is_only_parsed_in_filters() {
+    assert!(!parser::parse_source("p * x".to_string()).uses_pixel());
+    assert!(parser::parse_filter("p * x".to_string()).uses_pixel());
+}
+
+/// Sources that are complete programs are kept as they are, but most random sources turn into
+/// filters.
+#[test]
+fn random_filters_use_pixel() {
+    let mut rng = crate::compiler::differential::Rng(0xf117);
+    let filters = (0..2000)
+        .filter(|_| {
+            let source = crate::compiler::differential::random_source(&mut rng);
+            parser::parse_filter(source).uses_pixel()
+        })
+        .count();
+    assert!(filters > 1800, "Only {filters} sources use the pixel");
+}
+
+#[test]
+fn translated_pixels_are_kept() {
+    let expr = parser::parse_filter("tx(0.5, ty(0.5, p))".to_string());
+    let simplified = simplifier::simplify(expr.clone());
+    assert_eq!(simplified, expr);
+}
+
+#[test]
+fn passes_filter_the_previous_pass() {
+    let first = parser::parse_source("if x < 0.0 then {2.0, -2.0, -2.0} else -2.0 end".to_string());
+    // Moves the image one pixel to the right.
+    let shift = parser::parse_filter("tx(0.25, p)".to_string());
+    let once = crate::renderer::render_passes(&[first.clone(), shift.clone()], 8, 2);
+    let original = crate::renderer::render(&first, 8, 2);
+    for x in 0..8 {
+        assert_eq!(once.get_pixel((x + 1) % 8, 0), original.get_pixel(x, 0));
+    }
+
+    let mut passes = vec![first];
+    passes.extend(std::iter::repeat_n(shift, 8));
+    assert_eq!(crate::renderer::render_passes(&passes, 8, 2), original);
+}
+
+#[test]
+fn passes_blend_saturated_pixels() {
+    let first = parser::parse_source("if x < 0.0 then 20.0 else -20.0 end".to_string());
+    let blur = parser::parse_filter("(tx(0.25, p) + tx(-0.25, p)) / 2.0".to_string());
+    let original = crate::renderer::render(&first, 8, 2);
+    assert_eq!(original.get_pixel(0, 0).0, [255; 3]);
+    let image = crate::renderer::render_passes(&[first, blur], 8, 2);
+
+    // Between white and black, the average is gray instead of white.
+    let [gray, _, _] = image.get_pixel(4, 0).0;
+    assert!((100..=155).contains(&gray), "{gray}");
+}
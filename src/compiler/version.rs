@@ -0,0 +1,56 @@
+//! Versions of the language. Any input is a valid program, so every change to how input is lexed,
+//! parsed or evaluated changes the images of existing inputs. Such changes go into a new version,
+//! and old versions keep rendering every input the way they always did. This matters most for hash
+//! visualizations, which are compared against images rendered long ago.
+
+use super::{evaluator, lexer::TokenKind};
+
+/// A version of the language. A version pins the keywords of the lexer, how evaluated colors are
+/// normalized, and the options that the functions ending in `_with_version` parse with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LanguageVersion {
+    /// The first stable version. It has no keywords, so `dx`, `dy`, `normal`, `shade` and `tex`
+    /// are read letter by letter.
+    V1,
+    /// Reads the derivative, lighting and texture built-ins `dx`, `dy`, `normal`, `shade` and
+    /// `tex`, where `shade` takes an optional elevation of the light as its third argument. Parses
+    /// sources again while they are constant, and keeps programs within
+    /// [Limits::DEFAULT](super::limits::Limits::DEFAULT).
+    /// Its choices and colors are those of [LanguageVersion::V1].
+    #[default]
+    V2,
+}
+
+impl LanguageVersion {
+    /// The latest version, which is also the default.
+    pub const LATEST: Self = Self::V2;
+
+    /// Every version, from oldest to newest.
+    #[allow(dead_code)] // TODO: Find a better solution
+    pub const ALL: [Self; 2] = [Self::V1, Self::V2];
+
+    /// Words that the lexer reads as a single token.
+    pub(crate) fn keywords(self) -> &'static [(&'static str, TokenKind)] {
+        match self {
+            LanguageVersion::V1 => &[],
+            LanguageVersion::V2 => &[
+                ("dx", TokenKind::DX),
+                ("dy", TokenKind::DY),
+                ("normal", TokenKind::Normal),
+                ("shade", TokenKind::Shade),
+                ("tex", TokenKind::Tex),
+            ],
+        }
+    }
+
+    /// Normalize a channel of an evaluated color to the range [0, 1].
+    pub(crate) fn normalize(self, x: f64) -> f64 {
+        evaluator::norm(x)
+    }
+
+    /// The interval within which [LanguageVersion::normalize] is monotonic.
+    pub(crate) fn monotonic_range(self) -> (f64, f64) {
+        // `norm` takes the remainder of 50, which is only monotonic within (-50, 50).
+        (-50.0, 50.0)
+    }
+}
@@ -0,0 +1,134 @@
+//! Errors of the fallible API. Any input is a valid program, so the functions that can not fail
+//! repair and truncate their input instead. The `try_*` functions report what they would have to
+//! repair, along with sizes that can not be rendered.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+    ops::Range,
+};
+
+use crate::compiler::limits::Limit;
+
+/// A place where the parser had to repair the source, found by a strict parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Bytes of the source where the repair starts. Empty at the end of the source.
+    pub span: Range<usize>,
+    /// What the parser expected there, like ``expected `)` ``.
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Range { start, end } = self.span;
+        write!(f, "{} at {start}..{end}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Errors of the `try_*` functions.
+#[derive(Debug)]
+pub enum Error {
+    /// A file or stream could not be read or written.
+    Io(io::Error),
+    /// An image could not be encoded or decoded.
+    Encoding(image::ImageError),
+    /// A strict parse found a program over one of its limits, which would have been truncated.
+    LimitExceeded {
+        /// The budget that was exceeded.
+        limit: Limit,
+        /// The maximum of the budget.
+        max: usize,
+    },
+    /// A strict parse found source that would have been repaired.
+    Parse(ParseError),
+    /// An image has no pixels, or more than fit in memory.
+    InvalidDimensions {
+        /// Width of the image.
+        width: u32,
+        /// Height of the image.
+        height: u32,
+    },
+    /// A part of an image is not one of its parts.
+    InvalidPart {
+        /// The part to be rendered.
+        part: u32,
+        /// Number of parts of the image.
+        total_parts: u32,
+    },
+    /// A subimage has no pixels, or is not within its image.
+    InvalidRegion {
+        /// Columns of the subimage.
+        x: (u32, u32),
+        /// Rows of the subimage.
+        y: (u32, u32),
+        /// Width of the whole image.
+        width: u32,
+        /// Height of the whole image.
+        height: u32,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Encoding(e) => write!(f, "{e}"),
+            Error::LimitExceeded { limit, max } => match limit {
+                Limit::Tokens => write!(f, "program has more than {max} tokens"),
+                Limit::Nodes => write!(f, "program has more than {max} expressions"),
+                Limit::Depth => write!(f, "program reaches the depth limit of {max}"),
+                Limit::Ops => write!(f, "program takes more than {max} operations per pixel"),
+                Limit::Derivatives => write!(f, "program nests more than {max} derivatives"),
+            },
+            Error::Parse(e) => write!(f, "{e}"),
+            Error::InvalidDimensions { width, height } => {
+                write!(f, "invalid image size {width}x{height}")
+            }
+            Error::InvalidPart { part, total_parts } => {
+                write!(f, "invalid part {part} of {total_parts} parts")
+            }
+            Error::InvalidRegion {
+                x,
+                y,
+                width,
+                height,
+            } => write!(
+                f,
+                "invalid region {}..{} by {}..{} of a {width}x{height} image",
+                x.0, x.1, y.0, y.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Encoding(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Encoding(e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
@@ -0,0 +1,98 @@
+//! Contact sheets of many images of the same source, like one for every seed of the parser. Every
+//! thumbnail is labeled with its number, so a favorite can be rendered again on its own.
+
+use image::{GenericImage, Rgb, RgbImage};
+
+/// Space between thumbnails and around the edges of the sheet, in pixels.
+const GAP: u32 = 8;
+
+/// Size of a dot of the font, in pixels.
+const SCALE: u32 = 2;
+
+/// Height of the label below every thumbnail, including the space around it.
+const LABEL_HEIGHT: u32 = DIGIT_HEIGHT * SCALE + GAP;
+
+const BACKGROUND: Rgb<u8> = Rgb([24, 24, 24]);
+const FOREGROUND: Rgb<u8> = Rgb([230, 230, 230]);
+
+const DIGIT_WIDTH: u32 = 3;
+const DIGIT_HEIGHT: u32 = 5;
+
+/// The digits 0 to 9 in a font of 3 by 5 dots. Every row is a byte, with the left dot in the
+/// highest of its three bits.
+const DIGITS: [[u8; DIGIT_HEIGHT as usize]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Draw a number with its top left corner at the given point. Digits that do not fit within
+/// `max_x` are cut off.
+fn draw_number(image: &mut RgbImage, number: u64, (x, y): (u32, u32), max_x: u32) {
+    let advance = (DIGIT_WIDTH + 1) * SCALE;
+    for (i, digit) in number.to_string().bytes().enumerate() {
+        let left = x + i as u32 * advance;
+        for (row, bits) in DIGITS[(digit - b'0') as usize].iter().enumerate() {
+            for col in 0..DIGIT_WIDTH {
+                if bits & (1 << (DIGIT_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        let px = left + col * SCALE + dx;
+                        let py = y + row as u32 * SCALE + dy;
+                        if px < max_x.min(image.width()) && py < image.height() {
+                            image.put_pixel(px, py, FOREGROUND);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lay out thumbnails in a grid with the given number of columns, each labeled with its number
+/// below it. Every cell of the grid has the size of the largest thumbnail, and there are never more
+/// columns than thumbnails.
+///
+/// # Panics
+/// If a side of the sheet does not fit in a `u32`.
+pub fn contact_sheet(thumbnails: &[(u64, RgbImage)], columns: u32) -> RgbImage {
+    let count = u32::try_from(thumbnails.len()).expect("number of thumbnails fits in a u32");
+    let columns = columns.clamp(1, count.max(1));
+    let rows = count.div_ceil(columns);
+    let width = thumbnails.iter().map(|(_, t)| t.width()).max().unwrap_or(0);
+    let height = thumbnails
+        .iter()
+        .map(|(_, t)| t.height())
+        .max()
+        .unwrap_or(0);
+
+    let side = |cells: u32, cell: u32, spacing: u32| {
+        cell.checked_add(spacing)
+            .and_then(|cell| cell.checked_mul(cells))
+            .and_then(|cells| cells.checked_add(GAP))
+            .expect("side of the contact sheet fits in a u32")
+    };
+    let mut sheet = RgbImage::from_pixel(
+        side(columns, width, GAP),
+        side(rows, height, LABEL_HEIGHT),
+        BACKGROUND,
+    );
+    for (i, (number, thumbnail)) in thumbnails.iter().enumerate() {
+        let x = GAP + (i as u32 % columns) * (width + GAP);
+        let y = GAP + (i as u32 / columns) * (height + LABEL_HEIGHT);
+        sheet
+            .copy_from(thumbnail, x, y)
+            .expect("thumbnail fits its cell");
+        draw_number(&mut sheet, *number, (x, y + height + GAP / 2), x + width);
+    }
+    sheet
+}
@@ -15,6 +15,117 @@
 //!
 //! ```
 //!
+//! # Seeds
+//! The parser repairs random input by making choices, like which expression a stray character stands for. [generate_with_seed] and [parse_source_with_seed] make these choices differently for every seed, so the same input gives many variations of its image. Seed 0 gives the usual image, and the same seed always gives the same image.
+//! ```
+//! use ssl::{generate, generate_with_seed};
+//!
+//! let source = "Hello, I am a random string!".to_string();
+//! let variations: Vec<_> = (0..4)
+//!     .map(|seed| generate_with_seed(source.clone(), 32, 32, seed))
+//!     .collect();
+//! assert_eq!(variations[0], generate(source, 32, 32));
+//! ```
+//!
+//! To pick a favorite, [generate_gallery] renders a contact sheet with a labeled thumbnail for every seed in a range.
+//! ```
+//! use ssl::generate_gallery;
+//!
+//! // Three rows of four thumbnails, for the seeds 0 to 11
+//! let sheet = generate_gallery("Hello!".to_string(), 64, 64, 0..12, 4);
+//! assert!(sheet.width() > 4 * 64 && sheet.height() > 3 * 64);
+//! ```
+//!
+//! # Binary Input
+//! Any data can be rendered, not only text. Use [generate_from_bytes] for data that is not valid UTF-8, like the contents of a binary file.
+//! ```
+//! use ssl::generate_from_bytes;
+//!
+//! let bytes = [0x00, 0x9f, 0x92, 0x96, 0xff, b'x'];
+//! let image = generate_from_bytes(&bytes, 100, 100);
+//! assert_eq!(image.dimensions(), (100, 100));
+//! ```
+//!
+//! # Hash Visualization
+//! [generate] reads its input as a program, so similar inputs give similar images. To compare things like SSH keys or fingerprints at a glance, [visualize_hash] first digests the input with SHA-256 and renders a program made from the digest. Inputs that differ by a single bit give unrelated images.
+//! ```
+//! use ssl::visualize_hash;
+//!
+//! let key = b"ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+//! let image = visualize_hash(key, 64, 64);
+//! assert_eq!(image, visualize_hash(key, 64, 64));
+//! ```
+//!
+//! # Limits
+//! Any input is a valid program, so huge inputs would give expressions that take minutes to render or overflow the stack. The parser keeps expressions within [Limits]: it reads a limited number of tokens, nests expressions at most [MAX_DEPTH] deep, reads derivatives nested more than three deep as their operand, and truncates expressions that have too many nodes or take too many operations per pixel. The defaults only truncate inputs far larger than programs written by hand. Use [parse_source_with_limits] for tighter limits, like where the stack is small or images must render quickly.
+//! ```
+//! use ssl::{parse_source_with_limits, render, Limits};
+//!
+//! let source = "(".repeat(100_000) + "x";
+//! let limits = Limits {
+//!     max_depth: 64,
+//!     max_ops: 1_000,
+//!     ..Limits::DEFAULT
+//! };
+//! let expr = parse_source_with_limits(source, limits);
+//! let image = render(&expr, 32, 32);
+//! ```
+//!
+//! # Statistics
+//! [analyze] counts the expressions of an expression by kind and estimates how many operations a pixel takes. [render_with_stats] also counts how many expressions were evaluated and how often every conditional took each branch, which shows why a program renders slowly.
+//! ```
+//! use ssl::{parse_source, render_with_stats};
+//!
+//! let expr = parse_source("if x < 0.0 then y else sin(r) end".to_string());
+//! let (image, stats) = render_with_stats(&expr, 40, 40);
+//!
+//! assert_eq!(stats.kinds["If"], 1);
+//! let branch = &stats.branches[0];
+//! assert_eq!(branch.taken + branch.not_taken, 40 * 40);
+//! ```
+//!
+//! [render_heatmap] shows where the time goes instead of colors: the brightness of every pixel is the number of expressions evaluated to render it, relative to the [most that any pixel can evaluate](max_evaluated). Conditionals only evaluate one branch, so the cost differs across the image.
+//!
+//! # Parse Options
+//! The forgiving parser turns some inputs into constant expressions, which render as a single color. Like the command line, every parse function parses such inputs again with other choices. [ParseOptions] control how often, what counts as constant, and report every retry with its [RetryReason]. With [ConstantTest::Sampled], images that barely change are parsed again too.
+//! ```
+//! use std::cell::Cell;
+//! use ssl::{parse_source_with_options, ConstantTest, ParseOptions, SAMPLE_GRID};
+//!
+//! let retries = Cell::new(0);
+//! let on_retry = |_, _| retries.set(retries.get() + 1);
+//! let options = ParseOptions {
+//!     constant_test: ConstantTest::Sampled { grid: SAMPLE_GRID, tolerance: 0.05 },
+//!     on_retry: Some(&on_retry),
+//!     ..ParseOptions::default()
+//! };
+//! let expr = parse_source_with_options("Hello, I am a random string!", &options);
+//! println!("Parsed again {} times", retries.get());
+//! ```
+//!
+//! # Errors
+//! Every input is a valid program, so most functions can not fail. Where they would repair the source, truncate it or panic on an image without pixels, the `try_*` functions return an [Error] instead. With [ParseOptions::strict], [try_generate_with_options] and [try_parse_source_with_options] reject sources that would be repaired or truncated, which is useful for programs written by hand. [Error] converts from I/O and image errors, so code that loads and saves images can return it too.
+//! ```
+//! use ssl::{try_generate, try_generate_with_options, Error, ParseOptions};
+//!
+//! assert!(matches!(
+//!     try_generate("x * y".to_string(), 0, 600),
+//!     Err(Error::InvalidDimensions { .. })
+//! ));
+//!
+//! let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+//! let error = try_generate_with_options("sin(x".to_string(), 64, 64, &strict).unwrap_err();
+//! assert_eq!(error.to_string(), "expected `)` at 5..5");
+//! ```
+//!
+//! # Language Versions
+//! Every change to how input is lexed, parsed or evaluated changes the images of existing inputs, so such changes are made in a new [LanguageVersion]. Functions ending in `_with_version` render inputs the way a given version does, and their images never change. Use them when images are stored or compared later, like hash visualizations of keys. The options a version parses with are given by [ParseOptions::with_version], so [LanguageVersion::V1] never parses a source again or truncates it, even though the other functions now do.
+//! ```
+//! use ssl::{visualize_hash_with_version, LanguageVersion};
+//!
+//! let image = visualize_hash_with_version(b"some key", 64, 64, LanguageVersion::V1);
+//! ```
+//!
 //! # Render in Parts
 //! Sometimes you want to call a function every so often while rendering an image. Usually to report rendering progress. This can be achieved with the [render_in_parts] function.
 //! ```
@@ -36,14 +147,98 @@
 //! image.save("output.png").unwrap();
 //! ```
 //!
+//! # Formatting
+//! Random input is repaired while it is parsed. The repaired program can be turned back into SSL source code with [to_source], and parsing that source gives the same expression again.
+//! ```
+//! use ssl::{parse_source, to_source};
+//!
+//! let expr = parse_source("x+ (y*r".to_string());
+//! assert_eq!(to_source(&expr), "x + y * r");
+//! ```
+//!
+//! Whole files are formatted with [format_source], which also breaks lines that are longer than the given width. Expressions can be made shorter first with [simplify], which folds constants and rewrites the expression into a simpler one that renders the same image.
+//!
 //! # Multiple Threads
 //! Rendering an image can be a slow process. To speed things up, you can render parts of the image in parallel using multiple threads. This can be achieved with the [render_subimage] function.
+//!
+//! # Derivatives
+//! The partial derivatives of an expression can be evaluated at a point with the [gradient] function. Inside SSL programs, the same derivatives are available with the `dx(e)` and `dy(e)` built-ins.
+//! ```
+//! use ssl::{gradient, parse_source};
+//!
+//! let expr = parse_source("x * y".to_string());
+//!
+//! let g = gradient(&expr, 0.5, 0.25);
+//! assert_eq!(g.dx.r, 0.25);
+//! assert_eq!(g.dy.r, 0.5);
+//! ```
+//!
+//! # Textures
+//! SSL programs can read from images with the `tex(n, u, v)` built-in, which samples texture number `n` at the point `(u, v)`. Texture coordinates use the same space as `x` and `y`. Textures are bound to an expression with [bind_textures].
+//! ```
+//! use ssl::{bind_textures, parse_source, render, Address, Filter, RgbImage, Sampler, Texture};
+//!
+//! let photo = RgbImage::from_pixel(4, 4, image::Rgb([200, 0, 0]));
+//! let sampler = Sampler { filter: Filter::Nearest, address: Address::Clamp };
+//!
+//! // Flip the image horizontally
+//! let expr = parse_source("tex(0.0, -x, y)".to_string());
+//! let expr = bind_textures(expr, vec![Texture::new(photo, sampler)]);
+//!
+//! let image = render(&expr, 4, 4);
+//! assert_eq!(image.get_pixel(1, 1).0, [200, 0, 0]);
+//! ```
+//!
+//! # Filters
+//! Any source can also be used as an image filter with [apply_filter]. Filters are parsed with [parse_filter], where the variable `p` is the color of the pixel being filtered, and the image is bound with [bind_source].
+//! ```
+//! use ssl::{apply_filter, RgbImage};
+//!
+//! let photo = RgbImage::from_fn(60, 40, |x, y| image::Rgb([x as u8 * 4, y as u8 * 6, 128]));
+//!
+//! let filtered = apply_filter("Hello, I am a random filter!".to_string(), &photo);
+//! assert_eq!(filtered.dimensions(), photo.dimensions());
+//! ```
+//!
+//! # Multiple Passes
+//! Some effects need the rendered image itself, like a real blur. With [render_passes], every pass after the first is a filter of the image rendered by the pass before it.
+//! ```
+//! use ssl::{parse_filter, parse_source, render_passes};
+//!
+//! let first = parse_source("if x < 0.0 then {2.0, -2.0, -2.0} else -2.0 end".to_string());
+//!
+//! // Blur the previous pass by averaging it with its horizontal neighbors
+//! let blur = parse_filter("(tx(0.05, p) + p + tx(-0.05, p)) / 3.0".to_string());
+//!
+//! let mut passes = vec![first];
+//! passes.extend(std::iter::repeat_n(blur, 4));
+//! let image = render_passes(&passes, 80, 80);
+//! ```
+
+// The fuzz targets reach internals that are not documented like the public API.
+#![cfg_attr(not(feature = "fuzzing"), warn(missing_docs))]
 
-#![warn(missing_docs)]
+use std::ops::Range;
 
 mod compiler;
+mod error;
+mod gallery;
 mod renderer;
 
+/// Internals used by the fuzz targets in `fuzz/`. These are not part of the public API.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use crate::compiler::{
+        ast::Expr,
+        evaluator::eval,
+        lexer::{Lexer, Token, TokenKind},
+        limits::Limits,
+        parser::parse_tokens,
+        version::LanguageVersion,
+    };
+}
+
 /// Create an image to be rendered into
 pub fn create_image(width: u32, height: u32) -> image::RgbImage {
     image::RgbImage::new(width, height)
@@ -51,12 +246,133 @@ pub fn create_image(width: u32, height: u32) -> image::RgbImage {
 
 pub use image::RgbImage;
 
-pub use renderer::{render, render_in_parts, render_into, render_part_into, render_subimage};
+pub use error::{Error, ParseError};
+
+pub use gallery::contact_sheet;
 
-pub use compiler::parser::parse_source;
+pub use renderer::{
+    render, render_heatmap, render_heatmap_subimage, render_in_parts, render_into,
+    render_part_into, render_passes, render_subimage, render_subimage_with_stats,
+    render_with_stats, render_with_version, try_render, try_render_in_parts, try_render_into,
+    try_render_part_into, try_render_subimage,
+};
+
+pub use compiler::{
+    formatter::{format_source, to_source},
+    hash::{parse_hash, parse_hash_with_version},
+    limits::{estimate_ops, node_count, Limit, Limits, MAX_DEPTH},
+    parser::{
+        parse_filter, parse_source, parse_source_with_limits, parse_source_with_options,
+        parse_source_with_seed, parse_source_with_version, try_parse_source_with_options,
+        ConstantTest, ParseOptions, RetryReason, MAX_TRIES, SAMPLE_GRID,
+    },
+    simplifier::simplify,
+    stats::{analyze, max_evaluated, Branch, Stats},
+    version::LanguageVersion,
+};
+
+pub use compiler::{
+    autodiff::{gradient, Gradient},
+    evaluator::Color,
+    texture::{bind_source, bind_textures, Address, Filter, Sampler, Texture},
+};
 
 /// Generate an image from a source string
 pub fn generate(source: String, width: u32, height: u32) -> image::RgbImage {
     let expr = parse_source(source);
     renderer::render(&expr, width, height)
 }
+
+/// Generate an image from a source string parsed with the given options
+pub fn generate_with_options(
+    source: String,
+    width: u32,
+    height: u32,
+    options: &ParseOptions,
+) -> image::RgbImage {
+    let expr = parse_source_with_options(source, options);
+    renderer::render_with_version(&expr, width, height, options.version)
+}
+
+/// Generate an image from a source string like [generate], failing if the image has no pixels
+pub fn try_generate(source: String, width: u32, height: u32) -> Result<image::RgbImage, Error> {
+    try_generate_with_options(source, width, height, &ParseOptions::default())
+}
+
+/// Generate an image from a source string parsed with the given options like
+/// [generate_with_options], failing if the image has no pixels or a strict parse rejects the source
+pub fn try_generate_with_options(
+    source: String,
+    width: u32,
+    height: u32,
+    options: &ParseOptions,
+) -> Result<image::RgbImage, Error> {
+    // The size is checked first, so invalid sizes fail without parsing.
+    renderer::check_size(width, height)?;
+    let expr = try_parse_source_with_options(source, options)?;
+    Ok(renderer::render_with_version(
+        &expr,
+        width,
+        height,
+        options.version,
+    ))
+}
+
+/// Generate an image from a source string with a seed. Every seed gives another variation of the
+/// image, and seed 0 gives the same image as [generate]
+pub fn generate_with_seed(source: String, width: u32, height: u32, seed: u64) -> image::RgbImage {
+    let expr = parse_source_with_seed(source, seed);
+    renderer::render(&expr, width, height)
+}
+
+/// Generate a contact sheet of a source string, with a thumbnail of the given size for every seed.
+/// The thumbnails are laid out in a grid with the given number of columns and labeled with their
+/// seed, which [generate_with_seed] renders again at any size
+pub fn generate_gallery(
+    source: String,
+    width: u32,
+    height: u32,
+    seeds: Range<u64>,
+    columns: u32,
+) -> image::RgbImage {
+    let thumbnails: Vec<_> = seeds
+        .map(|seed| {
+            (
+                seed,
+                generate_with_seed(source.clone(), width, height, seed),
+            )
+        })
+        .collect();
+    contact_sheet(&thumbnails, columns)
+}
+
+/// Generate an image from any bytes, such as the contents of a binary file
+pub fn generate_from_bytes(bytes: &[u8], width: u32, height: u32) -> image::RgbImage {
+    let expr = parse_source(bytes);
+    renderer::render(&expr, width, height)
+}
+
+/// Generate the hash visualization of any data, such as a key or a fingerprint
+pub fn visualize_hash(data: &[u8], width: u32, height: u32) -> image::RgbImage {
+    let expr = parse_hash(data);
+    renderer::render(&expr, width, height)
+}
+
+/// Generate the hash visualization of any data the way a version of the language does. The image
+/// of some data never changes for a given version
+pub fn visualize_hash_with_version(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    version: LanguageVersion,
+) -> image::RgbImage {
+    let expr = parse_hash_with_version(data, version);
+    renderer::render_with_version(&expr, width, height, version)
+}
+
+/// Filter an image with a source string. The filtered image has the same size as the original
+pub fn apply_filter(source: String, image: &image::RgbImage) -> image::RgbImage {
+    let expr = parse_filter(source);
+    let expr = bind_source(expr, Texture::new(image.clone(), Sampler::default()));
+    renderer::render(&expr, image.width(), image.height())
+}
@@ -1,109 +1,484 @@
-use clap::Parser;
-use image::GenericImage;
-use std::{fs, process::exit, sync::mpsc, thread};
+use image::{
+    codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding},
+    ExtendedColorType, GenericImage, ImageEncoder,
+};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    process::exit,
+    rc::Rc,
+    sync::mpsc,
+    thread,
+};
 
 mod cli;
 mod compiler;
+mod error;
+mod gallery;
 mod renderer;
 
-use compiler::parser;
+use compiler::{
+    ast::Expr,
+    constant_evaluator, formatter, hash, lexer,
+    parser::{self, ParseOptions},
+    simplifier,
+    texture::{self, Sampler, Texture},
+};
 
-fn main() {
-    let opts = cli::Cli::parse();
+/// Size of images when no size is given.
+const DEFAULT_SIZE: (u32, u32) = (1200, 1200);
+
+/// Size of hash visualizations when no size is given.
+const DEFAULT_HASH_SIZE: (u32, u32) = (256, 256);
 
-    let source = match fs::read_to_string(&opts.input) {
-        Ok(s) => s,
+/// Size of the thumbnails of galleries when no size is given.
+const DEFAULT_THUMBNAIL_SIZE: (u32, u32) = (200, 200);
+
+/// Read an image, exiting if it cannot be read.
+fn read_image(path: &Path) -> image::RgbImage {
+    match image::open(path) {
+        Ok(image) => image.to_rgb8(),
         Err(e) => {
-            eprintln!("Error reading file '{}': {}", opts.input.display(), e);
+            eprintln!("Error reading image '{}': {}", path.display(), e);
             exit(1);
         }
-    };
+    }
+}
 
-    let lexer = compiler::lexer::Lexer::new(source);
-    let source = lexer.source();
-    let tokens: Vec<_> = lexer.collect();
+/// Whether a path refers to stdin or stdout.
+fn is_std_stream(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
 
-    if opts.print_tokens {
-        println!("Tokens:");
-        for token in &tokens {
-            println!("\t{:?}", token.kind);
+/// Read the raw bytes of a file, or of stdin if the path is `-`. Exits if the input cannot be read.
+fn read_input(path: &Path) -> Vec<u8> {
+    let result = match is_std_stream(path) {
+        true => {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+        }
+        false => fs::read(path),
+    };
+    match result {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Error reading file '{}': {}", path.display(), e);
+            exit(1);
         }
     }
+}
 
-    let on_retry = match opts.verbose {
-        true => || println!("Expression returned constant, retrying ..."),
-        false => || {},
-    };
-    let expr = parser::parse_tokens(tokens, source.clone(), on_retry);
+/// Encode an image in the given format.
+fn encode(image: &image::RgbImage, format: cli::Format) -> image::ImageResult<Vec<u8>> {
+    // Most encoders need to seek, so the image is encoded in memory.
+    let mut bytes = io::Cursor::new(Vec::new());
+    match format {
+        // Without a subtype, RGB images are written as PAM.
+        cli::Format::Ppm => PnmEncoder::new(&mut bytes)
+            .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary))
+            .write_image(
+                image,
+                image.width(),
+                image.height(),
+                ExtendedColorType::Rgb8,
+            )?,
+        format => image.write_to(&mut bytes, format.into())?,
+    }
+    Ok(bytes.into_inner())
+}
 
-    if opts.print_expr {
-        println!("{}", expr);
+/// Save an image to a file, or to stdout if the path is `-`. Without a format, the format is
+/// inferred from the extension of the file, or PNG for stdout. Exits if the image cannot be saved.
+fn save_image(image: &image::RgbImage, path: &Path, format: Option<cli::Format>, verbose: bool) {
+    if verbose {
+        eprintln!("Writing image to '{}' ...", path.display());
     }
 
-    if opts.print_ast {
-        expr.print_ast(source);
+    let result = match (is_std_stream(path), format) {
+        (false, None) => image.save(path),
+        (to_stdout, format) => {
+            encode(image, format.unwrap_or(cli::Format::Png)).and_then(|bytes| {
+                match to_stdout {
+                    true => io::stdout().lock().write_all(&bytes)?,
+                    false => fs::write(path, bytes)?,
+                }
+                Ok(())
+            })
+        }
+    };
+
+    let _ = result.map_err(|e| {
+        eprintln!("Error saving image to '{}': {}", path.display(), e);
+        exit(1);
+    });
+
+    if verbose {
+        eprintln!("Write successful!");
     }
+}
+
+/// Lex a program. One token more than the limit is kept, so strict parses know that the rest of
+/// the input would be ignored.
+fn lex(bytes: Vec<u8>, options: &ParseOptions) -> (Vec<lexer::Token>, Rc<Vec<u8>>) {
+    let lexer = lexer::Lexer::with_version(bytes, options.version);
+    let source = lexer.source();
+    let max_tokens = options.limits.max_tokens.saturating_add(1);
+    (lexer.take(max_tokens).collect(), source)
+}
 
-    if opts.dry_run {
-        return;
+/// Parse a program, retrying while it is constant, and fold its constants. Exits if a strict
+/// parse rejects the program.
+fn parse(
+    tokens: Vec<lexer::Token>,
+    source: Rc<Vec<u8>>,
+    path: &Path,
+    options: ParseOptions,
+    verbose: bool,
+) -> Expr {
+    let on_retry = |_, reason| eprintln!("{reason}, retrying ...");
+    let options = ParseOptions {
+        on_retry: verbose.then_some(&on_retry as &dyn Fn(usize, parser::RetryReason)),
+        ..options
+    };
+    match parser::try_parse_tokens(tokens, source, &options) {
+        Ok(expr) => constant_evaluator::evaluate_constants(expr),
+        Err(e) => {
+            eprintln!("Error parsing '{}': {}", path.display(), e);
+            exit(1);
+        }
     }
+}
 
+/// Read and parse a program.
+fn compile(path: &Path, options: ParseOptions, verbose: bool) -> Expr {
+    let (tokens, source) = lex(read_input(path), &options);
+    parse(tokens, source, path, options, verbose)
+}
+
+/// Load the images that programs can sample.
+fn load_textures(args: &cli::TextureArgs) -> (Vec<Texture>, Sampler) {
+    let sampler = Sampler {
+        filter: args.sampling.into(),
+        address: args.address.into(),
+    };
+    let textures = args
+        .input_images
+        .iter()
+        .map(|path| Texture::new(read_image(path), sampler))
+        .collect();
+    (textures, sampler)
+}
+
+/// Render an expression with multiple threads, or its heatmap of how many expressions are
+/// evaluated per pixel.
+fn render_image(
+    expr: &Expr,
+    (width, height): (u32, u32),
+    args: &cli::ImageArgs,
+    heatmap: bool,
+) -> image::RgbImage {
     const PARTS: u32 = 10;
 
-    let mut image = image::RgbImage::new(opts.width, opts.height);
+    let mut image = image::RgbImage::new(width, height);
 
     let mut part = 0;
     let mut done_parts = 0;
 
+    let mut stats = args.stats.then(|| compiler::stats::analyze(expr));
+
     let (tx, rx) = mpsc::channel();
 
     while part < PARTS {
         let mut threads_running = 0;
 
-        for _ in 0..opts.threads {
+        for _ in 0..args.threads {
             if part >= PARTS {
                 break;
             }
             threads_running += 1;
             let tx = tx.clone();
             let expr = expr.clone();
+            let with_stats = args.stats;
             thread::spawn(move || {
-                let width = opts.width;
-                let height = opts.height;
                 let start_y = part * height / PARTS;
                 let end_y = (part + 1) * height / PARTS;
-                let sub_image =
-                    renderer::render_subimage(&expr, (0, width), (start_y, end_y), width, height);
-                tx.send((start_y, sub_image)).unwrap();
+                let (x, y) = ((0, width), (start_y, end_y));
+                let (sub_image, stats) = if heatmap {
+                    let image = renderer::render_heatmap_subimage(&expr, x, y, width, height);
+                    (image, None)
+                } else if with_stats {
+                    let (image, stats) =
+                        renderer::render_subimage_with_stats(&expr, x, y, width, height);
+                    (image, Some(stats))
+                } else {
+                    (renderer::render_subimage(&expr, x, y, width, height), None)
+                };
+                tx.send((start_y, sub_image, stats)).unwrap();
             });
             part += 1;
         }
 
         while threads_running > 0 {
-            let (start_y, sub_image) = rx.recv().unwrap();
+            let (start_y, sub_image, part_stats) = rx.recv().unwrap();
             image.copy_from(&sub_image, 0, start_y).unwrap();
+            if let (Some(stats), Some(part_stats)) = (&mut stats, part_stats) {
+                stats.merge(&part_stats);
+            }
 
-            if opts.verbose {
+            if args.verbose {
                 done_parts += 1;
-                println!("Rendering {}% ...", done_parts * 100 / PARTS);
+                eprintln!("Rendering {}% ...", done_parts * 100 / PARTS);
             }
 
             threads_running -= 1;
         }
     }
 
-    let out_file = &opts.output;
+    if let Some(stats) = stats {
+        eprint!("{stats}");
+    }
+
+    image
+}
+
+/// Render a program, with its filter and passes.
+fn render(args: &cli::RenderArgs) {
+    let verbose = args.image.verbose;
+    let options = ParseOptions {
+        seed: args.seed.seed,
+        filter: args.filter.is_some(),
+        ..args.parse.options()
+    };
+    let (textures, sampler) = load_textures(&args.textures);
+
+    let expr = compile(&args.input, options, verbose);
+    let mut expr = texture::bind_textures(expr, textures.clone());
+
+    // Every pass is a filter of the image rendered before it.
+    let passes: Vec<_> = args
+        .passes
+        .iter()
+        .map(|path| {
+            compile(
+                path,
+                ParseOptions {
+                    filter: true,
+                    ..options
+                },
+                verbose,
+            )
+        })
+        .map(|pass| texture::bind_textures(pass, textures.clone()))
+        .collect();
 
-    if opts.verbose {
-        println!("Writing image to '{out_file}' ...");
+    // Filters default to the size of the image they filter.
+    let mut size = DEFAULT_SIZE;
+    if let Some(path) = &args.filter {
+        let source = read_image(path);
+        size = source.dimensions();
+        expr = texture::bind_source(expr, Texture::new(source, sampler));
     }
+    let size = args.image.size(size);
+
+    // Passes filter the colors of the image before them, so only the last image is a heatmap.
+    let total = args.repeat as usize * passes.len();
+    let heatmap = args.image.profile_heatmap;
+    let mut image = render_image(&expr, size, &args.image, heatmap && total == 0);
+
+    for i in 0..args.repeat {
+        for (j, pass) in passes.iter().enumerate() {
+            let n = i as usize * passes.len() + j + 1;
+            if verbose {
+                eprintln!("Pass {n}/{total} ...");
+            }
+            let pass = texture::bind_source(pass.clone(), Texture::new(image, sampler));
+            image = render_image(&pass, size, &args.image, heatmap && n == total);
+        }
+    }
+
+    save_image(&image, &args.output.output, args.output.format, verbose);
+}
 
-    let _ = image.save(out_file).map_err(|e| {
-        eprintln!("Error saving image to '{}': {}", out_file, e);
+/// Print how a program is lexed and parsed.
+fn inspect(args: &cli::InspectArgs) {
+    let options = ParseOptions {
+        seed: args.seed.seed,
+        filter: args.filter,
+        ..args.parse.options()
+    };
+    let (tokens, source) = lex(read_input(&args.input), &options);
+
+    if args.print_tokens {
+        println!("Tokens:");
+        for token in tokens.iter().take(options.limits.max_tokens) {
+            println!("\t{:?}", token.kind);
+        }
+    }
+
+    let expr = parse(tokens, source.clone(), &args.input, options, args.verbose);
+
+    if args.print_expr {
+        println!("{}", expr);
+    }
+
+    if args.print_source {
+        println!("{}", formatter::to_source(&expr));
+    }
+
+    if args.print_ast {
+        expr.print_ast(source);
+    }
+}
+
+/// Format source files, or check that they are formatted.
+fn fmt(args: &cli::FmtArgs) {
+    let mut unformatted = false;
+    // The source is parsed once, as retries would format another program than the one written.
+    let options = ParseOptions {
+        filter: args.filter,
+        max_tries: 1,
+        ..ParseOptions::default()
+    };
+
+    for path in &args.files {
+        let source = read_input(path);
+        if !args.filter && is_filter(&source) {
+            eprintln!(
+                "Error formatting '{}': the file is a filter, format it with --filter",
+                path.display()
+            );
+            exit(1);
+        }
+        let mut expr = parser::parse_source_with_options(source.as_slice(), &options);
+        if args.evaluate_constants {
+            expr = simplifier::simplify(expr);
+        }
+
+        let formatted = formatter::format_source(&expr, args.width);
+        let reparsed = parser::parse_source_with_options(formatted.as_str(), &options);
+        if formatter::to_source(&reparsed) != formatter::to_source(&expr) {
+            eprintln!(
+                "Error formatting '{}': the formatted program parses differently",
+                path.display()
+            );
+            exit(1);
+        }
+        if is_std_stream(path) && !args.check {
+            let _ = io::stdout().write_all(formatted.as_bytes());
+            continue;
+        }
+        if formatted.as_bytes() == source {
+            continue;
+        }
+
+        if args.check {
+            println!("'{}' is not formatted", path.display());
+            unformatted = true;
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("Error writing file '{}': {}", path.display(), e);
+            exit(1);
+        }
+    }
+
+    if unformatted {
         exit(1);
-    });
+    }
+}
+
+/// Check if a source is a filter written as one, which reads the source pixel without repairs.
+/// Parsed as a program, its `p` would be repaired into something else.
+fn is_filter(source: &[u8]) -> bool {
+    let options = ParseOptions {
+        filter: true,
+        max_tries: 1,
+        strict: true,
+        ..ParseOptions::default()
+    };
+    parser::try_parse_source_with_options(source, &options).is_ok_and(|expr| expr.uses_pixel())
+}
+
+/// Render the hash visualization of a file.
+fn hash(args: &cli::HashArgs) {
+    let expr = hash::parse_hash(&read_input(&args.input));
+    let size = args.image.size(DEFAULT_HASH_SIZE);
+    let image = render_image(&expr, size, &args.image, args.image.profile_heatmap);
+    save_image(
+        &image,
+        &args.output.output,
+        args.output.format,
+        args.image.verbose,
+    );
+}
+
+/// Render every program into the output directory.
+fn batch(args: &cli::BatchArgs) {
+    let (textures, _) = load_textures(&args.textures);
+    let size = args.image.size(DEFAULT_SIZE);
+    let options = ParseOptions {
+        seed: args.seed.seed,
+        ..args.parse.options()
+    };
+
+    for path in &args.inputs {
+        if args.image.verbose || args.image.stats {
+            eprintln!("Rendering '{}' ...", path.display());
+        }
+        let expr = compile(path, options, args.image.verbose);
+        let expr = texture::bind_textures(expr, textures.clone());
+        let image = render_image(&expr, size, &args.image, args.image.profile_heatmap);
+
+        let mut name = path.file_name().unwrap_or(path.as_os_str()).to_owned();
+        name.push(".");
+        name.push(args.format.extension());
+        let path = args.out_dir.join(name);
+        save_image(&image, &path, Some(args.format), args.image.verbose);
+    }
+}
+
+/// Render a program under many seeds into a contact sheet.
+fn gallery(args: &cli::GalleryArgs) {
+    let verbose = args.image.verbose;
+    let options = args.parse.options();
+    let (textures, _) = load_textures(&args.textures);
+    let size = args.image.size(DEFAULT_THUMBNAIL_SIZE);
+
+    // The input is read once, since stdin can not be read again for every seed.
+    let (tokens, source) = lex(read_input(&args.input), &options);
+
+    let thumbnails: Vec<_> = (0..args.count)
+        .map(|i| {
+            let seed = args.first_seed.wrapping_add(i);
+            if verbose {
+                eprintln!("Rendering seed {seed} ...");
+            }
+            let options = ParseOptions { seed, ..options };
+            let expr = parse(
+                tokens.clone(),
+                source.clone(),
+                &args.input,
+                options,
+                verbose,
+            );
+            let expr = texture::bind_textures(expr, textures.clone());
+            let heatmap = args.image.profile_heatmap;
+            (seed, render_image(&expr, size, &args.image, heatmap))
+        })
+        .collect();
+
+    let sheet = gallery::contact_sheet(&thumbnails, args.columns);
+    save_image(&sheet, &args.output.output, args.output.format, verbose);
+}
+
+fn main() {
+    let opts = cli::Cli::parse_args();
 
-    if opts.verbose {
-        println!("Write successful!");
+    match &opts.command {
+        cli::Command::Render(args) => render(args),
+        cli::Command::Inspect(args) => inspect(args),
+        cli::Command::Fmt(args) => fmt(args),
+        cli::Command::Hash(args) => hash(args),
+        cli::Command::Batch(args) => batch(args),
+        cli::Command::Gallery(args) => gallery(args),
     }
 }
@@ -1,8 +1,21 @@
 #![allow(dead_code)] // TODO: Find a better solution
 
+use std::ops::Range;
+
 use image::{self, Rgb, RgbImage};
 
-use crate::compiler::{ast::Expr, evaluator::eval};
+use crate::compiler::{
+    ast::Expr,
+    evaluator::{eval_observed, Observer},
+    interval::{eval_color_bounds, Interval},
+    stats::{max_evaluated, Recorder, Stats},
+    texture::{bind_source, Sampler, Texture},
+    version::LanguageVersion,
+};
+use crate::error::Error;
+
+/// Width and height of the tiles that are checked for a uniform color before rendering.
+const TILE_SIZE: u32 = 16;
 
 /// Convert a f64 color to a u8 color
 fn f64_color_to_u8(color: f64) -> u8 {
@@ -16,6 +29,123 @@ fn normalize(x: u32, y: u32, width: u32, height: u32) -> (f64, f64) {
     (nx, ny)
 }
 
+/// Render a single pixel at normalized coordinates
+fn render_pixel<O: Observer>(
+    expr: &Expr,
+    nx: f64,
+    ny: f64,
+    version: LanguageVersion,
+    obs: &mut O,
+) -> Rgb<u8> {
+    let c = eval_observed(expr, nx, ny, version, obs);
+    Rgb([
+        f64_color_to_u8(c.r),
+        f64_color_to_u8(c.g),
+        f64_color_to_u8(c.b),
+    ])
+}
+
+/// The color of every pixel in a region, if interval arithmetic proves that they are all the same
+fn uniform_color(
+    expr: &Expr,
+    x: Interval,
+    y: Interval,
+    version: LanguageVersion,
+) -> Option<Rgb<u8>> {
+    let c = eval_color_bounds(expr, x, y, version)?;
+    // Pad the bounds to make up for rounding errors in the clamping function
+    let channel = |i: Interval| {
+        let lo = f64_color_to_u8(i.lo - 1e-9);
+        let hi = f64_color_to_u8(i.hi + 1e-9);
+        (lo == hi).then_some(lo)
+    };
+    Some(Rgb([channel(c.r)?, channel(c.g)?, channel(c.b)?]))
+}
+
+/// Render the given rows of an image, which is located at `offset` in a larger image of the given
+/// size. The rows are rendered in tiles, and every pixel is drawn by `pixel` from its normalized
+/// coordinates and the color of its tile, if the tile has a uniform color.
+fn render_tiles(
+    image: &mut RgbImage,
+    expr: &Expr,
+    offset: (u32, u32),
+    size: (u32, u32),
+    rows: Range<u32>,
+    version: LanguageVersion,
+    mut pixel: impl FnMut(f64, f64, Option<Rgb<u8>>) -> Rgb<u8>,
+) {
+    let (width, height) = size;
+    let to_full = |x: u32, y: u32| normalize(offset.0 + x, offset.1 + y, width, height);
+
+    for tile_y in rows.clone().step_by(TILE_SIZE as usize) {
+        let end_y = (tile_y + TILE_SIZE).min(rows.end);
+        for tile_x in (0..image.width()).step_by(TILE_SIZE as usize) {
+            let end_x = (tile_x + TILE_SIZE).min(image.width());
+
+            // Normalized coordinates grow with the pixel coordinates, so the corners bound them
+            let (lo_x, lo_y) = to_full(tile_x, tile_y);
+            let (hi_x, hi_y) = to_full(end_x - 1, end_y - 1);
+            let (x, y) = (Interval::new(lo_x, hi_x), Interval::new(lo_y, hi_y));
+            let color = uniform_color(expr, x, y, version);
+
+            for y in tile_y..end_y {
+                for x in tile_x..end_x {
+                    let (nx, ny) = to_full(x, y);
+                    image.put_pixel(x, y, pixel(nx, ny, color));
+                }
+            }
+        }
+    }
+}
+
+/// Render the given rows of an image like [render_tiles]. Tiles that have a uniform color are
+/// filled without evaluating every pixel, and evaluated pixels are reported to the observer.
+fn render_rows<O: Observer>(
+    image: &mut RgbImage,
+    expr: &Expr,
+    offset: (u32, u32),
+    size: (u32, u32),
+    rows: Range<u32>,
+    version: LanguageVersion,
+    obs: &mut O,
+) {
+    render_tiles(image, expr, offset, size, rows, version, |nx, ny, color| {
+        color.unwrap_or_else(|| render_pixel(expr, nx, ny, version, obs))
+    });
+}
+
+/// Counts the expressions that are evaluated.
+struct Counter(usize);
+
+impl Observer for Counter {
+    fn visit(&mut self, _expr: &Expr) {
+        self.0 += 1;
+    }
+}
+
+/// Render the given rows of a heatmap like [render_tiles]. The brightness of a pixel is the number
+/// of expressions evaluated for it, relative to the most that any pixel can evaluate. Tiles of a
+/// uniform color are black because they are not evaluated.
+fn render_heatmap_rows(
+    image: &mut RgbImage,
+    expr: &Expr,
+    offset: (u32, u32),
+    size: (u32, u32),
+    rows: Range<u32>,
+) {
+    let max = max_evaluated(expr);
+    let version = LanguageVersion::LATEST;
+    render_tiles(image, expr, offset, size, rows, version, |nx, ny, color| {
+        if color.is_some() {
+            return Rgb([0, 0, 0]);
+        }
+        let mut counter = Counter(0);
+        eval_observed(expr, nx, ny, version, &mut counter);
+        let brightness = (counter.0.min(max) * u8::MAX as usize / max) as u8;
+        Rgb([brightness; 3])
+    });
+}
+
 /// Render sub-image defined by and x- and y-range
 pub fn render_subimage(
     expr: &Expr,
@@ -31,44 +161,83 @@ pub fn render_subimage(
     let sub_height = end_y - start_y;
 
     let mut image = RgbImage::new(sub_width, sub_height);
+    render_rows(
+        &mut image,
+        expr,
+        (start_x, start_y),
+        (width, height),
+        0..sub_height,
+        LanguageVersion::LATEST,
+        &mut (),
+    );
+    image
+}
 
-    for x in 0..sub_width {
-        for y in 0..sub_height {
-            let (nx, ny) = normalize(start_x + x, start_y + y, width, height);
-            let c = eval(expr, nx, ny);
-            image.put_pixel(
-                x,
-                y,
-                Rgb([
-                    f64_color_to_u8(c.r),
-                    f64_color_to_u8(c.g),
-                    f64_color_to_u8(c.b),
-                ]),
-            );
-        }
-    }
+/// Render sub-image like [render_subimage], with statistics of how the expression was evaluated.
+/// The statistics of all sub-images of an image can be combined with [Stats::merge].
+pub fn render_subimage_with_stats(
+    expr: &Expr,
+    x: (u32, u32),
+    y: (u32, u32),
+    width: u32,
+    height: u32,
+) -> (RgbImage, Stats) {
+    let (start_x, end_x) = x;
+    let (start_y, end_y) = y;
+
+    let mut image = RgbImage::new(end_x - start_x, end_y - start_y);
+    let mut recorder = Recorder::new(expr);
+    recorder.add_pixels(image.width() as usize * image.height() as usize);
+    render_rows(
+        &mut image,
+        expr,
+        (start_x, start_y),
+        (width, height),
+        0..end_y - start_y,
+        LanguageVersion::LATEST,
+        &mut recorder,
+    );
+    (image, recorder.finish())
+}
+
+/// Render the heatmap of a sub-image defined by an x- and y-range. Instead of colors, the
+/// brightness of every pixel shows how many expressions are evaluated to render it.
+pub fn render_heatmap_subimage(
+    expr: &Expr,
+    x: (u32, u32),
+    y: (u32, u32),
+    width: u32,
+    height: u32,
+) -> RgbImage {
+    let (start_x, end_x) = x;
+    let (start_y, end_y) = y;
+
+    let mut image = RgbImage::new(end_x - start_x, end_y - start_y);
+    render_heatmap_rows(
+        &mut image,
+        expr,
+        (start_x, start_y),
+        (width, height),
+        0..end_y - start_y,
+    );
     image
 }
 
 /// Render a part of an expression into a provided image
 pub fn render_part_into(image: &mut RgbImage, expr: &Expr, part: u32, total_parts: u32) {
-    let start_y = part * image.height() / total_parts;
-    let end_y = (part + 1) * image.height() / total_parts;
-    for y in start_y..end_y {
-        for x in 0..image.width() {
-            let (nx, ny) = normalize(x, y, image.width(), image.height());
-            let c = eval(expr, nx, ny);
-            image.put_pixel(
-                x,
-                y,
-                Rgb([
-                    f64_color_to_u8(c.r),
-                    f64_color_to_u8(c.g),
-                    f64_color_to_u8(c.b),
-                ]),
-            );
-        }
-    }
+    // Rows are computed in 64 bits, so images with many parts and rows do not overflow.
+    let row = |part: u32| (part as u64 * image.height() as u64 / total_parts as u64) as u32;
+    let (start_y, end_y) = (row(part), row(part + 1));
+    let size = (image.width(), image.height());
+    render_rows(
+        image,
+        expr,
+        (0, 0),
+        size,
+        start_y..end_y,
+        LanguageVersion::LATEST,
+        &mut (),
+    );
 }
 
 /// Render an expression into an image in parts, calling the provided function after each part
@@ -89,21 +258,16 @@ pub fn render_in_parts(
 
 /// Render an expression into a provided image
 pub fn render_into(image: &mut RgbImage, expr: &Expr) {
-    for y in 0..image.height() {
-        for x in 0..image.width() {
-            let (nx, ny) = normalize(x, y, image.width(), image.height());
-            let c = eval(expr, nx, ny);
-            image.put_pixel(
-                x,
-                y,
-                Rgb([
-                    f64_color_to_u8(c.r),
-                    f64_color_to_u8(c.g),
-                    f64_color_to_u8(c.b),
-                ]),
-            );
-        }
-    }
+    let size = (image.width(), image.height());
+    render_rows(
+        image,
+        expr,
+        (0, 0),
+        size,
+        0..image.height(),
+        LanguageVersion::LATEST,
+        &mut (),
+    );
 }
 
 /// Render an expression into an image
@@ -112,3 +276,133 @@ pub fn render(expr: &Expr, width: u32, height: u32) -> RgbImage {
     render_into(&mut image, expr);
     image
 }
+
+/// Render an expression into an image, with statistics of how the expression was evaluated
+pub fn render_with_stats(expr: &Expr, width: u32, height: u32) -> (RgbImage, Stats) {
+    render_subimage_with_stats(expr, (0, width), (0, height), width, height)
+}
+
+/// Render the heatmap of an expression. Instead of colors, the brightness of every pixel shows how
+/// many expressions are evaluated to render it, which differs across the image where conditionals
+/// take different branches.
+pub fn render_heatmap(expr: &Expr, width: u32, height: u32) -> RgbImage {
+    render_heatmap_subimage(expr, (0, width), (0, height), width, height)
+}
+
+/// Render an expression into an image, with colors clamped the way a version of the language does.
+/// Rendering with [LanguageVersion::LATEST] is the same as [render].
+pub fn render_with_version(
+    expr: &Expr,
+    width: u32,
+    height: u32,
+    version: LanguageVersion,
+) -> RgbImage {
+    let mut image = RgbImage::new(width, height);
+    render_rows(
+        &mut image,
+        expr,
+        (0, 0),
+        (width, height),
+        0..height,
+        version,
+        &mut (),
+    );
+    image
+}
+
+/// Render a chain of passes. Every pass after the first is a filter of the image rendered by the
+/// pass before it, so `p` is the color of the previous pass. Passes can be repeated to iterate an
+/// effect, like a blur or a cellular automaton.
+pub fn render_passes(passes: &[Expr], width: u32, height: u32) -> RgbImage {
+    let Some((first, rest)) = passes.split_first() else {
+        return RgbImage::new(width, height);
+    };
+    let mut image = render(first, width, height);
+    for pass in rest {
+        let pass = bind_source(pass.clone(), Texture::new(image, Sampler::default()));
+        image = render(&pass, width, height);
+    }
+    image
+}
+
+/// Check that an image of the given size has pixels, and that its bytes can be addressed.
+pub(crate) fn check_size(width: u32, height: u32) -> Result<(), Error> {
+    let bytes = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(3));
+    match bytes {
+        Some(bytes) if bytes > 0 => Ok(()),
+        _ => Err(Error::InvalidDimensions { width, height }),
+    }
+}
+
+/// Check that a part is one of the parts of an image.
+fn check_part(part: u32, total_parts: u32) -> Result<(), Error> {
+    match part < total_parts {
+        true => Ok(()),
+        false => Err(Error::InvalidPart { part, total_parts }),
+    }
+}
+
+/// Render an expression into an image like [render], failing if the image has no pixels
+pub fn try_render(expr: &Expr, width: u32, height: u32) -> Result<RgbImage, Error> {
+    check_size(width, height)?;
+    Ok(render(expr, width, height))
+}
+
+/// Render an expression into a provided image like [render_into], failing if the image has no
+/// pixels
+pub fn try_render_into(image: &mut RgbImage, expr: &Expr) -> Result<(), Error> {
+    check_size(image.width(), image.height())?;
+    render_into(image, expr);
+    Ok(())
+}
+
+/// Render a part of an expression into a provided image like [render_part_into], failing if the
+/// image has no pixels or the part is not one of its parts
+pub fn try_render_part_into(
+    image: &mut RgbImage,
+    expr: &Expr,
+    part: u32,
+    total_parts: u32,
+) -> Result<(), Error> {
+    check_size(image.width(), image.height())?;
+    check_part(part, total_parts)?;
+    render_part_into(image, expr, part, total_parts);
+    Ok(())
+}
+
+/// Render an expression into an image in parts like [render_in_parts], failing if the image has no
+/// pixels or no parts
+pub fn try_render_in_parts(
+    expr: &Expr,
+    width: u32,
+    height: u32,
+    total_parts: u32,
+    f: impl Fn(u32),
+) -> Result<RgbImage, Error> {
+    check_size(width, height)?;
+    check_part(0, total_parts)?;
+    Ok(render_in_parts(expr, width, height, total_parts, f))
+}
+
+/// Render sub-image like [render_subimage], failing if the sub-image has no pixels or is not
+/// within the image
+pub fn try_render_subimage(
+    expr: &Expr,
+    x: (u32, u32),
+    y: (u32, u32),
+    width: u32,
+    height: u32,
+) -> Result<RgbImage, Error> {
+    check_size(width, height)?;
+    if x.0 >= x.1 || x.1 > width || y.0 >= y.1 || y.1 > height {
+        return Err(Error::InvalidRegion {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+    Ok(render_subimage(expr, x, y, width, height))
+}
@@ -57,7 +57,7 @@ rec {
     record-examples = mkTask "record-examples" {
         script = /*bash*/ ''
             ls ./examples | xargs -I{} bash -c \
-                "mkdir -p tests/ ; echo 'Recording example {}' ; ./target/release/ssl examples/{} --expr --dry-run > tests/{}.expr"
+                "mkdir -p tests/ ; echo 'Recording example {}' ; ./target/release/ssl inspect examples/{} --expr > tests/{}.expr"
             '';
         depends = [ build ];
     };
@@ -65,12 +65,18 @@ rec {
     check-examples = mkTask "check-examples" {
         script = /*bash*/ ''
             ls ./examples | xargs -I{} bash -c \
-                "echo 'Checking example {}' ; ./target/release/ssl examples/{} --expr --dry-run | diff - tests/{}.expr || exit 1" \
+                "echo 'Checking example {}' ; ./target/release/ssl inspect examples/{} --expr | diff - tests/{}.expr || exit 1" \
                 || { echo "Example AST has changed." ; exit 1 ; }
             '';
         depends = [ build ];
     };
 
+    record-golden = mkTask "record-golden" {
+        script = /*bash*/ ''
+            SSL_BLESS=1 cargo test --test examples
+            '';
+    };
+
     demo-build = mkTask "demo-build" {
         script = /*bash*/ ''
             wasm-pack build --target web "`${root}`/demo"
@@ -135,6 +141,22 @@ rec {
         '';
     };
 
+    fuzz = mkTask "fuzz" {
+        script = /*bash*/ ''
+            root="`${root}`"
+            target=$1
+            if [ -z "$target" ]; then
+                target=parser
+            fi
+
+            shift
+
+            # New inputs are written to the first corpus, the examples only seed it
+            mkdir -p "$root/fuzz/corpus/$target"
+            cd "$root" && cargo +nightly fuzz run "$target" "fuzz/corpus/$target" examples $@
+        '';
+    };
+
     gen-scripts = gen.gen-scripts "gen-scripts";
 
     gen-random = mkTask "gen-random" {
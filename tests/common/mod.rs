@@ -0,0 +1,109 @@
+//! Golden images shared by the integration tests. Set `SSL_BLESS=1` to record new golden images
+//! instead of comparing against them.
+
+#![allow(dead_code)] // Not every test uses every helper.
+
+use std::path::{Path, PathBuf};
+
+use ssl::RgbImage;
+
+/// How much an image may differ from its golden image.
+pub struct Tolerance {
+    /// Largest difference in the perceived brightness of a pixel, on a scale of 0 to 255, before it
+    /// counts as changed.
+    pub distance: f64,
+    /// Largest difference in any channel of a pixel before it counts as changed.
+    pub channel: u8,
+    /// Fraction of the pixels that may change.
+    pub changed: f64,
+}
+
+impl Tolerance {
+    /// Only allow the rounding differences between platforms, one step in every channel.
+    pub const EXACT: Self = Self {
+        distance: 1.0,
+        channel: 1,
+        changed: 0.0,
+    };
+}
+
+/// Path of a golden image in `tests/golden`.
+pub fn golden_path(dir: &str, name: &str) -> PathBuf {
+    let mut path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", dir, name]
+        .iter()
+        .collect();
+    path.as_mut_os_string().push(".png");
+    path
+}
+
+/// Difference between two pixels, weighting the channels by how bright they are perceived.
+fn distance(a: &image::Rgb<u8>, b: &image::Rgb<u8>) -> f64 {
+    const WEIGHTS: [f64; 3] = [0.299, 0.587, 0.114];
+    (0..3)
+        .map(|c| WEIGHTS[c] * a[c].abs_diff(b[c]) as f64)
+        .sum()
+}
+
+/// An image of the golden image with the changed pixels in red.
+fn diff_image(golden: &RgbImage, changed: &[(u32, u32)]) -> RgbImage {
+    let mut diff = RgbImage::from_fn(golden.width(), golden.height(), |x, y| {
+        let p = golden.get_pixel(x, y);
+        let gray = (p[0] as u32 + p[1] as u32 + p[2] as u32) / 6;
+        image::Rgb([gray as u8; 3])
+    });
+    for &(x, y) in changed {
+        diff.put_pixel(x, y, image::Rgb([255, 0, 0]));
+    }
+    diff
+}
+
+/// Compare an image with its golden image. On failure, the image and a diff image are written to
+/// the temporary directory of the tests.
+pub fn check_golden(path: &Path, image: &RgbImage, tolerance: Tolerance) {
+    if std::env::var_os("SSL_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(path).unwrap();
+        return;
+    }
+
+    let golden = image::open(path)
+        .unwrap_or_else(|e| panic!("Missing golden image '{}': {e}", path.display()))
+        .to_rgb8();
+    assert_eq!(
+        golden.dimensions(),
+        image.dimensions(),
+        "Size of '{}' changed",
+        path.display()
+    );
+
+    let changed: Vec<_> = image
+        .enumerate_pixels()
+        .filter(|(x, y, pixel)| {
+            let golden = golden.get_pixel(*x, *y);
+            let channel = (0..3).map(|c| pixel[c].abs_diff(golden[c])).max().unwrap();
+            distance(pixel, golden) > tolerance.distance || channel > tolerance.channel
+        })
+        .map(|(x, y, _)| (x, y))
+        .collect();
+
+    let pixels = (image.width() * image.height()) as f64;
+    if changed.len() as f64 <= tolerance.changed * pixels {
+        return;
+    }
+
+    let name = path.file_stem().unwrap().to_string_lossy();
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&dir).unwrap();
+    let actual = dir.join(format!("{name}.actual.png"));
+    let diff = dir.join(format!("{name}.diff.png"));
+    image.save(&actual).unwrap();
+    diff_image(&golden, &changed).save(&diff).unwrap();
+
+    panic!(
+        "{} of {pixels} pixels changed in '{}'. See '{}' and '{}'",
+        changed.len(),
+        path.display(),
+        actual.display(),
+        diff.display()
+    );
+}
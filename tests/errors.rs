@@ -0,0 +1,119 @@
+//! The fallible API, which reports what the other functions would repair, truncate or panic on.
+
+use std::error::Error as _;
+
+use ssl::{
+    generate, parse_source, render_subimage, try_generate, try_generate_with_options, try_render,
+    try_render_in_parts, try_render_into, try_render_part_into, try_render_subimage, Error, Limit,
+    Limits, ParseOptions, RgbImage,
+};
+
+const SOURCE: &str = "Hello, I am a random string!";
+
+/// Whether a result is the error of an image of the given size.
+fn is_invalid_size<T>(result: Result<T, Error>, size: (u32, u32)) -> bool {
+    matches!(result, Err(Error::InvalidDimensions { width, height }) if (width, height) == size)
+}
+
+#[test]
+fn valid_sizes_render_like_the_infallible_api() {
+    let image = try_generate(SOURCE.to_string(), 24, 16).unwrap();
+    assert_eq!(image, generate(SOURCE.to_string(), 24, 16));
+
+    let expr = parse_source(SOURCE);
+    let mut parts = RgbImage::new(24, 16);
+    for part in 0..3 {
+        try_render_part_into(&mut parts, &expr, part, 3).unwrap();
+    }
+    assert_eq!(parts, image);
+    assert_eq!(
+        try_render_in_parts(&expr, 24, 16, 3, |_| ()).unwrap(),
+        image
+    );
+
+    let subimage = try_render_subimage(&expr, (4, 20), (0, 8), 24, 16).unwrap();
+    assert_eq!(subimage, render_subimage(&expr, (4, 20), (0, 8), 24, 16));
+}
+
+#[test]
+fn empty_images_are_invalid() {
+    let expr = parse_source(SOURCE);
+    for size in [(0, 16), (16, 0), (0, 0)] {
+        let (width, height) = size;
+        let invalid = try_generate(SOURCE.to_string(), width, height);
+        assert!(is_invalid_size(invalid, size));
+        assert!(is_invalid_size(try_render(&expr, width, height), size));
+        let mut image = RgbImage::new(width, height);
+        assert!(is_invalid_size(try_render_into(&mut image, &expr), size));
+    }
+}
+
+#[test]
+fn parts_and_regions_must_be_within_the_image() {
+    let expr = parse_source(SOURCE);
+    let mut image = RgbImage::new(16, 16);
+    assert!(matches!(
+        try_render_part_into(&mut image, &expr, 3, 3),
+        Err(Error::InvalidPart {
+            part: 3,
+            total_parts: 3
+        })
+    ));
+    assert!(matches!(
+        try_render_in_parts(&expr, 16, 16, 0, |_| ()),
+        Err(Error::InvalidPart { .. })
+    ));
+
+    for (x, y) in [((4, 4), (0, 8)), ((0, 17), (0, 8)), ((8, 4), (0, 8))] {
+        assert!(
+            matches!(
+                try_render_subimage(&expr, x, y, 16, 16),
+                Err(Error::InvalidRegion { .. })
+            ),
+            "{x:?} {y:?}"
+        );
+    }
+}
+
+#[test]
+fn strict_options_reject_what_would_be_repaired() {
+    let strict = ParseOptions {
+        strict: true,
+        ..ParseOptions::default()
+    };
+    assert!(try_generate_with_options("x * y".to_string(), 8, 8, &strict).is_ok());
+
+    let error = try_generate_with_options("sin(x".to_string(), 8, 8, &strict).unwrap_err();
+    assert!(matches!(error, Error::Parse(_)));
+    assert_eq!(error.to_string(), "expected `)` at 5..5");
+    assert!(error.source().is_some());
+
+    let limited = ParseOptions {
+        limits: Limits {
+            max_nodes: 2,
+            ..Limits::DEFAULT
+        },
+        ..strict
+    };
+    let error = try_generate_with_options("x * y".to_string(), 8, 8, &limited).unwrap_err();
+    assert!(matches!(
+        error,
+        Error::LimitExceeded {
+            limit: Limit::Nodes,
+            max: 2
+        }
+    ));
+
+    // Without strictness, the source is repaired as usual.
+    assert!(try_generate("sin(x".to_string(), 8, 8).is_ok());
+}
+
+#[test]
+fn io_and_image_errors_convert() {
+    fn read(path: &str) -> Result<RgbImage, Error> {
+        std::fs::read(path)?;
+        Ok(image::open(path)?.to_rgb8())
+    }
+    assert!(matches!(read("does/not/exist.png"), Err(Error::Io(_))));
+    assert!(matches!(read("Cargo.toml"), Err(Error::Encoding(_))));
+}
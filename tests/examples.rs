@@ -0,0 +1,51 @@
+//! Golden images of the examples, so changes to the evaluator or the renderer that change how the
+//! examples look do not go unnoticed.
+//!
+//! Run with `SSL_BLESS=1` to record the images again after an intended change.
+
+mod common;
+
+use std::path::Path;
+
+use common::{check_golden, golden_path, Tolerance};
+use ssl::RgbImage;
+
+/// Width and height of the golden images.
+const SIZE: u32 = 64;
+
+/// Examples that are filters, with the example whose image they filter.
+const FILTERS: &[(&str, &str)] = &[("edges.ssl", "heart.ssl")];
+
+/// Small changes in color are allowed, as long as few pixels change.
+const TOLERANCE: Tolerance = Tolerance {
+    distance: 4.0,
+    channel: u8::MAX,
+    changed: 0.005,
+};
+
+/// Render an example. Filters filter the image of the example they are listed with.
+fn render(dir: &Path, name: &str) -> RgbImage {
+    let source = std::fs::read(dir.join(name)).unwrap();
+    match FILTERS.iter().find(|(filter, _)| *filter == name) {
+        Some((_, input)) => {
+            let source = String::from_utf8(source).unwrap();
+            ssl::apply_filter(source, &render(dir, input))
+        }
+        None => ssl::generate_from_bytes(&source, SIZE, SIZE),
+    }
+}
+
+#[test]
+fn examples_match_golden_images() {
+    let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/examples"));
+    let mut names: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+
+    for name in names {
+        let image = render(dir, &name);
+        check_golden(&golden_path("examples", &name), &image, TOLERANCE);
+    }
+}
@@ -0,0 +1,52 @@
+//! The `fmt` command, which writes the formatted source back to the files.
+
+use std::{fs, path::PathBuf, process::Command};
+
+/// Write a source to a file of its own and run `ssl fmt` on it with some arguments. Returns
+/// whether the command succeeded and the file afterwards.
+fn fmt(name: &str, source: &str, args: &[&str]) -> (bool, String) {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, source).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_ssl"))
+        .arg("fmt")
+        .args(args)
+        .arg(&path)
+        .status()
+        .unwrap();
+    (status.success(), fs::read_to_string(&path).unwrap())
+}
+
+#[test]
+fn filters_keep_the_source_pixel() {
+    let (ok, formatted) = fmt("filter.ssl", "p*0.5+x", &["--filter"]);
+    assert!(ok);
+    assert_eq!(formatted, "p * 0.5 + x\n");
+
+    // Without `--filter`, the file is left alone instead of repairing `p`.
+    let (ok, unchanged) = fmt("unflagged-filter.ssl", "p*0.5+x", &[]);
+    assert!(!ok);
+    assert_eq!(unchanged, "p*0.5+x");
+}
+
+#[test]
+fn programs_are_formatted() {
+    let (ok, formatted) = fmt("program.ssl", "x*(y+r)", &[]);
+    assert!(ok);
+    assert_eq!(formatted, "x * (y + r)\n");
+
+    // Prose is not a filter, even though it has a `p`.
+    let (ok, formatted) = fmt("prose.ssl", "Hello people", &[]);
+    assert!(ok);
+    assert_ne!(formatted, "Hello people");
+}
+
+#[test]
+fn constants_are_evaluated_and_simplified() {
+    let (ok, formatted) = fmt(
+        "constants.ssl",
+        "cos(-x) + 2.0 * 3.0",
+        &["--evaluate-constants"],
+    );
+    assert!(ok);
+    assert_eq!(formatted, "cos(x) + 6.0\n");
+}
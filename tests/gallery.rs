@@ -0,0 +1,67 @@
+//! Contact sheets of thumbnails, like the galleries of a source under many seeds.
+
+use image::{GenericImageView, Rgb};
+use ssl::{contact_sheet, generate_gallery, generate_with_seed, RgbImage};
+
+/// Whether an image contains another image anywhere.
+fn contains(sheet: &RgbImage, image: &RgbImage) -> bool {
+    let (width, height) = image.dimensions();
+    (0..=sheet.height() - height).any(|y| {
+        (0..=sheet.width() - width)
+            .any(|x| sheet.view(x, y, width, height).to_image().as_raw() == image.as_raw())
+    })
+}
+
+#[test]
+fn sheet_has_a_cell_for_every_thumbnail() {
+    let colors = [
+        [255, 0, 0],
+        [0, 255, 0],
+        [0, 0, 255],
+        [255, 255, 0],
+        [0, 255, 255],
+    ];
+    let thumbnails: Vec<_> = colors
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| (i as u64, RgbImage::from_pixel(10, 10, Rgb(c))))
+        .collect();
+
+    let sheet = contact_sheet(&thumbnails, 2);
+    let two_rows = contact_sheet(&thumbnails[..4], 2);
+    assert_eq!(sheet.width(), two_rows.width());
+    assert!(sheet.height() > two_rows.height() + 10);
+    for c in colors {
+        assert_eq!(sheet.pixels().filter(|p| p.0 == c).count(), 100, "{c:?}");
+    }
+}
+
+#[test]
+fn thumbnails_are_labeled() {
+    let thumbnail = RgbImage::new(40, 40);
+    let one = contact_sheet(&[(1, thumbnail.clone())], 1);
+    let eight = contact_sheet(&[(8, thumbnail.clone())], 1);
+    let eighteen = contact_sheet(&[(18, thumbnail)], 1);
+
+    assert_eq!(one.dimensions(), eight.dimensions());
+    assert_ne!(one, eight);
+    assert_ne!(eight, eighteen);
+}
+
+#[test]
+fn columns_are_limited_to_the_thumbnails() {
+    let thumbnails = [(0, RgbImage::new(10, 10)), (1, RgbImage::new(10, 10))];
+    let sheet = contact_sheet(&thumbnails, 2);
+    assert_eq!(contact_sheet(&thumbnails, u32::MAX), sheet);
+    assert_eq!(contact_sheet(&[], u32::MAX).dimensions(), (16, 8));
+}
+
+#[test]
+fn gallery_shows_every_seed() {
+    let source = "Hello, I am a random string!";
+    let sheet = generate_gallery(source.to_string(), 16, 16, 3..6, 2);
+    for seed in 3..6 {
+        let image = generate_with_seed(source.to_string(), 16, 16, seed);
+        assert!(contains(&sheet, &image), "seed {seed}");
+    }
+}
@@ -0,0 +1,75 @@
+//! Golden images of every language version. The images of a version must never change, so a
+//! failure here means that a change leaked into an existing version instead of a new one.
+//!
+//! Run with `SSL_BLESS=1` to record the images of a new version.
+
+mod common;
+
+use common::{check_golden, golden_path, Tolerance};
+use ssl::{
+    parse_source_with_version, render_with_version, visualize_hash_with_version, LanguageVersion,
+    RgbImage,
+};
+
+/// Width and height of the golden images.
+const SIZE: u32 = 48;
+
+/// Data rendered as hash visualizations.
+const HASHES: &[(&str, &[u8])] = &[
+    ("hash-empty", b""),
+    (
+        "hash-key",
+        b"ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl",
+    ),
+    ("hash-binary", &[0x00, 0x9f, 0x92, 0x96, 0xff, 0x10, 0x80]),
+];
+
+/// Sources rendered as programs.
+const SOURCES: &[(&str, &str)] = &[
+    ("source-random", "Hello, I am a random string!"),
+    (
+        "source-program",
+        "if r < 0.5 then {sin(a * 5.0), y, x} else tx(0.2, x * y) end",
+    ),
+    ("source-keywords", "normal(shade(x * y)) + tex(0.0, x, y)"),
+    // Built-in names inside words are only letters, and the first version has no built-ins.
+    (
+        "source-prose",
+        "Let me dye the text in this context with a shaded, normal index.",
+    ),
+    ("source-words", "dxy index context abc normal shade tex"),
+    // Versions differ in whether a constant program is parsed again.
+    ("source-constant", "12"),
+];
+
+fn check(version: LanguageVersion, name: &str, image: RgbImage) {
+    let dir = format!("{version:?}").to_lowercase();
+    check_golden(&golden_path(&dir, name), &image, Tolerance::EXACT);
+}
+
+#[test]
+fn hash_visualizations_never_change() {
+    for version in LanguageVersion::ALL {
+        for (name, data) in HASHES {
+            check(
+                version,
+                name,
+                visualize_hash_with_version(data, SIZE, SIZE, version),
+            );
+        }
+    }
+}
+
+#[test]
+fn programs_never_change() {
+    for version in LanguageVersion::ALL {
+        for (name, source) in SOURCES {
+            let expr = parse_source_with_version(*source, version);
+            check(
+                version,
+                name,
+                render_with_version(&expr, SIZE, SIZE, version),
+            );
+        }
+    }
+}